@@ -0,0 +1,40 @@
+//! Times computing command names, run with `cargo bench --bench names`
+//!
+//! Uses only the standard library so it builds without extra dependencies.
+
+use fun_run::CommandWithName;
+use std::hint::black_box;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const ARGS: usize = 5_000;
+const ITERATIONS: u32 = 200;
+
+fn time(label: &str, mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_call = start.elapsed() / ITERATIONS;
+    println!("{label:<40} {per_call:>12?} per call");
+    per_call
+}
+
+fn main() {
+    let mut command = Command::new("rsync");
+    command.args((0..ARGS).map(|i| format!("path/to/file {i}.txt")));
+
+    println!("Names of a command with {ARGS} arguments");
+    time("name() on Command", || {
+        black_box(command.name());
+    });
+    time("fun_run::display()", || {
+        black_box(fun_run::display(&mut command));
+    });
+
+    let mut cached = command.cache_name();
+    time("name() after cache_name()", || {
+        black_box(cached.name());
+    });
+}
@@ -0,0 +1,42 @@
+//! Times quoting long argument lists, run with `cargo bench --bench quoting`
+//!
+//! Uses only the standard library so it builds without extra dependencies.
+
+use fun_run::Quoting;
+use std::hint::black_box;
+use std::process::Command;
+use std::time::Instant;
+
+const ARGS: usize = 10_000;
+const ITERATIONS: u32 = 100;
+
+fn time(label: &str, command: &mut Command, quoting: Quoting) {
+    black_box(fun_run::display_with(command, quoting));
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(fun_run::display_with(command, quoting));
+    }
+    let per_call = start.elapsed() / ITERATIONS;
+    println!("{label:<40} {per_call:>12?} per call");
+}
+
+fn main() {
+    let mut plain = Command::new("cc");
+    plain.args((0..ARGS).map(|i| format!("src/file_{i}.c")));
+    let mut special = Command::new("cc");
+    special.args((0..ARGS).map(|i| format!("src/my file's {i}.c")));
+
+    println!("Quoting a command with {ARGS} arguments");
+    for quoting in [Quoting::Debug, Quoting::Posix] {
+        time(
+            &format!("{quoting:?}, nothing to quote"),
+            &mut plain,
+            quoting,
+        );
+        time(
+            &format!("{quoting:?}, every argument quoted"),
+            &mut special,
+            quoting,
+        );
+    }
+}
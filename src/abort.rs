@@ -0,0 +1,133 @@
+use crate::mux::MAX_LINE_LENGTH;
+use crate::{OutputMatch, Stream};
+use regex::Regex;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Shared between the stdout and stderr writers of a single run
+pub(crate) struct AbortState {
+    patterns: Vec<Regex>,
+    pid: OnceLock<u32>,
+    matched: Mutex<Option<OutputMatch>>,
+}
+
+impl AbortState {
+    pub(crate) fn new(patterns: Vec<Regex>) -> Self {
+        Self {
+            patterns,
+            pid: OnceLock::new(),
+            matched: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn set_pid(&self, pid: u32) {
+        let _ = self.pid.set(pid);
+    }
+
+    pub(crate) fn writer<W: Write>(&self, stream: Stream, inner: W) -> AbortWriter<'_, W> {
+        AbortWriter {
+            inner,
+            stream,
+            state: self,
+            line: Vec::new(),
+        }
+    }
+
+    pub(crate) fn into_match(self) -> Option<OutputMatch> {
+        self.matched
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn check(&self, stream: Stream, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\n', '\r']);
+        let Some(regex) = self.patterns.iter().find(|regex| regex.is_match(line)) else {
+            return;
+        };
+
+        let mut matched = self.matched.lock().unwrap_or_else(PoisonError::into_inner);
+        if matched.is_none() {
+            *matched = Some(OutputMatch {
+                stream,
+                pattern: regex.as_str().to_string(),
+                line: line.to_string(),
+            });
+            if let Some(pid) = self.pid.get() {
+                kill(*pid);
+            }
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn kill(pid: u32) {
+    // SAFETY: The child has not been waited on yet so the pid cannot have been reused
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+/// Forwards output while checking each complete line against the abort patterns
+pub(crate) struct AbortWriter<'a, W: Write> {
+    inner: W,
+    stream: Stream,
+    state: &'a AbortState,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Write for AbortWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_all(buf)?;
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.ends_with(b"\n") || self.line.len() >= MAX_LINE_LENGTH {
+                self.state.check(self.stream, &self.line);
+                self.line.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for AbortWriter<'_, W> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.state.check(self.stream, &self.line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_first_match_is_kept() {
+        let state = AbortState::new(vec![
+            Regex::new("^FATAL").unwrap(),
+            Regex::new("panic").unwrap(),
+        ]);
+        let mut stdout = state.writer(Stream::Stdout, Vec::new());
+        let mut stderr = state.writer(Stream::Stderr, Vec::new());
+
+        stdout.write_all(b"ok\nFAT").unwrap();
+        stderr.write_all(b"thread panicked\r\n").unwrap();
+        stdout.write_all(b"AL: oops\n").unwrap();
+        drop(stdout);
+        drop(stderr);
+
+        assert_eq!(
+            Some(OutputMatch {
+                stream: Stream::Stderr,
+                pattern: "panic".to_string(),
+                line: "thread panicked".to_string()
+            }),
+            state.into_match()
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Fluent assertions on the result of running a command, for tests
+//!
+//! Failed assertions panic with the command name, its exit status and output.
+//!
+//! Example:
+//!
+//! ```
+//! use fun_run::assert::OutputAssertExt;
+//! use fun_run::CommandWithName;
+//! use std::process::Command;
+//!
+//! Command::new("bash")
+//!     .args(["-c", "echo 'Compiled 3 files'; echo 'warning: unused' >&2"])
+//!     .named_output()
+//!     .assert()
+//!     .success()
+//!     .stdout_contains("Compiled")
+//!     .stderr_contains("warning");
+//!
+//! Command::new("bash")
+//!     .args(["-c", "exit 2"])
+//!     .named_output()
+//!     .assert()
+//!     .failure()
+//!     .code(2);
+//! ```
+
+use crate::{CmdError, NamedOutput};
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+/// Start asserting on the result of a run, see the [module docs](self)
+pub trait OutputAssertExt {
+    /// Begin a chain of assertions
+    fn assert(self) -> Assert;
+}
+
+impl OutputAssertExt for Result<NamedOutput, CmdError> {
+    fn assert(self) -> Assert {
+        Assert { result: self }
+    }
+}
+
+impl OutputAssertExt for NamedOutput {
+    fn assert(self) -> Assert {
+        Assert { result: Ok(self) }
+    }
+}
+
+/// Assertions on a run, created by [OutputAssertExt::assert]
+///
+/// Each assertion returns the `Assert` so they can be chained and panics with the command
+/// name and everything known about the run when it fails.
+#[derive(Debug)]
+pub struct Assert {
+    result: Result<NamedOutput, CmdError>,
+}
+
+impl Assert {
+    /// Asserts the command ran and exited successfully
+    #[track_caller]
+    pub fn success(self) -> Self {
+        if self.result.is_err() {
+            self.fail("succeed");
+        }
+        self
+    }
+
+    /// Asserts the command ran and did not exit successfully
+    #[track_caller]
+    pub fn failure(self) -> Self {
+        if !self.output().success() {
+            return self;
+        }
+        self.fail("fail")
+    }
+
+    /// Asserts the command exited with this code
+    #[track_caller]
+    pub fn code(self, code: i32) -> Self {
+        if self.output().code() != Some(code) {
+            self.fail(&format!("exit with code {code}"));
+        }
+        self
+    }
+
+    /// Asserts stdout contains the text
+    #[track_caller]
+    pub fn stdout_contains(self, text: &str) -> Self {
+        if !self.output().stdout_lossy().contains(text) {
+            self.fail(&format!("have stdout containing {text:?}"));
+        }
+        self
+    }
+
+    /// Asserts stderr contains the text
+    #[track_caller]
+    pub fn stderr_contains(self, text: &str) -> Self {
+        if !self.output().stderr_lossy().contains(text) {
+            self.fail(&format!("have stderr containing {text:?}"));
+        }
+        self
+    }
+
+    /// Asserts stdout is exactly the text
+    #[track_caller]
+    pub fn stdout_eq(self, text: &str) -> Self {
+        if self.output().stdout_lossy() != text {
+            self.fail(&format!("have stdout equal to {text:?}"));
+        }
+        self
+    }
+
+    /// Asserts stderr is exactly the text
+    #[track_caller]
+    pub fn stderr_eq(self, text: &str) -> Self {
+        if self.output().stderr_lossy() != text {
+            self.fail(&format!("have stderr equal to {text:?}"));
+        }
+        self
+    }
+
+    /// Asserts stdout matches the pattern
+    #[track_caller]
+    #[cfg(feature = "regex")]
+    pub fn stdout_matches(self, pattern: &Regex) -> Self {
+        if !pattern.is_match(&self.output().stdout_lossy()) {
+            self.fail(&format!("have stdout matching `{pattern}`"));
+        }
+        self
+    }
+
+    /// Asserts stderr matches the pattern
+    #[track_caller]
+    #[cfg(feature = "regex")]
+    pub fn stderr_matches(self, pattern: &Regex) -> Self {
+        if !pattern.is_match(&self.output().stderr_lossy()) {
+            self.fail(&format!("have stderr matching `{pattern}`"));
+        }
+        self
+    }
+
+    /// The output of the run, for assertions not covered here
+    ///
+    /// # Panics
+    ///
+    /// Panics if the command could not be run.
+    #[track_caller]
+    #[must_use]
+    pub fn into_output(self) -> NamedOutput {
+        self.output();
+        match self.result {
+            Ok(output) => output,
+            Err(error) => NamedOutput::from(error),
+        }
+    }
+
+    /// The output of the run whether it succeeded or not, panics if it never ran
+    #[track_caller]
+    fn output(&self) -> &NamedOutput {
+        match &self.result {
+            Ok(output) => output,
+            Err(error) => match error.named_output() {
+                Some(output) => output,
+                None => self.fail("run"),
+            },
+        }
+    }
+
+    #[track_caller]
+    fn fail(&self, expectation: &str) -> ! {
+        let details = match &self.result {
+            Ok(output) => format!(
+                "{status}\nstdout: {stdout}\nstderr: {stderr}",
+                status = output.status(),
+                stdout = crate::display_out_or_empty(&output.stdout_lossy()),
+                stderr = crate::display_out_or_empty(&output.stderr_lossy())
+            ),
+            Err(error) => match error.named_output() {
+                Some(output) => format!(
+                    "{error}\nstdout: {stdout}\nstderr: {stderr}",
+                    stdout = crate::display_out_or_empty(&output.stdout_lossy()),
+                    stderr = crate::display_out_or_empty(&output.stderr_lossy())
+                ),
+                None => error.to_string(),
+            },
+        };
+        let name = match &self.result {
+            Ok(output) => output.name(),
+            Err(error) => error.name(),
+        };
+        panic!("Expected `{name}` to {expectation}\n\n{details}")
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::process::Command;
+
+    fn panic_message(f: impl FnOnce() + std::panic::UnwindSafe) -> String {
+        let payload = std::panic::catch_unwind(f).unwrap_err();
+        payload.downcast_ref::<String>().unwrap().clone()
+    }
+
+    #[test]
+    fn test_failed_assertion_names_command() {
+        let message = panic_message(|| {
+            let _ = Command::new("bash")
+                .args(["-c", "echo 'Compiling' && exit 1"])
+                .named_output()
+                .assert()
+                .success();
+        });
+        assert!(
+            message.starts_with("Expected `bash -c \"echo 'Compiling' && exit 1\"` to succeed"),
+            "{message}"
+        );
+        assert!(message.contains("Compiling"), "{message}");
+
+        let message = panic_message(|| {
+            let _ = Command::new("does_not_exist_fun_run")
+                .named_output()
+                .assert()
+                .failure();
+        });
+        assert!(
+            message.starts_with("Expected `does_not_exist_fun_run` to run"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_passing_assertions_chain() {
+        let output = Command::new("printf")
+            .arg("hello")
+            .named_output()
+            .assert()
+            .success()
+            .code(0)
+            .stdout_eq("hello")
+            .stdout_matches(&Regex::new("^h").unwrap())
+            .stderr_eq("")
+            .into_output();
+        assert_eq!("printf hello", output.name());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{CmdError, CommandSpec, NamedOutput, RunnableCommand};
+use std::io::Write;
+
+/// Where a backend sends the output of a run
+pub enum BackendIo<'a> {
+    /// Capture stdout and stderr, see [crate::CommandWithName::named_output]
+    Capture,
+    /// Write stdout and stderr as they arrive while capturing them, see
+    /// [crate::CommandWithName::stream_output]
+    Stream(&'a mut (dyn Write + Send), &'a mut (dyn Write + Send)),
+}
+
+impl std::fmt::Debug for BackendIo<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendIo::Capture => f.write_str("Capture"),
+            BackendIo::Stream(_, _) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl BackendIo<'_> {
+    /// Run the command the way this asks for
+    ///
+    /// For backends that translate a command into another local command, such as one that
+    /// talks to a remote system, so the run gets fun_run's streaming and errors.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from running the command, see [BackendIo].
+    pub fn run(self, command: &mut dyn RunnableCommand) -> Result<NamedOutput, CmdError> {
+        match self {
+            BackendIo::Capture => command.run(),
+            BackendIo::Stream(stdout, stderr) => command.run_streamed(stdout, stderr),
+        }
+    }
+}
+
+/// Where and how commands are executed
+///
+/// Write commands once as a [CommandSpec] and choose where they run, such as locally with
+/// [Local] or in a container or on another host with the `container` and `ssh` features.
+/// Implement it to run commands elsewhere, such as `kubectl exec` or in a chroot. Most
+/// backends translate the spec into a local command, give it a name that says where it ran
+/// and run it with [BackendIo::run].
+///
+/// Backends that only change how the spec's own command starts, such as `Chroot`, `Sandbox`
+/// and `Seccomp`, add a `pre_exec` hook to that command so everything else set on it is
+/// kept. Hooks can't be removed, so each run adds another one. Use a new spec for each run
+/// through them, or call their `exec` once per command when using them directly.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{BackendIo, CommandSpec, CommandWithName, ExecutionBackend, Local};
+/// use std::process::Command;
+///
+/// struct Nice;
+///
+/// impl ExecutionBackend for Nice {
+///     fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<fun_run::NamedOutput, fun_run::CmdError> {
+///         let name = format!("(niced) {}", spec.name());
+///         let mut command = Command::new("nice");
+///         command.arg(spec.command().get_program()).args(spec.command().get_args());
+///         io.run(&mut CommandSpec::new(command).with_name(name))
+///     }
+/// }
+///
+/// let backends: Vec<Box<dyn ExecutionBackend>> = vec![Box::new(Local), Box::new(Nice)];
+/// for backend in &backends {
+///     let mut spec = CommandSpec::new(Command::new("true"));
+///     let output = backend.run(&mut spec, BackendIo::Capture).unwrap();
+///     println!("Ran `{}`", output.name());
+/// }
+///
+/// let mut spec = CommandSpec::new(Command::new("false"));
+/// let error = Nice.run(&mut spec, BackendIo::Capture).unwrap_err();
+/// assert!(error.to_string().starts_with("Command failed `(niced) false`"));
+/// ```
+pub trait ExecutionBackend {
+    /// Run the command, naming the result for where it ran
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CmdError` if the command could not be run or failed, like the
+    /// [crate::CommandWithName] method matching `io`.
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError>;
+}
+
+/// Runs commands on this machine, as they were written
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Local;
+
+impl ExecutionBackend for Local {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(spec)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_local_streams_and_captures() {
+        let mut spec = CommandSpec::new(Command::new("echo")).with_name("greet");
+        let mut stdout = Vec::new();
+        let output = Local
+            .run(
+                &mut spec,
+                BackendIo::Stream(&mut stdout, &mut std::io::sink()),
+            )
+            .unwrap();
+        assert_eq!(b"\n", stdout.as_slice());
+        assert_eq!("greet", output.name());
+
+        let output = Local.run(&mut spec, BackendIo::Capture).unwrap();
+        assert_eq!("\n", output.stdout_lossy());
+    }
+}
@@ -0,0 +1,686 @@
+use crate::child::{NamedChild, Readiness};
+use crate::{CmdError, NamedOutput};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Run several commands and report on each of them
+///
+/// Output of each step is captured. Steps added with [Batch::step] or [Batch::named_step]
+/// run after the step added before them. The batch stops at the first failure and the
+/// remaining steps are reported as skipped.
+///
+/// Steps added with [Batch::named_step_after] instead wait only for the steps they name,
+/// running in parallel with every other step that is ready. When a dependency fails they are
+/// reported as [StepResult::DependencyFailed]. At most [Batch::max_parallel] steps run at
+/// the same time.
+///
+/// With a [Batch::budget] the whole batch shares a time limit. Each step is killed if it runs
+/// past what is left of the budget, and once it is used up the remaining steps are skipped.
+/// Steps then run in their own process group so anything they spawn is killed with them.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{Batch, StepResult};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let mut sleep = Command::new("sleep");
+/// sleep.arg("5");
+///
+/// let report = Batch::new()
+///     .step(Command::new("true"))
+///     .step(sleep)
+///     .step(Command::new("true"))
+///     .budget(Duration::from_millis(200))
+///     .run();
+///
+/// assert!(!report.success());
+/// assert!(matches!(report.steps()[0].result, StepResult::Succeeded(_)));
+/// assert!(matches!(report.steps()[1].result, StepResult::Failed(_)));
+/// assert!(matches!(report.steps()[2].result, StepResult::Skipped(_)));
+/// ```
+#[derive(Debug, Default)]
+pub struct Batch {
+    steps: Vec<Step>,
+    budget: Option<Duration>,
+    max_parallel: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Step {
+    name: String,
+    /// Added with a custom name rather than named via [crate::display]
+    named: bool,
+    command: Command,
+    /// `None` to run after the previously added step
+    dependencies: Option<Vec<String>>,
+}
+
+impl Batch {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a step, named via [crate::display]
+    ///
+    /// Several of these steps may run the same command and share a name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a step added with a custom name already has this name.
+    #[must_use]
+    pub fn step(mut self, mut command: Command) -> Self {
+        let name = crate::display(&mut command);
+        self.push(Step {
+            name,
+            named: false,
+            command,
+            dependencies: None,
+        });
+        self
+    }
+
+    /// Add a step with a custom name
+    ///
+    /// # Panics
+    ///
+    /// Panics if another step already has this name.
+    #[must_use]
+    pub fn named_step(mut self, name: impl AsRef<str>, command: Command) -> Self {
+        self.push(Step {
+            name: name.as_ref().to_string(),
+            named: true,
+            command,
+            dependencies: None,
+        });
+        self
+    }
+
+    /// Add a step that runs once every step named in `dependencies` succeeded
+    ///
+    /// Dependencies can be added before or after this step. A step with no dependencies, `&[]`,
+    /// starts right away. Steps whose dependencies don't exist or depend on each other in a
+    /// cycle are skipped.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{Batch, StepResult};
+    /// use std::process::Command;
+    ///
+    /// let report = Batch::new()
+    ///     .named_step_after("codegen", Command::new("true"), &[])
+    ///     .named_step_after("lint", Command::new("false"), &[])
+    ///     .named_step_after("compile", Command::new("true"), &["codegen"])
+    ///     .named_step_after("package", Command::new("true"), &["compile", "lint"])
+    ///     .run();
+    ///
+    /// assert!(matches!(report.steps()[2].result, StepResult::Succeeded(_)));
+    /// let StepResult::DependencyFailed(failed) = &report.steps()[3].result else {
+    ///     panic!("{report:?}")
+    /// };
+    /// assert_eq!("lint", failed);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if another step already has this name.
+    #[must_use]
+    pub fn named_step_after(
+        mut self,
+        name: impl AsRef<str>,
+        command: Command,
+        dependencies: &[&str],
+    ) -> Self {
+        self.push(Step {
+            name: name.as_ref().to_string(),
+            named: true,
+            command,
+            dependencies: Some(
+                dependencies
+                    .iter()
+                    .map(|dependency| (*dependency).to_string())
+                    .collect(),
+            ),
+        });
+        self
+    }
+
+    /// Total time all steps may take together
+    #[must_use]
+    pub fn budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// At most `max` steps running at the same time, a `max` of 0 is treated as 1
+    ///
+    /// Defaults to [std::thread::available_parallelism]. Steps that are ready wait for a
+    /// running step to finish, in the order they were added.
+    #[must_use]
+    pub fn max_parallel(mut self, max: usize) -> Self {
+        self.max_parallel = Some(max.max(1));
+        self
+    }
+
+    /// Steps are found by name, so a custom name must not be shared with any other step
+    fn push(&mut self, step: Step) {
+        assert!(
+            !self
+                .steps
+                .iter()
+                .any(|other| other.name == step.name && (other.named || step.named)),
+            "Batch already has a step named `{}`",
+            step.name
+        );
+        self.steps.push(step);
+    }
+
+    /// Run every step once the steps it depends on succeeded
+    #[must_use]
+    pub fn run(self) -> RunReport {
+        let batch_start = Instant::now();
+        let deadline = self.budget.map(|budget| Instant::now() + budget);
+        let used_up = self
+            .budget
+            .map(|budget| format!("Batch budget of {budget:?} was used up"));
+        let max_parallel = self.max_parallel.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+
+        let names = self
+            .steps
+            .iter()
+            .map(|step| step.name.clone())
+            .collect::<Vec<_>>();
+        let mut pending = Vec::new();
+        for (index, step) in self.steps.into_iter().enumerate() {
+            let dependencies = match &step.dependencies {
+                None => Ok(index.checked_sub(1).into_iter().collect()),
+                Some(dependencies) => dependencies
+                    .iter()
+                    .map(|dependency| {
+                        let found = (0..names.len())
+                            .filter(|other| names[*other] == *dependency)
+                            .collect::<Vec<_>>();
+                        if found.is_empty() {
+                            Err(format!("No step named `{dependency}` to depend on"))
+                        } else {
+                            Ok(found)
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|found| found.concat()),
+            };
+            pending.push(Some((step, dependencies)));
+        }
+
+        let mut results: Vec<Option<StepReport>> = pending.iter().map(|_| None).collect();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::scope(|scope| {
+            let mut running = 0;
+            loop {
+                // Starting or skipping one step can settle others, so go until nothing changes
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for index in 0..pending.len() {
+                        let Some((step, dependencies)) = &pending[index] else {
+                            continue;
+                        };
+                        let skipped = match dependencies {
+                            Err(reason) => Some(StepResult::Skipped(reason.clone())),
+                            Ok(dependencies) => {
+                                if dependencies
+                                    .iter()
+                                    .any(|dependency| results[*dependency].is_none())
+                                {
+                                    continue;
+                                }
+                                dependencies.iter().find_map(|dependency| {
+                                    results[*dependency].as_ref().and_then(|report| {
+                                        skip_after(
+                                            report,
+                                            step.dependencies.is_some(),
+                                            used_up.as_ref(),
+                                        )
+                                    })
+                                })
+                            }
+                        };
+                        let skipped = skipped.or_else(|| {
+                            deadline
+                                .filter(|deadline| Instant::now() >= *deadline)
+                                .and(used_up.clone())
+                                .map(StepResult::Skipped)
+                        });
+                        if skipped.is_none() && running >= max_parallel {
+                            continue;
+                        }
+
+                        let Some((step, _)) = pending[index].take() else {
+                            continue;
+                        };
+                        changed = true;
+                        if let Some(result) = skipped {
+                            results[index] = Some(StepReport {
+                                name: step.name,
+                                run_id: None,
+                                result,
+                                start: batch_start.elapsed(),
+                                duration: Duration::ZERO,
+                            });
+                            continue;
+                        }
+
+                        running += 1;
+                        let sender = sender.clone();
+                        scope.spawn(move || {
+                            let Step {
+                                name, mut command, ..
+                            } = step;
+                            let start = Instant::now();
+                            let result = run_step(&name, &mut command, deadline);
+                            let run_id = crate::result_run_id(&result);
+                            let result = match result {
+                                Ok(named_output) => StepResult::Succeeded(named_output),
+                                Err(error) => StepResult::Failed(error),
+                            };
+                            let _ = sender.send((
+                                index,
+                                StepReport {
+                                    name,
+                                    run_id,
+                                    result,
+                                    start: start.duration_since(batch_start),
+                                    duration: start.elapsed(),
+                                },
+                            ));
+                        });
+                    }
+                }
+
+                if running == 0 {
+                    break;
+                }
+                if let Ok((index, report)) = receiver.recv() {
+                    results[index] = Some(report);
+                    running -= 1;
+                }
+            }
+        });
+
+        let steps = results
+            .into_iter()
+            .zip(pending)
+            .map(|(report, step)| {
+                report.unwrap_or_else(|| StepReport {
+                    name: step.map(|(step, _)| step.name).unwrap_or_default(),
+                    run_id: None,
+                    result: StepResult::Skipped("Dependencies form a cycle".to_string()),
+                    start: batch_start.elapsed(),
+                    duration: Duration::ZERO,
+                })
+            })
+            .collect();
+        RunReport { steps }
+    }
+}
+
+/// Why a step is skipped when its dependency did not succeed, `None` if it did
+///
+/// Steps with named dependencies report the failure that caused the skip as
+/// [StepResult::DependencyFailed], while steps that run after the previous one keep the
+/// reason the first skipped step was given.
+fn skip_after(
+    dependency: &StepReport,
+    named_dependencies: bool,
+    used_up: Option<&String>,
+) -> Option<StepResult> {
+    let failed = match &dependency.result {
+        StepResult::Succeeded(_) => return None,
+        StepResult::Skipped(reason) => return Some(StepResult::Skipped(reason.clone())),
+        StepResult::Failed(CmdError::TimedOut(_, _)) if used_up.is_some() => {
+            return used_up.cloned().map(StepResult::Skipped)
+        }
+        StepResult::Failed(_) => &dependency.name,
+        StepResult::DependencyFailed(failed) => failed,
+    };
+    Some(if named_dependencies {
+        StepResult::DependencyFailed(failed.clone())
+    } else {
+        StepResult::Skipped(format!("Skipped after `{failed}` failed"))
+    })
+}
+
+fn run_step(
+    name: &str,
+    command: &mut Command,
+    deadline: Option<Instant>,
+) -> Result<NamedOutput, CmdError> {
+    match deadline {
+        Some(deadline) => NamedChild::spawn(
+            name.to_string(),
+            command.process_group(0),
+            Arc::new(Readiness::new()),
+            io::sink(),
+            io::sink(),
+        )?
+        .wait_timeout(deadline.saturating_duration_since(Instant::now())),
+        None => {
+            let run_id = crate::next_run_id();
+            crate::command::output(command)
+                .map_err(|error| CmdError::SystemError(name.to_string(), error, Some(run_id)))
+                .map(|output| NamedOutput::for_run(name, run_id, output))
+        }
+    }
+    .and_then(NamedOutput::nonzero_captured)
+}
+
+/// The outcome of every step of a [Batch]
+#[derive(Debug)]
+pub struct RunReport {
+    steps: Vec<StepReport>,
+}
+
+impl RunReport {
+    /// Every step, in the order they were added
+    #[must_use]
+    pub fn steps(&self) -> &[StepReport] {
+        &self.steps
+    }
+
+    /// Returns true if every step succeeded
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|step| matches!(step.result, StepResult::Succeeded(_)))
+    }
+
+    /// Write a timeline of the steps that ran in the Chrome tracing format
+    ///
+    /// Open the file in [Perfetto](https://ui.perfetto.dev) or `chrome://tracing` to see each
+    /// step as a span with its name. Skipped steps are left out.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::Batch;
+    /// use std::process::Command;
+    ///
+    /// let report = Batch::new()
+    ///     .step(Command::new("true"))
+    ///     .step(Command::new("false"))
+    ///     .run();
+    ///
+    /// let mut trace = Vec::new();
+    /// report.write_chrome_trace(&mut trace).unwrap();
+    /// let trace: serde_json::Value = serde_json::from_slice(&trace).unwrap();
+    /// assert_eq!("false", trace["traceEvents"][1]["name"]);
+    /// assert_eq!(false, trace["traceEvents"][1]["args"]["success"]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    #[cfg(feature = "serde")]
+    pub fn write_chrome_trace(&self, writer: impl std::io::Write) -> io::Result<()> {
+        let spans = self
+            .steps
+            .iter()
+            .filter_map(|step| {
+                let error = match &step.result {
+                    StepResult::Succeeded(_) => None,
+                    StepResult::Failed(error) => Some(error.to_string()),
+                    StepResult::Skipped(_) | StepResult::DependencyFailed(_) => return None,
+                };
+                Some(crate::trace::Span {
+                    name: &step.name,
+                    run_id: step.run_id,
+                    start: step.start,
+                    duration: step.duration,
+                    error,
+                })
+            })
+            .collect::<Vec<_>>();
+        crate::trace::write_chrome_trace(&spans, writer)
+    }
+
+    /// Write a standalone HTML page for people to read
+    ///
+    /// Each step has its status, how long it took and a bar showing when it ran during the
+    /// batch. Output and errors are in collapsible sections, expanded for failed steps.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::Batch;
+    /// use std::process::Command;
+    ///
+    /// let mut failing = Command::new("bash");
+    /// failing.args(["-c", "echo 'disk full' >&2 && exit 1"]);
+    ///
+    /// let report = Batch::new()
+    ///     .step(Command::new("true"))
+    ///     .named_step("backup", failing)
+    ///     .run();
+    ///
+    /// let mut html = Vec::new();
+    /// report.write_html(&mut html).unwrap();
+    /// let html = String::from_utf8(html).unwrap();
+    /// assert!(html.contains("1 succeeded, 1 failed, 0 skipped"));
+    /// assert!(html.contains("stderr: disk full"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn write_html(&self, writer: impl std::io::Write) -> io::Result<()> {
+        crate::html::write_html_report(&self.steps, writer)
+    }
+
+    /// Write JUnit XML so CI systems show each step as a test case
+    ///
+    /// `suite` names the test suite and is the class name of every test case. Failed steps
+    /// are failures with the error as the message, steps that did not run are skipped.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::Batch;
+    /// use std::process::Command;
+    ///
+    /// let mut failing = Command::new("bash");
+    /// failing.args(["-c", "echo 'disk full' >&2 && exit 1"]);
+    ///
+    /// let report = Batch::new()
+    ///     .step(Command::new("true"))
+    ///     .named_step("backup", failing)
+    ///     .step(Command::new("true"))
+    ///     .run();
+    ///
+    /// let mut xml = Vec::new();
+    /// report.write_junit_xml("nightly", &mut xml).unwrap();
+    /// let xml = String::from_utf8(xml).unwrap();
+    /// assert!(xml.contains(r#"tests="3" failures="1" errors="0" skipped="1""#));
+    /// assert!(xml.contains(r#"<failure message="Command failed `backup`">"#));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn write_junit_xml(&self, suite: &str, writer: impl std::io::Write) -> io::Result<()> {
+        crate::junit::write_junit_xml(suite, &self.steps, writer)
+    }
+}
+
+/// The outcome of a single step of a [Batch]
+#[derive(Debug)]
+pub struct StepReport {
+    /// Name of the command
+    pub name: String,
+    /// See [NamedOutput::run_id], also set when the command could not be spawned and `None`
+    /// if the step did not run
+    pub run_id: Option<u64>,
+    /// What happened when the step ran, or why it did not
+    pub result: StepResult,
+    /// Time from the start of the batch until the step started
+    pub start: Duration,
+    /// How long the step ran for, zero if it was skipped
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+pub enum StepResult {
+    /// The step ran and succeeded
+    Succeeded(NamedOutput),
+    /// The step ran and failed
+    Failed(CmdError),
+    /// The step did not run, with the reason why
+    Skipped(String),
+    /// The step did not run because a step it depends on failed, with the name of the step
+    /// that failed
+    ///
+    /// Only for steps added with [Batch::named_step_after].
+    DependencyFailed(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failure_skips_remaining_steps() {
+        let mut failing = Command::new("bash");
+        failing.args(["-c", "echo nope >&2 && exit 2"]);
+
+        let report = Batch::new()
+            .named_step("first", Command::new("true"))
+            .named_step("second", failing)
+            .named_step("third", Command::new("true"))
+            .run();
+
+        let StepResult::Failed(error) = &report.steps()[1].result else {
+            panic!("Expected second step to fail {report:?}")
+        };
+        assert!(matches!(error, CmdError::NonZeroExitNotStreamed(_)));
+        let StepResult::Skipped(reason) = &report.steps()[2].result else {
+            panic!("Expected third step to be skipped {report:?}")
+        };
+        assert_eq!("Skipped after `second` failed", reason);
+    }
+
+    #[test]
+    fn test_budget_times_out_step() {
+        let mut sleep = Command::new("bash");
+        sleep.args(["-c", "echo started && exec sleep 5"]);
+
+        let report = Batch::new()
+            .named_step("sleep", sleep)
+            .named_step("after", Command::new("true"))
+            .budget(Duration::from_millis(200))
+            .run();
+
+        let StepResult::Failed(error) = &report.steps()[0].result else {
+            panic!("Expected step to fail {report:?}")
+        };
+        assert!(matches!(error, CmdError::TimedOut(_, _)));
+        assert!(error.to_string().contains("stdout: started"), "{error}");
+        assert!(report.steps()[0].duration < Duration::from_secs(5));
+        let StepResult::Skipped(reason) = &report.steps()[1].result else {
+            panic!("Expected step to be skipped {report:?}")
+        };
+        assert_eq!("Batch budget of 200ms was used up", reason);
+    }
+
+    #[test]
+    fn test_budget_kills_processes_spawned_by_step() {
+        let mut forking = Command::new("sh");
+        forking.args(["-c", "sleep 5; true"]);
+
+        let report = Batch::new()
+            .named_step("forking", forking)
+            .budget(Duration::from_millis(200))
+            .run();
+
+        let StepResult::Failed(error) = &report.steps()[0].result else {
+            panic!("Expected step to fail {report:?}")
+        };
+        assert!(matches!(error, CmdError::TimedOut(_, _)));
+        assert!(report.steps()[0].duration < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_independent_steps_run_in_parallel() {
+        let sleep = || {
+            let mut sleep = Command::new("sleep");
+            sleep.arg("0.3");
+            sleep
+        };
+
+        let report = Batch::new()
+            .named_step_after("one", sleep(), &[])
+            .named_step_after("two", sleep(), &[])
+            .named_step_after("both", Command::new("true"), &["one", "two"])
+            .named_step_after("missing", Command::new("true"), &["nope"])
+            .named_step_after("cycle", Command::new("true"), &["cycle"])
+            .max_parallel(2)
+            .run();
+
+        let [one, two, both, ..] = report.steps() else {
+            panic!("{report:?}")
+        };
+        assert!(two.start < end(one) && one.start < end(two), "{report:?}");
+        assert!(both.start >= end(one).max(end(two)), "{report:?}");
+        assert!(matches!(report.steps()[2].result, StepResult::Succeeded(_)));
+        let StepResult::Skipped(reason) = &report.steps()[3].result else {
+            panic!("Expected missing dependency to be skipped {report:?}")
+        };
+        assert_eq!("No step named `nope` to depend on", reason);
+        let StepResult::Skipped(reason) = &report.steps()[4].result else {
+            panic!("Expected cycle to be skipped {report:?}")
+        };
+        assert_eq!("Dependencies form a cycle", reason);
+    }
+
+    #[test]
+    fn test_max_parallel_limits_running_steps() {
+        let sleep = || {
+            let mut sleep = Command::new("sleep");
+            sleep.arg("0.2");
+            sleep
+        };
+
+        let report = Batch::new()
+            .named_step_after("one", sleep(), &[])
+            .named_step_after("two", sleep(), &[])
+            .named_step_after("three", sleep(), &[])
+            .max_parallel(2)
+            .run();
+
+        assert!(report.success(), "{report:?}");
+        let [one, two, three] = report.steps() else {
+            panic!("{report:?}")
+        };
+        assert!(two.start < end(one) && one.start < end(two), "{report:?}");
+        assert!(three.start >= end(one).min(end(two)), "{report:?}");
+    }
+
+    fn end(step: &StepReport) -> Duration {
+        step.start + step.duration
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch already has a step named `build`")]
+    fn test_duplicate_step_names_are_rejected() {
+        let _ = Batch::new()
+            .named_step("build", Command::new("true"))
+            .named_step_after("build", Command::new("true"), &[]);
+    }
+}
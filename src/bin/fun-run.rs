@@ -0,0 +1,237 @@
+//! Run a command the fun_run way from a shell
+//!
+//! Advertises the command, streams its output, prints a summary when it fails and exits with
+//! the command's exit code. With `--timeout` the command runs in its own process group so
+//! anything it spawned is killed with it.
+//!
+//! ```text
+//! fun-run [--name NAME] [--timeout SECONDS] [--retries COUNT] [--] COMMAND [ARGS...]
+//! ```
+
+#![allow(clippy::result_large_err)]
+
+use fun_run::{CmdError, CommandWithName, NamedOutput};
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+
+/// How often to check whether a command with a timeout exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Exit code when the command timed out, the same as `timeout(1)`
+const TIMED_OUT: u8 = 124;
+
+const USAGE: &str =
+    "Usage: fun-run [--name NAME] [--timeout SECONDS] [--retries COUNT] [--] COMMAND [ARGS...]
+
+Runs COMMAND, streaming its output, and exits with its exit code.
+
+Options:
+  --name NAME          Show NAME instead of the command
+  --timeout SECONDS    Kill the command if it runs longer than SECONDS
+  --retries COUNT      Run the command again up to COUNT times while it fails
+  -h, --help           Show this message";
+
+#[derive(Debug, Default)]
+struct Options {
+    name: Option<String>,
+    timeout: Option<Duration>,
+    retries: usize,
+    /// Arguments are passed on as given, they don't have to be UTF-8
+    command: Vec<OsString>,
+}
+
+fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .ok_or(format!("Missing a value for {flag}"))?
+                .into_string()
+                .map_err(|value| format!("Invalid {flag} `{}`, expected UTF-8", value.display()))
+        };
+        match arg.to_str().unwrap_or_default() {
+            "-h" | "--help" => return Ok(None),
+            "--name" => options.name = Some(value("--name")?),
+            "--timeout" => {
+                let seconds = value("--timeout")?;
+                options.timeout = Some(
+                    seconds
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or(format!("Invalid --timeout `{seconds}`, expected seconds"))?,
+                );
+            }
+            "--retries" => {
+                let count = value("--retries")?;
+                options.retries = count
+                    .parse()
+                    .map_err(|_| format!("Invalid --retries `{count}`, expected a count"))?;
+            }
+            "--" => {
+                options.command.extend(args);
+                break;
+            }
+            _ if arg.as_encoded_bytes().starts_with(b"-") && options.command.is_empty() => {
+                return Err(format!("Unknown option `{}`", arg.display()));
+            }
+            _ => {
+                options.command.push(arg);
+                options.command.extend(args);
+                break;
+            }
+        }
+    }
+    if options.command.is_empty() {
+        return Err("Missing a command to run".to_string());
+    }
+    Ok(Some(options))
+}
+
+/// Run once, streaming output, killing the command and its process group once `timeout`
+/// elapses
+fn run_once(
+    command: &mut Command,
+    name: &str,
+    timeout: Option<Duration>,
+) -> Result<NamedOutput, CmdError> {
+    let mut child = command
+        .named(name)
+        .start(std::io::stdout(), std::io::stderr())?;
+    if let Some(timeout) = timeout {
+        let deadline = Instant::now() + timeout;
+        while child.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                kill_group(child.id());
+                let output = match child.wait() {
+                    Ok(output) => output,
+                    Err(error) => NamedOutput::from(error),
+                };
+                return Err(CmdError::TimedOut(output, timeout));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+    child.wait()
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn kill_group(pid: u32) {
+    // SAFETY: The child has not been waited on yet so its process group still exists
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse(std::env::args_os().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut command = Command::new(&options.command[0]);
+    command.args(&options.command[1..]);
+    if options.timeout.is_some() {
+        // Leading its own group keeps the command from getting `SIGINT` from the terminal,
+        // terminate the group when this process is interrupted instead
+        fun_run::terminate_children_on_exit();
+        command.process_group(0);
+    }
+    let name = options.name.unwrap_or_else(|| command.name());
+
+    let attempts = options.retries + 1;
+    let mut attempt = 1;
+    loop {
+        if attempt == 1 {
+            eprintln!("- Running `{name}`");
+        } else {
+            eprintln!("- Retrying `{name}` (attempt {attempt} of {attempts})");
+        }
+        match run_once(&mut command, &name, options.timeout) {
+            Ok(_) => return ExitCode::SUCCESS,
+            Err(CmdError::TimedOut(output, timeout)) => {
+                // The output was already streamed, only summarize
+                eprintln!("Command timed out `{}` after {timeout:?}", output.name());
+                if attempt == attempts {
+                    return ExitCode::from(TIMED_OUT);
+                }
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                if attempt == attempts || matches!(error, CmdError::SystemError(..)) {
+                    return error.into();
+                }
+            }
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_stops_at_command() {
+        let options = parse(args(&["--timeout", "1.5", "--retries", "2", "ls", "--all"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(Duration::from_millis(1500)), options.timeout);
+        assert_eq!(2, options.retries);
+        assert_eq!(args(&["ls", "--all"]), options.command);
+
+        let options = parse(args(&["--", "--weird"])).unwrap().unwrap();
+        assert_eq!(args(&["--weird"]), options.command);
+
+        assert_eq!(
+            "Unknown option `--bogus`",
+            parse(args(&["--bogus", "ls"])).unwrap_err()
+        );
+        assert_eq!("Missing a command to run", parse(args(&[])).unwrap_err());
+        assert!(parse(args(&["--help"])).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_keeps_arguments_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = OsString::from_vec(vec![b'f', 0xff]);
+        let options = parse([OsString::from("ls"), invalid.clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec![OsString::from("ls"), invalid.clone()], options.command);
+
+        let options = parse([invalid.clone()]).unwrap().unwrap();
+        assert_eq!(vec![invalid.clone()], options.command);
+
+        assert_eq!(
+            "Invalid --name `f\u{FFFD}`, expected UTF-8",
+            parse([OsString::from("--name"), invalid, OsString::from("ls")]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_timeout_kills_the_process_group() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 5; true"]).process_group(0);
+        let start = Instant::now();
+        let error = run_once(&mut command, "sleepy", Some(Duration::from_millis(200))).unwrap_err();
+
+        assert!(matches!(error, CmdError::TimedOut(_, _)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Stops running a command that keeps failing, giving it time to recover
+///
+/// Runs are keyed by command name. After `threshold` consecutive failures the circuit opens
+/// and further runs return `CmdError::CircuitOpen` without executing anything until
+/// `cool_down` has passed. The next run after that is let through as a probe while other
+/// runs of the same command wait for it to finish. A successful probe closes the circuit again
+/// and lets the waiting runs through, while a failure reopens it for another cool down.
+///
+/// Clones share state, so a single breaker can be handed to every part of an application.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CircuitBreaker, CmdError, CommandWithName};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+/// let mut command = Command::new("false");
+///
+/// for _ in 0..2 {
+///     let error = breaker.run(&mut command, |cmd| cmd.named_output()).unwrap_err();
+///     assert!(matches!(error, CmdError::NonZeroExitNotStreamed(_)));
+/// }
+///
+/// let error = breaker.run(&mut command, |cmd| cmd.named_output()).unwrap_err();
+/// assert!(matches!(error, CmdError::CircuitOpen(_, _, _)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    cool_down: Duration,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    circuits: Mutex<HashMap<String, Circuit>>,
+    /// Notified when a probe finishes
+    probed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: usize,
+    opened_at: Option<Instant>,
+    /// A run is checking whether the command recovered after the cool down
+    probing: bool,
+}
+
+/// Ends a probe even if the run panics, so waiting runs aren't stuck
+struct Probe<'a> {
+    breaker: &'a CircuitBreaker,
+    name: &'a str,
+}
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        if let Some(circuit) = self.breaker.lock().get_mut(self.name) {
+            circuit.probing = false;
+        }
+        self.breaker.state.probed.notify_all();
+    }
+}
+
+impl CircuitBreaker {
+    /// Open the circuit after `threshold` consecutive failures, for `cool_down`
+    #[must_use]
+    pub fn new(threshold: usize, cool_down: Duration) -> Self {
+        Self {
+            threshold,
+            cool_down,
+            state: Arc::default(),
+        }
+    }
+
+    /// Run the command with `f` unless its circuit is open
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::CircuitOpen` if the command failed too many times in a row recently,
+    /// including when the probe after a cool down failed, otherwise any error returned by `f`.
+    pub fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        let name = command.name();
+        let mut circuits = self.lock();
+        let probe = loop {
+            let circuit = circuits.entry(name.clone()).or_default();
+            let Some(opened_at) = circuit.opened_at else {
+                break None;
+            };
+            if let Some(remaining) = self.remaining_since(opened_at) {
+                return Err(CmdError::CircuitOpen(name, circuit.failures, remaining));
+            }
+            if !circuit.probing {
+                circuit.probing = true;
+                break Some(Probe {
+                    breaker: self,
+                    name: &name,
+                });
+            }
+            circuits = self
+                .state
+                .probed
+                .wait(circuits)
+                .unwrap_or_else(PoisonError::into_inner);
+        };
+        drop(circuits);
+
+        let result = f(command);
+        let mut circuits = self.lock();
+        let circuit = circuits.entry(name.clone()).or_default();
+        if result.is_ok() {
+            *circuit = Circuit {
+                probing: circuit.probing,
+                ..Circuit::default()
+            };
+        } else {
+            circuit.failures += 1;
+            if circuit.failures >= self.threshold {
+                circuit.opened_at = Some(Instant::now());
+            }
+        }
+        drop(circuits);
+        drop(probe);
+        result
+    }
+
+    /// Time left before a command with this name may run again, `None` if it may run now
+    #[must_use]
+    pub fn remaining(&self, name: &str) -> Option<Duration> {
+        self.lock()
+            .get(name)
+            .and_then(|circuit| circuit.opened_at)
+            .and_then(|opened_at| self.remaining_since(opened_at))
+    }
+
+    fn remaining_since(&self, opened_at: Instant) -> Option<Duration> {
+        self.cool_down
+            .checked_sub(opened_at.elapsed())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Circuit>> {
+        self.state
+            .circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_success_after_cool_down_closes_circuit() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        let mut command = Command::new("true");
+        let mut command = command.named("flaky");
+
+        let _ = breaker.run(&mut command, |_| {
+            Err(CmdError::SystemError(
+                "flaky".to_string(),
+                std::io::Error::other("boom"),
+                None,
+            ))
+        });
+        let error = breaker
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Not running command `flaky`. It failed 1 time in a row, try again in"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        breaker
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap();
+        assert_eq!(None, breaker.remaining("flaky"));
+    }
+
+    #[test]
+    fn test_one_probe_after_cool_down() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(200));
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let fail = |_: &mut crate::NamedCommand<'_>| {
+            runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(100));
+            Err(CmdError::Declined("flaky".to_string()))
+        };
+        let mut command = Command::new("true");
+        let _ = breaker.run(&mut command.named("flaky"), |_| {
+            Err(CmdError::Declined("flaky".to_string()))
+        });
+        std::thread::sleep(Duration::from_millis(210));
+
+        let errors = std::thread::scope(|scope| {
+            let probe = scope.spawn(|| {
+                let mut command = Command::new("true");
+                breaker.run(&mut command.named("flaky"), fail).unwrap_err()
+            });
+            std::thread::sleep(Duration::from_millis(20));
+            let waiting = (0..3)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut command = Command::new("true");
+                        breaker.run(&mut command.named("flaky"), fail).unwrap_err()
+                    })
+                })
+                .collect::<Vec<_>>();
+            std::iter::once(probe)
+                .chain(waiting)
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(1, runs.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(matches!(errors[0], CmdError::Declined(_)));
+        assert!(errors[1..]
+            .iter()
+            .all(|error| matches!(error, CmdError::CircuitOpen(_, 2, _))));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::mux::MAX_LINE_LENGTH;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Collect lines that match a pattern into named buffers while a command runs
+///
+/// Register one or more named patterns, then pass to
+/// [crate::CommandWithName::stream_output_capturing]. Every line of stdout or stderr that
+/// matches a pattern is stored in the buffer with that name and is available afterwards via
+/// [crate::NamedOutput::captured_lines]. This avoids grepping through large captures after the
+/// fact.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, LineCaptures};
+/// use regex::Regex;
+/// use std::process::Command;
+///
+/// let captures = LineCaptures::new()
+///     .pattern("warnings", Regex::new("^warning:").unwrap());
+///
+/// let output = Command::new("bash")
+///     .args(["-c", "echo 'warning: unused' && echo 'compiled' && echo 'warning: deprecated'"])
+///     .stream_output_capturing(captures, std::io::stdout(), std::io::stderr())
+///     .unwrap();
+///
+/// assert_eq!(
+///     vec!["warning: unused".to_string(), "warning: deprecated".to_string()],
+///     output.captured_lines("warnings")
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LineCaptures {
+    patterns: Arc<Vec<(String, Regex)>>,
+    lines: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+}
+
+impl LineCaptures {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store lines matching `regex` in the buffer named `name`
+    #[must_use]
+    pub fn pattern(mut self, name: impl Into<String>, regex: Regex) -> Self {
+        Arc::make_mut(&mut self.patterns).push((name.into(), regex));
+        self
+    }
+
+    /// Returns a writer that forwards all bytes to `inner` while checking each line for matches
+    ///
+    /// Writes are forwarded immediately, matching happens once a line is complete. Lines longer
+    /// than 64 KiB are matched in pieces.
+    #[must_use]
+    pub fn writer<W: Write>(&self, inner: W) -> CapturingWriter<W> {
+        CapturingWriter {
+            inner,
+            captures: self.clone(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Returns the lines captured so far, keyed by pattern name
+    ///
+    /// Every registered name is present, even when nothing has matched.
+    #[must_use]
+    pub fn lines(&self) -> BTreeMap<String, Vec<String>> {
+        let mut lines = self
+            .lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for (name, _) in self.patterns.iter() {
+            lines.entry(name.clone()).or_default();
+        }
+        lines
+    }
+
+    fn check(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\n', '\r']);
+        for (name, regex) in self.patterns.iter() {
+            if regex.is_match(line) {
+                self.lines
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .entry(name.clone())
+                    .or_default()
+                    .push(line.to_string());
+            }
+        }
+    }
+}
+
+/// A writer created with [LineCaptures::writer]
+#[derive(Debug)]
+pub struct CapturingWriter<W: Write> {
+    inner: W,
+    captures: LineCaptures,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Write for CapturingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_all(buf)?;
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.ends_with(b"\n") || self.line.len() >= MAX_LINE_LENGTH {
+                self.captures.check(&self.line);
+                self.line.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for CapturingWriter<W> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.captures.check(&self.line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines_split_across_writes() {
+        let captures = LineCaptures::new()
+            .pattern("errors", Regex::new("^error").unwrap())
+            .pattern("empty", Regex::new("^nope").unwrap());
+        let mut forwarded = Vec::new();
+        let mut writer = captures.writer(&mut forwarded);
+
+        writer.write_all(b"err").unwrap();
+        writer.write_all(b"or: one\r\nok\nerror: two").unwrap();
+        drop(writer);
+
+        assert_eq!(b"error: one\r\nok\nerror: two".to_vec(), forwarded);
+        assert_eq!(
+            BTreeMap::from([
+                ("empty".to_string(), Vec::new()),
+                (
+                    "errors".to_string(),
+                    vec!["error: one".to_string(), "error: two".to_string()]
+                ),
+            ]),
+            captures.lines()
+        );
+    }
+
+    #[test]
+    fn test_long_lines_are_matched_in_pieces() {
+        let captures = LineCaptures::new().pattern("dots", Regex::new(r"^\.+$").unwrap());
+        let mut writer = captures.writer(std::io::sink());
+
+        writer.write_all(&[b'.'; MAX_LINE_LENGTH]).unwrap();
+        assert_eq!(1, captures.lines()["dots"].len());
+        assert!(writer.line.is_empty());
+    }
+}
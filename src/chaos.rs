@@ -0,0 +1,281 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// A failure injected by [Chaos]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Don't run the command, fail as if it exited with this code and printed nothing
+    ///
+    /// The run still goes through, with a child process that exits before the program is
+    /// executed, so the error is the same one the run would return, such as
+    /// `CmdError::NonZeroExitAlreadyStreamed` when output is streamed.
+    ExitCode(i32),
+    /// Don't run the command, fail as if it could not be spawned
+    SpawnError(std::io::ErrorKind),
+    /// Wait this long before running the command
+    Delay(Duration),
+    /// Run the command then keep at most this many bytes of stdout and stderr, whether it
+    /// succeeded or failed
+    TruncateOutput(usize),
+}
+
+/// Injects failures into commands run by a [crate::FunRunner], for testing retries and
+/// circuit breakers without flaky commands
+///
+/// Faults are injected on specific runs with [Chaos::on_run] or at random with
+/// [Chaos::with_probability]. Random faults come from a seeded generator so a failing test
+/// can be reproduced with [Chaos::seed]. Clones share state.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{Chaos, CmdError, CommandWithName, Fault, FunRunner};
+/// use std::process::Command;
+///
+/// let runner = FunRunner::new().chaos(
+///     Chaos::new()
+///         .on_run(1, Fault::ExitCode(137))
+///         .on_run(2, Fault::SpawnError(std::io::ErrorKind::NotFound)),
+/// );
+///
+/// let mut command = Command::new("true");
+/// let first = runner.run(&mut command, |cmd| cmd.named_output());
+/// assert_eq!(Some(137), first.unwrap_err().status().code());
+///
+/// let second = runner.run(&mut command, |cmd| cmd.named_output());
+/// assert!(matches!(second, Err(CmdError::SystemError(..))));
+///
+/// assert!(runner.run(&mut command, |cmd| cmd.named_output()).is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    scheduled: BTreeMap<usize, Fault>,
+    random: Vec<(Fault, f64)>,
+    state: Arc<Mutex<ChaosState>>,
+}
+
+#[derive(Debug)]
+struct ChaosState {
+    runs: usize,
+    injected: usize,
+    rng: u64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        Self {
+            scheduled: BTreeMap::new(),
+            random: Vec::new(),
+            state: Arc::new(Mutex::new(ChaosState {
+                runs: 0,
+                injected: 0,
+                rng: seed,
+            })),
+        }
+    }
+}
+
+impl Chaos {
+    /// No faults until some are added, random ones are seeded from the clock
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the random faults so a run can be reproduced
+    #[must_use]
+    pub fn seed(self, seed: u64) -> Self {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rng = seed;
+        self
+    }
+
+    /// Inject `fault` on the nth run, counting from 1
+    ///
+    /// Scheduled faults take precedence over random ones.
+    #[must_use]
+    pub fn on_run(mut self, run: usize, fault: Fault) -> Self {
+        self.scheduled.insert(run, fault);
+        self
+    }
+
+    /// Inject `fault` on each run with this probability, from 0.0 to 1.0
+    ///
+    /// When several random faults are added they are tried in the order they were added.
+    #[must_use]
+    pub fn with_probability(mut self, fault: Fault, probability: f64) -> Self {
+        self.random.push((fault, probability));
+        self
+    }
+
+    /// How many faults were injected so far
+    #[must_use]
+    pub fn injected(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .injected
+    }
+
+    fn next_fault(&self) -> Option<Fault> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.runs += 1;
+        let fault = self.scheduled.get(&state.runs).cloned().or_else(|| {
+            self.random
+                .iter()
+                .find(|(_, probability)| state.next_f64() < *probability)
+                .map(|(fault, _)| fault.clone())
+        });
+        if fault.is_some() {
+            state.injected += 1;
+        }
+        fault
+    }
+
+    /// Run the command with `f` unless a fault replaces or changes the run
+    pub(crate) fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        match self.next_fault() {
+            None => f(command),
+            Some(Fault::ExitCode(code)) => {
+                let mut exits = crate::clone_command(command.mut_cmd());
+                // SAFETY: `_exit` is async-signal-safe, the child ends before anything else runs
+                unsafe {
+                    exits.pre_exec(move || libc::_exit(code));
+                }
+                let original = std::mem::replace(command.mut_cmd(), exits);
+                let result = f(command);
+                *command.mut_cmd() = original;
+                result
+            }
+            Some(Fault::SpawnError(kind)) => Err(CmdError::SystemError(
+                command.name(),
+                std::io::Error::new(kind, "Injected by Chaos"),
+                None,
+            )),
+            Some(Fault::Delay(delay)) => {
+                std::thread::sleep(delay);
+                f(command)
+            }
+            Some(Fault::TruncateOutput(len)) => {
+                let truncate = |mut named_output: NamedOutput| {
+                    named_output.output.stdout.truncate(len);
+                    named_output.output.stderr.truncate(len);
+                    named_output
+                };
+                f(command)
+                    .map(truncate)
+                    .map_err(|error| error.map_named_output(truncate))
+            }
+        }
+    }
+}
+
+impl ChaosState {
+    /// A number from 0.0 up to 1.0 using xorshift64*
+    fn next_f64(&mut self) -> f64 {
+        // Zero would only ever produce zero
+        let mut x = self.rng.max(1);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FunRunner;
+    use std::process::Command;
+
+    #[test]
+    fn test_random_faults_are_reproducible() {
+        let outcomes = |seed| {
+            let chaos = Chaos::new()
+                .seed(seed)
+                .with_probability(Fault::ExitCode(1), 0.5);
+            let runner = FunRunner::new().chaos(chaos.clone());
+            let outcomes = (0..20)
+                .map(|_| {
+                    runner
+                        .run(&mut Command::new("true"), CommandWithName::named_output)
+                        .is_ok()
+                })
+                .collect::<Vec<_>>();
+            (outcomes, chaos.injected())
+        };
+
+        let (first, injected) = outcomes(42);
+        assert_eq!(first, outcomes(42).0);
+        assert!((1..20).contains(&injected), "{injected}");
+        assert_eq!(injected, first.iter().filter(|ok| !**ok).count());
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        let runner = FunRunner::new().chaos(Chaos::new().on_run(1, Fault::TruncateOutput(3)));
+        let output = runner
+            .run(
+                Command::new("echo").arg("hello"),
+                CommandWithName::named_output,
+            )
+            .unwrap();
+        assert_eq!("hel", output.stdout_lossy());
+
+        let runner = FunRunner::new().chaos(Chaos::new().on_run(1, Fault::TruncateOutput(3)));
+        let error = runner
+            .run(
+                Command::new("bash").args(["-c", "echo hello; echo oops >&2; exit 1"]),
+                CommandWithName::named_output,
+            )
+            .unwrap_err();
+        let output = NamedOutput::from(error);
+        assert_eq!("hel", output.stdout_lossy());
+        assert_eq!("oop", output.stderr_lossy());
+    }
+
+    #[test]
+    fn test_exit_code_keeps_how_output_was_handled() {
+        let runner = FunRunner::new().chaos(
+            Chaos::new()
+                .on_run(1, Fault::ExitCode(3))
+                .on_run(2, Fault::ExitCode(4)),
+        );
+        let mut command = Command::new("echo");
+        command.arg("not printed");
+
+        let error = runner
+            .run(&mut command, |cmd| {
+                cmd.stream_output(std::io::sink(), std::io::sink())
+            })
+            .unwrap_err();
+        assert!(matches!(error, CmdError::NonZeroExitAlreadyStreamed(_)));
+        assert_eq!(Some(3), error.status().code());
+        assert_eq!("echo \"not printed\"", error.name());
+
+        let error = runner
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap_err();
+        assert!(matches!(error, CmdError::NonZeroExitNotStreamed(_)));
+        assert_eq!(Some(4), error.status().code());
+        assert!(NamedOutput::from(error).stdout().is_empty());
+
+        let output = runner
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap();
+        assert_eq!("not printed\n", output.stdout_lossy());
+    }
+}
@@ -0,0 +1,728 @@
+use crate::signals::{self, Signal};
+use crate::{CmdError, NamedOutput};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::{self, Child, Command, ExitStatus, Output};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often [NamedChild::wait_for_port] tries to connect
+const PORT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often [NamedChild::wait_timeout] checks whether the command exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Output of a [NamedChild] that arrived since it was last drained, see
+/// [NamedChild::drain_new_output]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl NewOutput {
+    /// Returns true if nothing arrived on either stream
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+}
+
+/// Copy what was added to `buffer` past `drained` and move `drained` to the end
+fn take_new(buffer: &Mutex<Vec<u8>>, drained: &mut usize) -> Vec<u8> {
+    let buffer = buffer.lock().unwrap_or_else(PoisonError::into_inner);
+    let new = buffer.get(*drained..).unwrap_or_default().to_vec();
+    *drained = buffer.len();
+    new
+}
+
+/// A running command, with a name
+///
+/// Output is drained on background threads, forwarded to the writers it was started with and
+/// captured so that [NamedChild::wait] can return a [NamedOutput] like any other fun run.
+///
+/// Created by [crate::CommandWithName::start] or [crate::CommandWithName::spawn_until].
+/// Dropping a `NamedChild` without waiting leaves the process running.
+#[derive(Debug)]
+pub struct NamedChild {
+    name: String,
+    run_id: u64,
+    child: Child,
+    stdout: Arc<Mutex<Vec<u8>>>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+    drains: Vec<JoinHandle<io::Result<()>>>,
+    /// Bytes of stdout and stderr already returned by [NamedChild::drain_new_output]
+    drained: (usize, usize),
+}
+
+impl NamedChild {
+    /// Spawns the command and starts draining its output in the background
+    pub(crate) fn spawn<OW, EW>(
+        name: String,
+        command: &mut Command,
+        readiness: Arc<Readiness>,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<Self, CmdError>
+    where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        let run_id = crate::next_run_id();
+        let mut child = crate::command::spawn(
+            command
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped()),
+        )
+        .map_err(|error| CmdError::SystemError(name.clone(), error, Some(run_id)))?;
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let mut drains = Vec::new();
+        if let Some(child_stdout) = child.stdout.take() {
+            let (buffer, readiness) = (Arc::clone(&stdout), Arc::clone(&readiness));
+            drains.push(thread::spawn(move || {
+                drain(child_stdout, stdout_write, &buffer, &readiness)
+            }));
+        } else {
+            readiness.close();
+        }
+        if let Some(child_stderr) = child.stderr.take() {
+            let (buffer, readiness) = (Arc::clone(&stderr), Arc::clone(&readiness));
+            drains.push(thread::spawn(move || {
+                drain(child_stderr, stderr_write, &buffer, &readiness)
+            }));
+        } else {
+            readiness.close();
+        }
+
+        Ok(Self {
+            name,
+            run_id,
+            child,
+            stdout,
+            stderr,
+            drains,
+            drained: (0, 0),
+        })
+    }
+
+    /// Return name of the command that is running
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The [NamedOutput::run_id] the output will have, available while the command runs
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let child = Command::new("echo")
+    ///     .arg("hello")
+    ///     .start(std::io::sink(), std::io::sink())
+    ///     .unwrap();
+    /// let run_id = child.run_id();
+    ///
+    /// assert_eq!(run_id, child.wait().unwrap().run_id());
+    /// ```
+    #[must_use]
+    pub fn run_id(&self) -> u64 {
+        self.run_id
+    }
+
+    /// Return the OS process id of the running command
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Number of bytes read from stdout so far
+    ///
+    /// Output keeps draining in the background so this grows while the command runs.
+    #[must_use]
+    pub fn stdout_bytes(&self) -> usize {
+        self.stdout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Number of bytes read from stderr so far
+    #[must_use]
+    pub fn stderr_bytes(&self) -> usize {
+        self.stderr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Output that arrived since the last call, without blocking
+    ///
+    /// For event loops such as a GUI or TUI that poll a running command instead of dedicating
+    /// a thread to it. Output is still written to the writers the command was started with
+    /// and everything is still returned by [NamedChild::wait].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let mut child = Command::new("bash")
+    ///     .args(["-c", "for i in 1 2 3; do echo \"step $i\"; sleep 0.05; done"])
+    ///     .start(std::io::sink(), std::io::sink())
+    ///     .unwrap();
+    ///
+    /// let mut log = String::new();
+    /// while child.try_wait().unwrap().is_none() {
+    ///     log.push_str(&String::from_utf8_lossy(&child.drain_new_output().stdout));
+    ///     std::thread::sleep(Duration::from_millis(10));
+    /// }
+    ///
+    /// let output = child.wait().unwrap();
+    /// assert!(log.starts_with("step 1\n"));
+    /// assert!(output.stdout_lossy().starts_with(&log));
+    /// ```
+    pub fn drain_new_output(&mut self) -> NewOutput {
+        let (stdout_drained, stderr_drained) = &mut self.drained;
+        NewOutput {
+            stdout: take_new(&self.stdout, stdout_drained),
+            stderr: take_new(&self.stderr, stderr_drained),
+        }
+    }
+
+    /// Returns the exit status if the command has exited, without blocking
+    ///
+    /// Once it returns a status, [NamedChild::wait] collects the output without waiting on
+    /// the command.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to check on the command.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, CmdError> {
+        self.child
+            .try_wait()
+            .map_err(|error| CmdError::SystemError(self.name.clone(), error, Some(self.run_id)))
+    }
+
+    /// Kill the running command
+    ///
+    /// Call [NamedChild::wait] afterwards to collect its output.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the process could not be killed
+    pub fn kill(&mut self) -> Result<(), CmdError> {
+        self.child
+            .kill()
+            .map_err(|error| CmdError::SystemError(self.name.clone(), error, Some(self.run_id)))
+    }
+
+    /// Send a signal to the running command
+    ///
+    /// Useful for servers that reload their configuration on `SIGHUP` or rotate logs on `SIGUSR1`.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use fun_run::{CommandWithName, Signal};
+    /// use regex::Regex;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let mut server = Command::new("nginx")
+    ///     .args(["-g", "daemon off;"])
+    ///     .spawn_until(
+    ///         Regex::new("start worker process").unwrap(),
+    ///         Duration::from_secs(10),
+    ///         std::io::stdout(),
+    ///         std::io::stderr(),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// // Update the config, then
+    /// server.signal(Signal::Hup).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the command has already exited or the signal could not be sent
+    pub fn signal(&mut self, signal: Signal) -> Result<(), CmdError> {
+        match self.child.try_wait() {
+            Ok(None) => signals::send(self.child.id(), signal),
+            Ok(Some(status)) => Err(io::Error::other(format!(
+                "Cannot send {signal}, already exited with {status}"
+            ))),
+            Err(error) => Err(error),
+        }
+        .map_err(|error| CmdError::SystemError(self.name.clone(), error, Some(self.run_id)))
+    }
+
+    /// Wait for the command to exit and return its output
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to wait on the command or read its output.
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    pub fn wait(mut self) -> Result<NamedOutput, CmdError> {
+        self.wait_for_output()
+            .map_err(|error| CmdError::SystemError(self.name.clone(), error, Some(self.run_id)))
+            .map(|output| NamedOutput::for_run(self.name.clone(), self.run_id, output))
+            .and_then(NamedOutput::nonzero_streamed)
+    }
+
+    /// Block until the command accepts TCP connections on `addr`
+    ///
+    /// Replaces sleep loops when starting a service in the background. Returns the child
+    /// once a connection succeeds so calls can be chained.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use fun_run::CommandWithName;
+    /// use regex::Regex;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let server = Command::new("python3")
+    ///     .args(["-u", "-m", "http.server", "8123"])
+    ///     .spawn_until(
+    ///         Regex::new("Serving HTTP").unwrap(),
+    ///         Duration::from_secs(10),
+    ///         std::io::stdout(),
+    ///         std::io::stderr(),
+    ///     )
+    ///     .and_then(|server| server.wait_for_port("127.0.0.1:8123", Duration::from_secs(10)))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::NotReady` if the command exits or the timeout elapses before a
+    /// connection succeeds. The command is killed and the error includes all output captured so far.
+    pub fn wait_for_port(
+        mut self,
+        addr: impl ToSocketAddrs + std::fmt::Debug,
+        timeout: Duration,
+    ) -> Result<Self, CmdError> {
+        let addrs = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs.collect::<Vec<SocketAddr>>(),
+            Err(error) => {
+                return Err(self.not_ready(format!("Could not resolve {addr:?}. {error}")))
+            }
+        };
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if addrs
+                .iter()
+                .any(|addr| TcpStream::connect_timeout(addr, PORT_POLL_INTERVAL).is_ok())
+            {
+                return Ok(self);
+            }
+            match self.child.try_wait() {
+                Ok(Some(status)) => {
+                    return Err(self.not_ready(format!(
+                        "Exited with {status} before {addr:?} accepted connections"
+                    )))
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    return Err(CmdError::SystemError(
+                        self.name.clone(),
+                        error,
+                        Some(self.run_id),
+                    ))
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(self.not_ready(format!(
+                    "Timed out after {timeout:?} waiting for {addr:?} to accept connections"
+                )));
+            }
+            thread::sleep(PORT_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the exit status if the command has exited, without blocking
+    pub(crate) fn try_wait_status(&mut self) -> io::Result<Option<std::process::ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Wait for the command to exit, killing it once `timeout` elapses
+    ///
+    /// When the command leads its own process group the whole group is killed, otherwise
+    /// anything it spawned could keep the output pipes open and block collecting the output.
+    /// The exit status is not checked, returns `CmdError::TimedOut` if the command was killed.
+    pub(crate) fn wait_timeout(mut self, timeout: Duration) -> Result<NamedOutput, CmdError> {
+        let deadline = Instant::now() + timeout;
+        let timed_out = loop {
+            match self.child.try_wait() {
+                Ok(Some(_)) => break false,
+                Ok(None) if Instant::now() >= deadline => {
+                    self.kill_group();
+                    break true;
+                }
+                Ok(None) => thread::sleep(EXIT_POLL_INTERVAL),
+                Err(error) => {
+                    return Err(CmdError::SystemError(self.name, error, Some(self.run_id)))
+                }
+            }
+        };
+
+        match self.wait_for_output() {
+            Ok(output) if timed_out => Err(CmdError::TimedOut(
+                NamedOutput::for_run(self.name, self.run_id, output),
+                timeout,
+            )),
+            Ok(output) => Ok(NamedOutput::for_run(self.name, self.run_id, output)),
+            Err(error) => Err(CmdError::SystemError(self.name, error, Some(self.run_id))),
+        }
+    }
+
+    /// Kill the process group the command leads, or only the command when it doesn't lead one
+    #[allow(clippy::cast_possible_wrap)]
+    fn kill_group(&mut self) {
+        let pid = self.child.id() as libc::pid_t;
+        // SAFETY: The child has not been waited on yet so the pid cannot have been reused
+        if unsafe { libc::getpgid(pid) } == pid {
+            // SAFETY: As above, the process group still exists while its leader is unreaped
+            unsafe {
+                libc::kill(-pid, libc::SIGKILL);
+            }
+        } else {
+            let _ = self.child.kill();
+        }
+    }
+
+    /// Kill the command, collect its output and describe why it was not ready
+    pub(crate) fn not_ready(mut self, reason: String) -> CmdError {
+        self.kill_group();
+        match self.wait_for_output() {
+            Ok(output) => {
+                CmdError::NotReady(NamedOutput::for_run(self.name, self.run_id, output), reason)
+            }
+            Err(error) => CmdError::SystemError(self.name, error, Some(self.run_id)),
+        }
+    }
+
+    fn wait_for_output(&mut self) -> io::Result<Output> {
+        let status = self.child.wait()?;
+        for drain in self.drains.drain(..) {
+            match drain.join() {
+                Ok(result) => result?,
+                Err(err) => std::panic::resume_unwind(err),
+            }
+        }
+
+        Ok(Output {
+            status,
+            stdout: std::mem::take(
+                &mut *self.stdout.lock().unwrap_or_else(PoisonError::into_inner),
+            ),
+            stderr: std::mem::take(
+                &mut *self.stderr.lock().unwrap_or_else(PoisonError::into_inner),
+            ),
+        })
+    }
+}
+
+/// Tracks whether a line matching the readiness pattern has been seen on either stream
+#[derive(Debug)]
+pub(crate) struct Readiness {
+    #[cfg(feature = "regex")]
+    pattern: Option<Regex>,
+    state: Mutex<ReadyState>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct ReadyState {
+    ready: bool,
+    open_streams: usize,
+}
+
+/// The outcome of waiting on [Readiness]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg(feature = "regex")]
+pub(crate) enum Ready {
+    Matched,
+    Closed,
+    TimedOut,
+}
+
+impl Readiness {
+    /// Never becomes ready, only tracks when both streams close
+    pub(crate) fn new() -> Self {
+        Self {
+            #[cfg(feature = "regex")]
+            pattern: None,
+            state: Mutex::new(ReadyState {
+                ready: false,
+                open_streams: 2,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Becomes ready once a line on either stream matches `pattern`
+    #[cfg(feature = "regex")]
+    pub(crate) fn matching(pattern: Regex) -> Self {
+        Self {
+            pattern: Some(pattern),
+            ..Self::new()
+        }
+    }
+
+    /// Blocks until a line matches, both streams are closed, or the timeout elapses
+    #[cfg(feature = "regex")]
+    pub(crate) fn wait(&self, timeout: Duration) -> Ready {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if state.ready {
+                return Ready::Matched;
+            }
+            if state.open_streams == 0 {
+                return Ready::Closed;
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Ready::TimedOut;
+            };
+            state = self
+                .changed
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .ready
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[allow(clippy::unused_self)]
+    fn check(&self, _line: &[u8]) {}
+
+    #[cfg(feature = "regex")]
+    fn check(&self, line: &[u8]) {
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+        let line = String::from_utf8_lossy(line);
+        if pattern.is_match(line.trim_end_matches(['\n', '\r'])) {
+            self.state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .ready = true;
+            self.changed.notify_all();
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.open_streams = state.open_streams.saturating_sub(1);
+        self.changed.notify_all();
+    }
+}
+
+/// Copies output into the capture buffer and writer, checking lines for readiness
+fn drain(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    buffer: &Mutex<Vec<u8>>,
+    readiness: &Readiness,
+) -> io::Result<()> {
+    let result = (|| {
+        let mut chunk = [0; 8 * 1024];
+        let mut line = Vec::new();
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            let bytes = &chunk[..read];
+            buffer
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend_from_slice(bytes);
+            writer.write_all(bytes)?;
+
+            if !readiness.is_ready() {
+                for part in bytes.split_inclusive(|&byte| byte == b'\n') {
+                    line.extend_from_slice(part);
+                    if part.ends_with(b"\n") {
+                        readiness.check(&line);
+                        line.clear();
+                    }
+                }
+            }
+        }
+        if !line.is_empty() {
+            readiness.check(&line);
+        }
+        Ok(())
+    })();
+    readiness.close();
+    result
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+
+    #[test]
+    fn test_readiness_partial_lines() {
+        let readiness = Readiness::matching(Regex::new("^Listening on").unwrap());
+        let buffer = Mutex::new(Vec::new());
+
+        drain(&b"booting\nListen"[..], io::sink(), &buffer, &readiness).unwrap();
+        assert_eq!(Ready::TimedOut, readiness.wait(Duration::ZERO));
+
+        drain(&b""[..], io::sink(), &buffer, &readiness).unwrap();
+        assert_eq!(Ready::Closed, readiness.wait(Duration::ZERO));
+        assert_eq!(b"booting\nListen".to_vec(), *buffer.lock().unwrap());
+
+        let readiness = Readiness::matching(Regex::new("^Listening on").unwrap());
+        drain(
+            &b"booting\nListening on 5000\r\n"[..],
+            io::sink(),
+            &buffer,
+            &readiness,
+        )
+        .unwrap();
+        assert_eq!(Ready::Matched, readiness.wait(Duration::ZERO));
+    }
+
+    fn spawn_sleeper() -> NamedChild {
+        Command::new("bash")
+            .args(["-c", "echo started && exec sleep 100"])
+            .spawn_until(
+                Regex::new("started").unwrap(),
+                Duration::from_secs(10),
+                io::sink(),
+                io::sink(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_wait_for_port_timeout_includes_output() {
+        let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = unused.local_addr().unwrap();
+        drop(unused);
+
+        let error = spawn_sleeper()
+            .wait_for_port(addr, Duration::from_millis(200))
+            .unwrap_err();
+
+        assert!(matches!(error, CmdError::NotReady(_, _)));
+        assert!(error.to_string().contains("stdout: started"), "{error}");
+    }
+
+    #[test]
+    fn test_not_ready_kills_process_group() {
+        use std::os::unix::process::CommandExt;
+
+        let error = Command::new("sh")
+            .args(["-c", "echo booting; sleep 100; true"])
+            .process_group(0)
+            .spawn_until(
+                Regex::new("^Listening on").unwrap(),
+                Duration::from_millis(200),
+                io::sink(),
+                io::sink(),
+            )
+            .unwrap_err();
+
+        assert!(matches!(error, CmdError::NotReady(_, _)));
+        assert!(error.to_string().contains("stdout: booting"), "{error}");
+    }
+
+    #[test]
+    fn test_wait_for_port_connects() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut child = spawn_sleeper()
+            .wait_for_port(listener.local_addr().unwrap(), Duration::from_secs(5))
+            .unwrap();
+
+        child.kill().unwrap();
+        assert!(child.wait().is_err());
+    }
+
+    #[test]
+    fn test_signal_running_and_exited() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = spawn_sleeper();
+        child.signal(Signal::Term).unwrap();
+        while child.try_wait_status().unwrap().is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let error = child.signal(Signal::Hup).unwrap_err();
+        assert!(matches!(error, CmdError::SystemError(..)));
+        assert!(error.to_string().contains("Cannot send SIGHUP"), "{error}");
+        assert_eq!(
+            Some(libc::SIGTERM),
+            child.wait().unwrap_err().status().signal()
+        );
+    }
+
+    #[test]
+    fn test_start_then_wait_returns_streamed_result() {
+        let child = std::process::Command::new("bash")
+            .args([
+                "-c",
+                "echo working && sleep 0.1 && echo 'failed' >&2 && exit 4",
+            ])
+            .start(io::sink(), io::sink())
+            .unwrap();
+        assert!(child.name().starts_with("bash -c"));
+
+        let error = child.wait().unwrap_err();
+        assert!(matches!(error, CmdError::NonZeroExitAlreadyStreamed(_)));
+        let output = NamedOutput::from(error);
+        assert_eq!("working\n", output.stdout_lossy());
+        assert_eq!(Some(4), output.status().code());
+    }
+
+    #[test]
+    fn test_drain_new_output_returns_each_byte_once() {
+        let mut child = std::process::Command::new("bash")
+            .args(["-c", "echo one && sleep 0.3 && echo two >&2"])
+            .start(io::sink(), io::sink())
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(b"one\n", child.drain_new_output().stdout.as_slice());
+        assert!(child.drain_new_output().is_empty());
+        assert_eq!(None, child.try_wait().unwrap());
+
+        let mut stderr = Vec::new();
+        while stderr.is_empty() {
+            stderr = child.drain_new_output().stderr;
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(b"two\n", stderr.as_slice());
+
+        let output = child.wait().unwrap();
+        assert_eq!("one\n", output.stdout_lossy());
+        assert_eq!("two\n", output.stderr_lossy());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    BackendIo, CmdError, CommandSpec, CommandWithName, ExecutionBackend, IoErrorAnnotation,
+    NamedCommand, NamedOutput,
+};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+
+/// Runs commands with a different root directory, for staged filesystems
+///
+/// Build the command as if it ran inside the root, then change its root with [Chroot::exec].
+/// The program is looked up and the working directory is resolved inside the root, commands
+/// without a working directory start at its `/`. Everything else set on the command, such as
+/// stdio or a cleared environment, is kept. The command keeps its name, prefixed with the
+/// root, such as ``(in chroot /tmp/stage) bundle install``.
+///
+/// Changing the root takes root privileges or `CAP_SYS_CHROOT`. As an [ExecutionBackend]
+/// a spawn that fails for lack of permission says so in the error.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{BackendIo, Chroot, CommandSpec, CommandWithName, ExecutionBackend};
+/// use std::process::Command;
+///
+/// let chroot = Chroot::new("/");
+/// assert_eq!("(in chroot /) ls", chroot.exec(&mut Command::new("ls")).name());
+///
+/// let mut command = Command::new("ls");
+/// command.current_dir("/usr");
+/// let mut spec = CommandSpec::new(command);
+/// match chroot.run(&mut spec, BackendIo::Capture) {
+///     Ok(output) => assert!(output.stdout_lossy().contains("bin")),
+///     Err(error) => assert!(error.to_string().contains("CAP_SYS_CHROOT")),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Chroot {
+    root: PathBuf,
+}
+
+impl Chroot {
+    /// Use `root` as `/` for commands, relative paths are resolved from the current directory
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        // The child changes directory before changing the root
+        let root = root.into();
+        Self {
+            root: std::path::absolute(&root).unwrap_or(root),
+        }
+    }
+
+    /// The directory commands see as `/`
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Change the root of `command`, naming it after `command`
+    ///
+    /// Sets the working directory and a `pre_exec` hook on `command` itself, see
+    /// [ExecutionBackend] for calling it more than once.
+    pub fn exec<'a, C: CommandWithName>(&self, command: &'a mut C) -> NamedCommand<'a> {
+        let name = format!("(in chroot {}) {}", self.root.display(), command.name());
+        let chrooted = command.mut_cmd();
+
+        // The working directory is entered before `pre_exec` runs, so enter it through the
+        // root first, then again by its path inside the root
+        let dir = chrooted
+            .get_current_dir()
+            .map_or_else(|| PathBuf::from("/"), |dir| Path::new("/").join(dir));
+        chrooted.current_dir(self.root.join(dir.strip_prefix("/").unwrap_or(&dir)));
+
+        // Allocate before forking, the child may only call async-signal-safe functions
+        let root = CString::new(self.root.as_os_str().as_bytes()).unwrap_or_default();
+        let dir = CString::new(dir.as_os_str().as_bytes()).unwrap_or_default();
+        // SAFETY: `chroot` and `chdir` are async-signal-safe and the strings were allocated
+        // before forking
+        unsafe {
+            chrooted.pre_exec(move || {
+                if libc::chroot(root.as_ptr()) != 0 || libc::chdir(dir.as_ptr()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command.named(name)
+    }
+
+    /// Explain spawn errors caused by missing privileges
+    fn explain(&self, error: CmdError) -> CmdError {
+        match error {
+            CmdError::SystemError(name, error, run_id)
+                if error.kind() == std::io::ErrorKind::PermissionDenied =>
+            {
+                let annotation = format!(
+                    "Changing the root to `{}` requires running as root or the `CAP_SYS_CHROOT` capability",
+                    self.root.display()
+                );
+                CmdError::SystemError(
+                    name,
+                    IoErrorAnnotation::new(error, annotation).into_io_error(),
+                    run_id,
+                )
+            }
+            error => error,
+        }
+    }
+}
+
+impl ExecutionBackend for Chroot {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(&mut self.exec(spec))
+            .map_err(|error| self.explain(error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_permission_errors_are_explained() {
+        let chroot = Chroot::new("/srv/stage");
+        let error = chroot.explain(CmdError::SystemError(
+            "(in chroot /srv/stage) ls".to_string(),
+            std::io::Error::from_raw_os_error(libc::EPERM),
+            None,
+        ));
+        assert!(error
+            .to_string()
+            .contains("Changing the root to `/srv/stage` requires running as root"));
+
+        let mut command = Command::new("ls");
+        command.current_dir("app").env_clear();
+        let _ = chroot.exec(&mut command);
+        assert_eq!(Some(Path::new("/srv/stage/app")), command.get_current_dir());
+    }
+
+    #[test]
+    fn test_secrets_stay_masked() {
+        let mut command = Command::new("deploy");
+        command.arg("hunter2");
+        let mut named = command.named_redacting(["hunter2"]);
+        assert_eq!(
+            vec!["deploy", "****"],
+            Chroot::new("/srv/stage").exec(&mut named).log_argv()
+        );
+    }
+}
@@ -1,25 +1,229 @@
+use crate::progress::{OutputStats, PhaseTimer, ProgressTracker, SilenceTimer};
+use crate::Stream;
+use std::ffi::{OsStr, OsString};
 use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process::Command;
 use std::{io, process, thread};
 use std::{mem, panic};
 
+/// Like [Command::output], suggesting similarly named programs when it is not found
+///
+/// NUL bytes in the program, arguments or environment and exceeding `ARG_MAX` become a
+/// [crate::ValidationError] naming the problem.
+pub(crate) fn output(command: &mut Command) -> io::Result<process::Output> {
+    let _capturing = crate::orphans::track_thread();
+    command
+        .output()
+        .map_err(|error| explain_spawn_error(command, error))
+}
+
+/// Like [output] but stdout is discarded instead of captured
+pub(crate) fn output_discarding_stdout(command: &mut Command) -> io::Result<process::Output> {
+    output(
+        command
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::piped()),
+    )
+}
+
+/// Like [Command::spawn], suggesting similarly named programs when it is not found
+///
+/// NUL bytes in the program, arguments or environment and exceeding `ARG_MAX` become a
+/// [crate::ValidationError] naming the problem.
+pub(crate) fn spawn(command: &mut Command) -> io::Result<process::Child> {
+    let child = command
+        .spawn()
+        .map_err(|error| explain_spawn_error(command, error))?;
+    crate::orphans::track(&child);
+    Ok(child)
+}
+
+fn explain_spawn_error(command: &mut Command, error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::InvalidInput {
+        if let Some(validation_error) = crate::validate::find_nul(command) {
+            return io::Error::new(io::ErrorKind::InvalidInput, validation_error);
+        }
+    }
+    if error.raw_os_error() == Some(libc::E2BIG) {
+        let validation_error =
+            if let Some((size, limit)) = crate::validate::exceeds_arg_max(command) {
+                crate::ValidationError::ArgumentListTooLong(crate::display(command), size, limit)
+            } else if let Some((position, len)) = crate::validate::exceeds_arg_strlen(command) {
+                crate::ValidationError::ArgumentTooLong(crate::display(command), position, len)
+            } else {
+                // The limits could not be read or the estimate is off, the plain error is accurate
+                return error;
+            };
+        return io::Error::new(error.kind(), validation_error);
+    }
+    hint_not_found(command, error)
+}
+
+/// Adds "Did you mean" to a not found error when an executable on the `PATH` has a similar name
+fn hint_not_found(command: &Command, error: io::Error) -> io::Error {
+    if error.kind() != io::ErrorKind::NotFound {
+        return error;
+    }
+    match path_env(command).and_then(|path_env| did_you_mean(command.get_program(), &path_env)) {
+        Some(suggestion) => io::Error::new(
+            error.kind(),
+            NotFoundHint {
+                source: error,
+                suggestion,
+            },
+        ),
+        None => error,
+    }
+}
+
+/// A not found error with the closest program name on the `PATH`, see [hint_not_found]
+#[derive(Debug)]
+struct NotFoundHint {
+    source: io::Error,
+    suggestion: String,
+}
+
+impl std::fmt::Display for NotFoundHint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.source)?;
+        write!(f, "Did you mean `{}`?", self.suggestion)
+    }
+}
+
+impl std::error::Error for NotFoundHint {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Removes the "Did you mean" hint from a not found error
+///
+/// `which_problem` lists its own guesses, showing both would repeat the suggestion.
+#[cfg(feature = "which_problem")]
+pub(crate) fn without_hint(error: io::Error) -> io::Error {
+    if !error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<NotFoundHint>())
+    {
+        return error;
+    }
+    let kind = error.kind();
+    match error
+        .into_inner()
+        .map(|inner| inner.downcast::<NotFoundHint>())
+    {
+        Some(Ok(hint)) => hint.source,
+        Some(Err(inner)) => io::Error::new(kind, inner),
+        None => io::Error::from(kind),
+    }
+}
+
+/// The `PATH` the command will search, set on the command or inherited from the parent
+pub(crate) fn path_env(command: &Command) -> Option<OsString> {
+    env_value(command, OsStr::new("PATH"))
+}
+
+/// The value the command will see for `key`, set on the command or inherited from the parent
+///
+/// `Command` has no stable way to tell whether [Command::env_clear] was called, so keys that
+/// are not set on the command are assumed to be inherited.
+pub(crate) fn env_value(command: &Command, key: &OsStr) -> Option<OsString> {
+    command.get_envs().find(|(set, _)| *set == key).map_or_else(
+        || std::env::var_os(key),
+        |(_, value)| value.map(OsStr::to_os_string),
+    )
+}
+
+/// Finds the executable on the `PATH` with the closest name, if one is close enough
+fn did_you_mean(program: &OsStr, path_env: &OsStr) -> Option<String> {
+    let program = program.to_str()?;
+    if program.contains('/') {
+        return None;
+    }
+    let max_distance = (program.chars().count() / 3).clamp(1, 2);
+
+    std::env::split_paths(path_env)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let distance = if name.eq_ignore_ascii_case(program) {
+                0
+            } else {
+                edit_distance(program, &name)
+            };
+            (distance <= max_distance && name != program && is_executable(&entry.path()))
+                .then_some((distance, name))
+        })
+        .min()
+        .map(|(_, name)| name)
+}
+
+pub(crate) fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Runs the command, writing output to the writers as it arrives and capturing it
+///
+/// Also returns [OutputStats] about when output arrived and how long each phase took.
 pub(crate) fn output_and_write_streams<OW: Write + Send, EW: Write + Send>(
     command: &mut Command,
     stdout_write: OW,
     stderr_write: EW,
-) -> io::Result<process::Output> {
+) -> io::Result<(process::Output, OutputStats)> {
+    output_and_write_streams_on_spawn(command, stdout_write, stderr_write, |_| {})
+}
+
+/// Like [output_and_write_streams] but calls `on_spawn` with the child before output is read
+pub(crate) fn output_and_write_streams_on_spawn<OW: Write + Send, EW: Write + Send>(
+    command: &mut Command,
+    stdout_write: OW,
+    stderr_write: EW,
+    on_spawn: impl FnOnce(&process::Child),
+) -> io::Result<(process::Output, OutputStats)> {
     let mut stdout_buffer = Vec::new();
     let mut stderr_buffer = Vec::new();
 
-    let mut stdout = tee(&mut stdout_buffer, stdout_write);
-    let mut stderr = tee(&mut stderr_buffer, stderr_write);
+    let mut silence = SilenceTimer::start();
+    let tracker = ProgressTracker::new(|_| silence.record());
+    let mut stdout = tee(
+        &mut stdout_buffer,
+        tracker.writer(Stream::Stdout, stdout_write),
+    );
+    let mut stderr = tee(
+        &mut stderr_buffer,
+        tracker.writer(Stream::Stderr, stderr_write),
+    );
 
-    let mut child = command
-        .stdout(process::Stdio::piped())
-        .stderr(process::Stdio::piped())
-        .spawn()?;
+    let mut phases = PhaseTimer::start();
+    let mut child = spawn(
+        command
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped()),
+    )?;
+    phases.spawned();
+    on_spawn(&child);
 
-    thread::scope(|scope| {
+    let status = thread::scope(|scope| {
         let stdout_thread = mem::take(&mut child.stdout).map(|mut child_stdout| {
             scope.spawn(move || std::io::copy(&mut child_stdout, &mut stdout))
         });
@@ -27,7 +231,7 @@ pub(crate) fn output_and_write_streams<OW: Write + Send, EW: Write + Send>(
             scope.spawn(move || std::io::copy(&mut child_stderr, &mut stderr))
         });
 
-        stdout_thread
+        let copied = stdout_thread
             .map_or_else(
                 || Ok(0),
                 |handle| match handle.join() {
@@ -43,14 +247,20 @@ pub(crate) fn output_and_write_streams<OW: Write + Send, EW: Write + Send>(
                         Err(err) => panic::resume_unwind(err),
                     },
                 )
-            })
-            .and_then(|_| child.wait())
-    })
-    .map(|status| process::Output {
+            });
+        phases.read();
+        // Wait even when writing failed so the child is not left behind
+        let status = child.wait();
+        copied.and(status)
+    })?;
+
+    let stats = silence.finish(stdout_buffer.len() + stderr_buffer.len(), phases.finish());
+    let output = process::Output {
         status,
         stdout: stdout_buffer,
         stderr: stderr_buffer,
-    })
+    };
+    Ok((output, stats))
 }
 
 /// Constructs a writer that writes to two other writers. Similar to the UNIX `tee` command.
@@ -87,6 +297,102 @@ mod test {
     use pretty_assertions::assert_str_eq;
     use std::process::Command;
 
+    #[test]
+    fn test_env_value_prefers_the_command() {
+        let mut command = Command::new("printenv");
+        command.env("PATH", "/bin");
+        assert_eq!(
+            std::env::var_os("HOME"),
+            env_value(&command, OsStr::new("HOME"))
+        );
+        assert_eq!(Some(OsString::from("/bin")), path_env(&command));
+
+        command.env_remove("HOME");
+        assert_eq!(None, env_value(&command, OsStr::new("HOME")));
+    }
+
+    #[test]
+    fn test_write_error_still_waits_for_child() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken writer"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut pid = 0;
+        let error = output_and_write_streams_on_spawn(
+            Command::new("echo").arg("hello"),
+            Broken,
+            io::sink(),
+            |child| pid = child.id(),
+        )
+        .unwrap_err();
+        assert_eq!("broken writer", error.to_string());
+
+        // SAFETY: `kill` with signal 0 only checks whether the process exists
+        let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+        assert!(!exists, "child {pid} was not waited on");
+    }
+
+    #[test]
+    fn test_not_found_suggests_similar_program() {
+        let dir = std::env::temp_dir().join(format!("fun_run_did_you_mean_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bundle = dir.join("bundle");
+        std::fs::write(&bundle, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&bundle, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut command = Command::new("bundel");
+        command.env("PATH", &dir);
+        let error = output(&mut command).unwrap_err();
+        let mut command = Command::new("something_else_entirely");
+        command.env("PATH", &dir);
+        let unrelated = output(&mut command).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+        assert!(
+            error.to_string().ends_with("Did you mean `bundle`?"),
+            "{error}"
+        );
+        assert!(
+            !unrelated.to_string().contains("Did you mean"),
+            "{unrelated}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "which_problem")]
+    fn test_which_problem_replaces_did_you_mean() {
+        let dir =
+            std::env::temp_dir().join(format!("fun_run_which_problem_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bundle = dir.join("bundle");
+        std::fs::write(&bundle, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&bundle, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut command = Command::new("bundel");
+        command.env("PATH", &dir);
+        let error = crate::CmdError::SystemError(
+            "bundel".to_string(),
+            output(&mut command).unwrap_err(),
+            None,
+        );
+        let error = crate::map_which_problem(error, &mut command, Some(dir.clone().into()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let message = error.to_string();
+        assert!(
+            message.contains("System diagnostic information"),
+            "{message}"
+        );
+        assert!(!message.contains("Did you mean `bundle`?"), "{message}");
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_output_and_write_streams_stdout() {
@@ -96,7 +402,8 @@ mod test {
         let mut cmd = Command::new("echo");
         cmd.args(["-n", "Hello World!"]);
 
-        let output = output_and_write_streams(&mut cmd, &mut stdout_buf, &mut stderr_buf).unwrap();
+        let (output, _) =
+            output_and_write_streams(&mut cmd, &mut stdout_buf, &mut stderr_buf).unwrap();
 
         assert_eq!(stdout_buf, "Hello World!".as_bytes());
         assert_eq!(stderr_buf, Vec::<u8>::new());
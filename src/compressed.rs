@@ -0,0 +1,154 @@
+use crate::NamedOutput;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// A [NamedOutput] with stdout and stderr kept gzip compressed in memory
+///
+/// Verbose commands can produce megabytes of output that mostly compress very well. When
+/// holding on to many outputs, such as every step of a long build, compress them as they
+/// finish and decompress only the ones you need to show. Created with [NamedOutput::compress].
+///
+/// Example:
+///
+/// ```
+/// use fun_run::CommandWithName;
+/// use std::process::Command;
+///
+/// let output = Command::new("bash")
+///     .args(["-c", "for i in $(seq 1 1000); do echo 'Compiling widget'; done"])
+///     .named_output()
+///     .unwrap()
+///     .compress();
+///
+/// println!(
+///     "Kept {} bytes of output in {} bytes",
+///     output.uncompressed_bytes(),
+///     output.compressed_bytes()
+/// );
+/// assert!(output.compressed_bytes() < output.uncompressed_bytes() / 10);
+///
+/// let output = output.decompress();
+/// assert_eq!(1000, output.stdout_lossy().lines().count());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedOutput {
+    inner: NamedOutput,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    stdout_len: usize,
+    stderr_len: usize,
+}
+
+impl NamedOutput {
+    /// Compress stdout and stderr, see [CompressedOutput]
+    #[must_use]
+    pub fn compress(mut self) -> CompressedOutput {
+        let stdout = std::mem::take(&mut self.output.stdout);
+        let stderr = std::mem::take(&mut self.output.stderr);
+        CompressedOutput {
+            stdout: deflate(&stdout),
+            stderr: deflate(&stderr),
+            stdout_len: stdout.len(),
+            stderr_len: stderr.len(),
+            inner: self,
+        }
+    }
+}
+
+impl CompressedOutput {
+    /// Name of the command that was run
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// Returns true if the command exited successfully
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.inner.success()
+    }
+
+    /// Decompressed stdout
+    #[must_use]
+    pub fn stdout(&self) -> Vec<u8> {
+        inflate(&self.stdout, self.stdout_len)
+    }
+
+    /// Decompressed stderr
+    #[must_use]
+    pub fn stderr(&self) -> Vec<u8> {
+        inflate(&self.stderr, self.stderr_len)
+    }
+
+    /// Bytes used to store stdout and stderr
+    #[must_use]
+    pub fn compressed_bytes(&self) -> usize {
+        self.stdout.len() + self.stderr.len()
+    }
+
+    /// Bytes of stdout and stderr once decompressed
+    #[must_use]
+    pub fn uncompressed_bytes(&self) -> usize {
+        self.stdout_len + self.stderr_len
+    }
+
+    /// Decompress back into the original [NamedOutput]
+    #[must_use]
+    pub fn decompress(self) -> NamedOutput {
+        let mut named_output = self.inner;
+        named_output.output.stdout = inflate(&self.stdout, self.stdout_len);
+        named_output.output.stderr = inflate(&self.stderr, self.stderr_len);
+        named_output
+    }
+}
+
+impl From<CompressedOutput> for NamedOutput {
+    fn from(value: CompressedOutput) -> Self {
+        value.decompress()
+    }
+}
+
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|()| encoder.finish())
+        .expect("Writing to a Vec cannot fail")
+}
+
+fn inflate(compressed: &[u8], len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    GzDecoder::new(compressed)
+        .read_to_end(&mut bytes)
+        .expect("Output compressed in memory is valid gzip");
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::process::Command;
+
+    #[test]
+    fn test_compress_round_trip_keeps_everything() {
+        let original = Command::new("bash")
+            .args(["-c", "echo out; echo err >&2; exit 2"])
+            .named_output()
+            .unwrap_err();
+        let original = NamedOutput::from(original);
+
+        let compressed = original.clone().compress();
+        assert_eq!(original.name(), compressed.name());
+        assert!(!compressed.success());
+        assert_eq!(b"out\n".to_vec(), compressed.stdout());
+        assert_eq!(b"err\n".to_vec(), compressed.stderr());
+        assert_eq!(8, compressed.uncompressed_bytes());
+
+        let decompressed = compressed.decompress();
+        assert_eq!(original, decompressed);
+        assert_eq!(original.run_id(), decompressed.run_id());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Asks before running a command, for destructive operations
+///
+/// Prints the command name and `Run? [y/N]` then waits for an answer, by default on stderr
+/// and stdin. Anything but `y` or `yes` declines, including closed stdin, and returns
+/// `CmdError::Declined` without running the command. Use [Confirm::assume_yes] to skip the
+/// prompt, such as for a `--yes` flag or in CI. Clones share the input and output.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CmdError, CommandWithName, Confirm, FunRunner};
+/// use std::process::Command;
+///
+/// let confirm = Confirm::new().with_io(&b"n\ny\n"[..], std::io::sink());
+/// let runner = FunRunner::new().confirm_then_run(confirm);
+///
+/// let mut command = Command::new("echo");
+/// command.arg("dropping database");
+///
+/// let error = runner.run(&mut command, |cmd| cmd.named_output()).unwrap_err();
+/// assert!(matches!(error, CmdError::Declined(_)));
+///
+/// let output = runner.run(&mut command, |cmd| cmd.named_output()).unwrap();
+/// assert_eq!("dropping database\n", output.stdout_lossy());
+///
+/// let runner = FunRunner::new().confirm_then_run(Confirm::new().assume_yes(true));
+/// assert!(runner.run(&mut command, |cmd| cmd.named_output()).is_ok());
+/// ```
+#[derive(Clone, Default)]
+pub struct Confirm {
+    assume_yes: bool,
+    io: Option<Arc<Mutex<ConfirmIo>>>,
+}
+
+struct ConfirmIo {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+}
+
+impl std::fmt::Debug for Confirm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Confirm")
+            .field("assume_yes", &self.assume_yes)
+            .field("io", &self.io.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+impl Confirm {
+    /// Prompt on stderr and read the answer from stdin
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run without asking when `yes` is true, for non-interactive use
+    #[must_use]
+    pub fn assume_yes(mut self, yes: bool) -> Self {
+        self.assume_yes = yes;
+        self
+    }
+
+    /// Read answers from `input` and write prompts to `output` instead of stdin and stderr
+    #[must_use]
+    pub fn with_io(
+        mut self,
+        input: impl BufRead + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        self.io = Some(Arc::new(Mutex::new(ConfirmIo {
+            input: Box::new(input),
+            output: Box::new(output),
+        })));
+        self
+    }
+
+    /// Run the command with `f` once the user agrees
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::Declined` if the user did not agree, or `CmdError::SystemError` if
+    /// the prompt could not be shown or answered, otherwise any error returned by `f`.
+    pub fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        self.ask(command.name())?;
+        f(command)
+    }
+
+    /// Returns an error unless the command named `name` may run
+    pub(crate) fn ask(&self, name: String) -> Result<(), CmdError> {
+        if self.assume_yes {
+            return Ok(());
+        }
+        let answer = match &self.io {
+            Some(io) => {
+                let mut io = io.lock().unwrap_or_else(PoisonError::into_inner);
+                let ConfirmIo { input, output } = &mut *io;
+                prompt(&name, input, output)
+            }
+            None => prompt(&name, &mut std::io::stdin().lock(), &mut std::io::stderr()),
+        };
+        match answer {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(CmdError::Declined(name)),
+            Err(error) => Err(CmdError::SystemError(name, error, None)),
+        }
+    }
+}
+
+/// Prints the prompt and returns true if the answer is yes
+fn prompt(name: &str, input: &mut impl BufRead, output: &mut impl Write) -> std::io::Result<bool> {
+    write!(output, "About to run `{name}`\nRun? [y/N] ")?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prompt_answers() {
+        let answers = ["y\n", "YES\n", " yes \n", "n\n", "\n", "", "yep\n"]
+            .map(|answer| prompt("rm -rf build", &mut answer.as_bytes(), &mut Vec::new()).unwrap());
+        assert_eq!([true, true, true, false, false, false, false], answers);
+
+        let mut output = Vec::new();
+        prompt("rm -rf build", &mut "y\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            "About to run `rm -rf build`\nRun? [y/N] ",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
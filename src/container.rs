@@ -0,0 +1,116 @@
+use crate::{BackendIo, CmdError, CommandSpec, CommandWithName, ExecutionBackend, NamedOutput};
+use std::ffi::OsString;
+use std::process::Command;
+
+/// Runs commands inside a running container with `docker exec` or `podman exec`
+///
+/// Build the command as if it ran locally, then translate it with [Container::exec]. The
+/// working directory becomes `--workdir` and environment variables set on the command become
+/// `--env`, both resolved inside the container. Variables removed from the command or a
+/// cleared environment can't be expressed with `exec`, so the container's own values stay.
+/// The command keeps its name, prefixed with the container, so errors read
+/// ``Command failed `(in container web-1) bundle install` `` instead of showing the
+/// `docker` invocation. As an [ExecutionBackend] it runs a [CommandSpec] this way.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Container};
+/// use std::process::Command;
+///
+/// let mut command = Command::new("bundle");
+/// command.arg("install").current_dir("/app").env("RAILS_ENV", "test");
+///
+/// let mut exec = Container::docker("web-1").exec(&mut command);
+/// assert_eq!("(in container web-1) bundle install", exec.name());
+/// assert_eq!(
+///     vec!["exec", "--workdir", "/app", "--env", "RAILS_ENV=test", "web-1", "bundle", "install"],
+///     exec.command().get_args().collect::<Vec<_>>()
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Container {
+    runtime: OsString,
+    name: String,
+}
+
+impl Container {
+    /// A container run by `docker`
+    #[must_use]
+    pub fn docker(name: impl Into<String>) -> Self {
+        Self::with_runtime("docker", name)
+    }
+
+    /// A container run by `podman`
+    #[must_use]
+    pub fn podman(name: impl Into<String>) -> Self {
+        Self::with_runtime("podman", name)
+    }
+
+    /// A container run by any program that takes `exec` like `docker`, such as `nerdctl`
+    #[must_use]
+    pub fn with_runtime(runtime: impl Into<OsString>, name: impl Into<String>) -> Self {
+        Self {
+            runtime: runtime.into(),
+            name: name.into(),
+        }
+    }
+
+    /// The container name or id
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A command that runs `command` in the container, named after `command`
+    #[must_use]
+    pub fn exec<C: CommandWithName>(&self, command: &mut C) -> CommandSpec {
+        let name = format!("(in container {}) {}", self.name, command.name());
+        let inner = command.mut_cmd();
+
+        let mut exec = Command::new(&self.runtime);
+        exec.arg("exec");
+        if let Some(dir) = inner.get_current_dir() {
+            exec.arg("--workdir").arg(dir);
+        }
+        for (key, value) in inner.get_envs() {
+            if let Some(value) = value {
+                let mut pair = key.to_os_string();
+                pair.push("=");
+                pair.push(value);
+                exec.arg("--env").arg(pair);
+            }
+        }
+        exec.arg(&self.name)
+            .arg(inner.get_program())
+            .args(inner.get_args());
+
+        CommandSpec::new(exec).with_name(name)
+    }
+}
+
+impl ExecutionBackend for Container {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(&mut self.exec(spec))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exec_keeps_custom_name_and_program_args() {
+        let mut command = Command::new("rake");
+        command.args(["db:migrate", "--trace"]).env_remove("DEBUG");
+        let mut named = command.named("rake db:migrate");
+
+        let mut exec = Container::podman("db").exec(&mut named);
+        assert_eq!("(in container db) rake db:migrate", exec.name());
+        assert_eq!(
+            vec!["exec", "db", "rake", "db:migrate", "--trace"],
+            exec.command().get_args().collect::<Vec<_>>()
+        );
+        assert_eq!("podman", exec.command().get_program());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{CmdError, CommandWithName, NameFit, NamedOutput, TerminalSize};
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+/// Prints a header before a command runs and a footer with the outcome once it finishes
+///
+/// Meant for streamed commands, the output shows up between the two lines:
+///
+/// ```text
+/// ▶ bundle install
+/// ...
+/// ✔ done in 12.3s
+/// ```
+///
+/// A failed command prints `✘ failed (exit 1)`, or `(signal 9)` when it was killed. Use
+/// [Decorations::ascii] for terminals that can't show these symbols or
+/// [Decorations::symbols] to pick your own. Lines are written to stdout unless
+/// [Decorations::with_output] is used. Clones share the output.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Decorations};
+/// use std::process::Command;
+/// use std::sync::{Arc, Mutex};
+///
+/// #[derive(Clone, Default)]
+/// struct Shared(Arc<Mutex<Vec<u8>>>);
+///
+/// impl std::io::Write for Shared {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+///         self.0.lock().unwrap().write(buf)
+///     }
+///     fn flush(&mut self) -> std::io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let log = Shared::default();
+/// let decorations = Decorations::new().ascii().with_output(log.clone());
+///
+/// decorations
+///     .run(Command::new("echo").arg("installing"), |cmd| {
+///         cmd.stream_output(log.clone(), log.clone())
+///     })
+///     .unwrap();
+/// let _ = decorations.run(&mut Command::new("false"), |cmd| {
+///     cmd.stream_output(log.clone(), log.clone())
+/// });
+///
+/// let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+/// let lines = log.lines().collect::<Vec<_>>();
+/// assert_eq!("> echo installing", lines[0]);
+/// assert_eq!("installing", lines[1]);
+/// assert!(lines[2].starts_with("+ done in "));
+/// assert_eq!(vec!["> false", "x failed (exit 1)"], lines[3..]);
+/// ```
+#[derive(Clone)]
+pub struct Decorations {
+    start: String,
+    success: String,
+    failure: String,
+    name_fit: Option<NameFit>,
+    output: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl std::fmt::Debug for Decorations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Decorations")
+            .field("start", &self.start)
+            .field("success", &self.success)
+            .field("failure", &self.failure)
+            .field("name_fit", &self.name_fit)
+            .field("output", &self.output.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+impl Default for Decorations {
+    fn default() -> Self {
+        Self {
+            start: "▶".to_string(),
+            success: "✔".to_string(),
+            failure: "✘".to_string(),
+            name_fit: None,
+            output: None,
+        }
+    }
+}
+
+impl Decorations {
+    /// Decorate with `▶`, `✔` and `✘`
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decorate with `>`, `+` and `x` instead
+    #[must_use]
+    pub fn ascii(self) -> Self {
+        self.symbols(">", "+", "x")
+    }
+
+    /// Decorate with custom symbols for the header, the success footer and the failure footer
+    #[must_use]
+    pub fn symbols(
+        mut self,
+        start: impl Into<String>,
+        success: impl Into<String>,
+        failure: impl Into<String>,
+    ) -> Self {
+        self.start = start.into();
+        self.success = success.into();
+        self.failure = failure.into();
+        self
+    }
+
+    /// Fit the name in the header to the width of the terminal, see [TerminalSize::detect]
+    ///
+    /// Names are shown in full when no width is detected.
+    #[must_use]
+    pub fn name_fit(mut self, fit: NameFit) -> Self {
+        self.name_fit = Some(fit);
+        self
+    }
+
+    /// Write the header and footer to `output` instead of stdout
+    #[must_use]
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Some(Arc::new(Mutex::new(Box::new(output))));
+        self
+    }
+
+    /// Run the command with `f` between the header and the footer
+    ///
+    /// Errors writing the footer are ignored so they never change the result of a run.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the header could not be written, otherwise any
+    /// error returned by `f`.
+    pub fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        let name = command.name();
+        self.write(&format!("{} {}\n", self.start, self.header_name(&name)))
+            .map_err(|error| CmdError::SystemError(name, error, None))?;
+
+        let start = Instant::now();
+        let result = f(command);
+        let footer = match &result {
+            Ok(_) => format!(
+                "{} done in {:.1}s\n",
+                self.success,
+                start.elapsed().as_secs_f64()
+            ),
+            Err(error) => format!("{} {}\n", self.failure, failure(error)),
+        };
+        let _ = self.write(&footer);
+        result
+    }
+
+    fn header_name(&self, name: &str) -> String {
+        let width = TerminalSize::detect().map(|size| usize::from(size.columns));
+        match (self.name_fit, width) {
+            (Some(fit), Some(width)) => {
+                fit.apply(name, width.saturating_sub(self.start.chars().count() + 1))
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    fn write(&self, line: &str) -> std::io::Result<()> {
+        match &self.output {
+            Some(output) => {
+                let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+                output.write_all(line.as_bytes())?;
+                output.flush()
+            }
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(line.as_bytes())?;
+                stdout.flush()
+            }
+        }
+    }
+}
+
+/// Describes how the command failed, with its exit code or signal when it ran
+fn failure(error: &CmdError) -> String {
+    let named_output = error.named_output();
+    match (
+        named_output.and_then(NamedOutput::code),
+        named_output.and_then(NamedOutput::signal),
+    ) {
+        (Some(code), _) => format!("failed (exit {code})"),
+        (None, Some(signal)) => format!("failed (signal {signal})"),
+        (None, None) => "failed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failure_describes_exit() {
+        let error = std::process::Command::new("bash")
+            .args(["-c", "kill -9 $$"])
+            .named_output()
+            .unwrap_err();
+        assert_eq!("failed (signal 9)", failure(&error));
+
+        let error = CmdError::Declined("rm -rf build".to_string());
+        assert_eq!("failed", failure(&error));
+    }
+}
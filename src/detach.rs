@@ -0,0 +1,219 @@
+use crate::CmdError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const PIDFILE: &str = "pid";
+const STDOUT_LOG: &str = "stdout.log";
+const STDERR_LOG: &str = "stderr.log";
+
+/// Where a detached command lives, see [crate::CommandWithName::detach]
+///
+/// The pidfile holds the pid on the first line and the command name on the second, so it can
+/// be identified later with [Detached::from_dir].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detached {
+    name: String,
+    pid: u32,
+    dir: PathBuf,
+}
+
+impl Detached {
+    /// Load a previously detached command from the directory it was detached into
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pidfile cannot be read or is not in the expected format
+    pub fn from_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let contents = fs::read_to_string(dir.join(PIDFILE))?;
+        let (pid, name) = contents.split_once('\n').unwrap_or((&contents, ""));
+        let pid = pid.trim().parse().map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid pid in {}: {error}", dir.join(PIDFILE).display()),
+            )
+        })?;
+
+        Ok(Self {
+            name: name.trim_end_matches('\n').to_string(),
+            pid,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Name of the detached command
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Process id of the detached command
+    #[must_use]
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// File holding the pid and command name
+    #[must_use]
+    pub fn pidfile(&self) -> PathBuf {
+        self.dir.join(PIDFILE)
+    }
+
+    /// File the command's stdout is appended to
+    #[must_use]
+    pub fn stdout_path(&self) -> PathBuf {
+        self.dir.join(STDOUT_LOG)
+    }
+
+    /// File the command's stderr is appended to
+    #[must_use]
+    pub fn stderr_path(&self) -> PathBuf {
+        self.dir.join(STDERR_LOG)
+    }
+
+    /// Returns true if a process with the recorded pid still exists
+    #[must_use]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn is_running(&self) -> bool {
+        // SAFETY: Signal 0 only checks that the process exists
+        unsafe { libc::kill(self.pid as libc::pid_t, 0) == 0 }
+    }
+}
+
+pub(crate) fn detach(
+    name: String,
+    command: &mut Command,
+    dir: &Path,
+) -> Result<Detached, CmdError> {
+    let system_error = |error| CmdError::SystemError(name.clone(), error, None);
+    let mut detached = Detached {
+        name: name.clone(),
+        pid: 0,
+        dir: dir.to_path_buf(),
+    };
+
+    fs::create_dir_all(dir).map_err(system_error)?;
+    let stdout = append(&detached.stdout_path()).map_err(system_error)?;
+    let stderr = append(&detached.stderr_path()).map_err(system_error)?;
+    let (pid_read, pid_write) = pipe().map_err(system_error)?;
+
+    let pid_fd = pid_write.as_raw_fd();
+    command.stdin(Stdio::null()).stdout(stdout).stderr(stderr);
+    // SAFETY: Only async-signal-safe functions are called in the child
+    unsafe {
+        command.pre_exec(move || daemonize(pid_fd));
+    }
+
+    let mut intermediate = command.spawn().map_err(system_error)?;
+    drop(pid_write);
+    intermediate.wait().map_err(system_error)?;
+
+    let mut bytes = Vec::new();
+    File::from(pid_read)
+        .read_to_end(&mut bytes)
+        .map_err(system_error)?;
+    let pid = <[u8; 4]>::try_from(bytes.as_slice())
+        .map(libc::pid_t::from_ne_bytes)
+        .map_err(|_| system_error(io::Error::other("Detached command did not report its pid")))?;
+    detached.pid = pid.unsigned_abs();
+
+    fs::write(detached.pidfile(), format!("{}\n{name}\n", detached.pid)).map_err(system_error)?;
+    Ok(detached)
+}
+
+/// Runs in the forked child: starts a new session then forks again so the command can never
+/// reacquire a controlling terminal. The grandchild reports its pid and goes on to exec.
+#[allow(clippy::cast_possible_wrap)]
+fn daemonize(pid_fd: RawFd) -> io::Result<()> {
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+
+        let pid = libc::getpid().to_ne_bytes();
+        if libc::write(pid_fd, pid.as_ptr().cast(), pid.len()) != pid.len() as isize {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Returns the read and write ends of a pipe, both closed on exec
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors written by `pipe2`
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The descriptors were just opened and are owned by nothing else
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    #[allow(clippy::cast_possible_wrap)]
+    fn test_detach_writes_pidfile_and_logs() {
+        let dir = std::env::temp_dir().join(format!("fun_run_detach_{}", std::process::id()));
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo hello && echo oops >&2 && exec sleep 100"]);
+
+        let detached = detach("sleepy server".to_string(), &mut command, &dir).unwrap();
+        assert_eq!(detached, Detached::from_dir(&dir).unwrap());
+        assert_eq!("sleepy server", detached.name());
+        assert!(detached.is_running());
+
+        // SAFETY: Looking up session ids has no side effects
+        let (own_session, detached_session) =
+            unsafe { (libc::getsid(0), libc::getsid(detached.pid() as libc::pid_t)) };
+        assert_ne!(own_session, detached_session);
+
+        let start = Instant::now();
+        while fs::read_to_string(detached.stderr_path()).unwrap() != "oops\n" {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            "hello\n",
+            fs::read_to_string(detached.stdout_path()).unwrap()
+        );
+
+        // SAFETY: The pid belongs to the sleep started above
+        unsafe { libc::kill(detached.pid() as libc::pid_t, libc::SIGKILL) };
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_detach_missing_program() {
+        let dir =
+            std::env::temp_dir().join(format!("fun_run_detach_missing_{}", std::process::id()));
+        let error = detach(
+            "missing".to_string(),
+            &mut Command::new("fun_run_does_not_exist"),
+            &dir,
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, CmdError::SystemError(..)));
+        assert!(!dir.join(PIDFILE).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{CmdError, NamedOutput};
+use miette::{Diagnostic, LabeledSpan, Severity, SourceCode};
+use std::fmt::Display;
+use std::os::unix::process::ExitStatusExt;
+
+/// Rich reports for applications using `miette`
+///
+/// Each variant has a code such as `fun_run::non_zero_exit`, help text suggesting what to try
+/// next, and output that was captured but not streamed to the user is attached as labeled
+/// source. Stderr is preferred, stdout is used when stderr is empty.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::CommandWithName;
+/// use miette::Diagnostic;
+/// use std::process::Command;
+///
+/// let error = Command::new("fun_run_does_not_exist")
+///     .named_output()
+///     .unwrap_err();
+///
+/// assert_eq!("fun_run::system_error", error.code().unwrap().to_string());
+/// assert!(error.help().unwrap().to_string().contains("installed"));
+/// ```
+impl Diagnostic for CmdError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            CmdError::SystemError(..) => "fun_run::system_error",
+            CmdError::NonZeroExitNotStreamed(_)
+            | CmdError::NonZeroExitAlreadyStreamed(_)
+            | CmdError::NonZeroExitStdoutStreamed(_)
+            | CmdError::NonZeroExitStderrStreamed(_)
+            | CmdError::NonZeroExitStdoutDiscarded(_) => "fun_run::non_zero_exit",
+            CmdError::InvalidUtf8(_, _, _) => "fun_run::invalid_utf8",
+            CmdError::ParseError(_, _) => "fun_run::parse_error",
+            CmdError::OutputMatched(_, _) => "fun_run::output_matched",
+            CmdError::NotReady(_, _) => "fun_run::not_ready",
+            CmdError::Interrupted(_, _) => "fun_run::interrupted",
+            CmdError::InteractionFailed(_, _) => "fun_run::interaction_failed",
+            CmdError::CircuitOpen(_, _, _) => "fun_run::circuit_open",
+            CmdError::Throttled(_, _) => "fun_run::throttled",
+            CmdError::Declined(_) => "fun_run::declined",
+            CmdError::Rejected(_, _) => "fun_run::rejected",
+            CmdError::TimedOut(_, _) => "fun_run::timed_out",
+            CmdError::SeccompKilled(_) => "fun_run::seccomp_killed",
+        };
+        Some(Box::new(code))
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        Some(Severity::Error)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        help(self).map(|help| Box::new(help) as Box<dyn Display>)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        captured(self).map(|(_, contents)| contents as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        captured(self).map(|(label, contents)| {
+            Box::new(std::iter::once(LabeledSpan::new_with_span(
+                Some(label.to_string()),
+                (0, contents.len()),
+            ))) as Box<dyn Iterator<Item = LabeledSpan>>
+        })
+    }
+}
+
+/// Suggests what to try next
+fn help(error: &CmdError) -> Option<String> {
+    match error {
+        CmdError::SystemError(name, error, _) => match error.kind() {
+            std::io::ErrorKind::NotFound => Some(format!(
+                "Check that the program for `{name}` is installed and on the PATH"
+            )),
+            std::io::ErrorKind::PermissionDenied => {
+                Some(format!("Check that the program for `{name}` is executable"))
+            }
+            _ => None,
+        },
+        CmdError::NonZeroExitNotStreamed(named_output)
+        | CmdError::NonZeroExitAlreadyStreamed(named_output)
+        | CmdError::NonZeroExitStdoutStreamed(named_output)
+        | CmdError::NonZeroExitStderrStreamed(named_output)
+        | CmdError::NonZeroExitStdoutDiscarded(named_output) => {
+            named_output.status().signal().map(|signal| {
+                format!(
+                    "The command was killed by {}",
+                    crate::signals::signal_name(signal)
+                )
+            })
+        }
+        CmdError::InvalidUtf8(_, _, _) => {
+            Some("Decode the output lossily or with a different encoding".to_string())
+        }
+        CmdError::ParseError(_, _) => None,
+        CmdError::OutputMatched(_, _) => {
+            Some("The command was stopped because its output matched an abort pattern".to_string())
+        }
+        CmdError::NotReady(_, _) => Some(
+            "Check that the readiness check matches the command's output or increase the timeout"
+                .to_string(),
+        ),
+        CmdError::Interrupted(_, _) => {
+            Some("The command was interrupted before it finished, run it again".to_string())
+        }
+        CmdError::InteractionFailed(_, _) => {
+            Some("Check that the expected prompts match the command's output".to_string())
+        }
+        CmdError::CircuitOpen(_, _, _) => {
+            Some("Check why earlier runs failed, the command was not run this time".to_string())
+        }
+        CmdError::SeccompKilled(_) => Some(
+            "Allow the system call in the seccomp filter or change the command to avoid it"
+                .to_string(),
+        ),
+        CmdError::TimedOut(_, _) => Some(
+            "The command was killed for taking too long, check the output for where it got stuck"
+                .to_string(),
+        ),
+        CmdError::Throttled(_, _) => {
+            Some("Try again later, the command was not run this time".to_string())
+        }
+        CmdError::Declined(_) => Some("The command was not run, answer `y` to run it".to_string()),
+        CmdError::Rejected(_, _) => Some(
+            "The command was not run, change it or the allowed and denied programs".to_string(),
+        ),
+    }
+}
+
+/// Output that was captured and not already shown to the user, with a label for its stream
+fn captured(error: &CmdError) -> Option<(&'static str, &Vec<u8>)> {
+    let (named_output, stdout, stderr): (&NamedOutput, bool, bool) = match error {
+        CmdError::NonZeroExitNotStreamed(named_output)
+        | CmdError::NotReady(named_output, _)
+        | CmdError::InteractionFailed(named_output, _)
+        | CmdError::TimedOut(named_output, _)
+        | CmdError::SeccompKilled(named_output) => (named_output, true, true),
+        CmdError::NonZeroExitStdoutStreamed(named_output)
+        | CmdError::NonZeroExitStdoutDiscarded(named_output) => (named_output, false, true),
+        CmdError::NonZeroExitStderrStreamed(named_output) => (named_output, true, false),
+        CmdError::SystemError(..)
+        | CmdError::NonZeroExitAlreadyStreamed(_)
+        | CmdError::InvalidUtf8(_, _, _)
+        | CmdError::ParseError(_, _)
+        | CmdError::OutputMatched(_, _)
+        | CmdError::Interrupted(_, _)
+        | CmdError::CircuitOpen(_, _, _)
+        | CmdError::Throttled(_, _)
+        | CmdError::Declined(_)
+        | CmdError::Rejected(_, _) => return None,
+    };
+
+    [
+        (stderr, "stderr", named_output.stderr()),
+        (stdout, "stdout", named_output.stdout()),
+    ]
+    .into_iter()
+    .find(|(captured, _, contents)| *captured && !contents.is_empty())
+    .map(|(_, label, contents)| (label, contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::process::Command;
+
+    #[test]
+    fn test_captured_stderr_is_labeled() {
+        let error = Command::new("bash")
+            .args(["-c", "echo 'out' && echo 'bad things' >&2 && exit 1"])
+            .named_output()
+            .unwrap_err();
+
+        let labels = error.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(Some("stderr"), labels[0].label());
+        assert_eq!(11, labels[0].len());
+        assert_eq!("fun_run::non_zero_exit", error.code().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_streamed_output_is_not_attached() {
+        let error = Command::new("bash")
+            .args(["-c", "echo 'bad things' >&2 && exit 1"])
+            .stream_output(std::io::sink(), std::io::sink())
+            .unwrap_err();
+
+        assert!(error.source_code().is_none());
+        assert!(error.labels().is_none());
+    }
+}
@@ -0,0 +1,542 @@
+use crate::NamedOutput;
+use std::fmt::Write;
+use std::process::ExitStatus;
+
+/// Lines of unchanged output shown around each change
+const CONTEXT_LINES: usize = 3;
+
+impl NamedOutput {
+    /// Compare the status, stdout and stderr of two runs
+    ///
+    /// `self` is treated as the old output and `other` as the new one. Displaying the
+    /// [OutputDiff] renders a unified diff, useful for golden output tests and for seeing what
+    /// changed between two runs of the same command.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let before = Command::new("printf")
+    ///     .arg("one\ntwo\nthree\n")
+    ///     .named_output()
+    ///     .unwrap();
+    /// let after = Command::new("printf")
+    ///     .arg("one\n2\nthree\n")
+    ///     .named_output()
+    ///     .unwrap();
+    ///
+    /// let diff = before.diff(&after);
+    /// assert!(diff.stdout_changed());
+    /// assert!(!diff.status_changed());
+    /// assert!(diff.to_string().contains(
+    ///     "stdout:\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"
+    /// ));
+    /// ```
+    #[must_use]
+    pub fn diff(&self, other: &NamedOutput) -> OutputDiff {
+        OutputDiff {
+            old_name: self.name().to_string(),
+            new_name: other.name().to_string(),
+            old_status: self.output.status,
+            new_status: other.output.status,
+            stdout: StreamDiff::new(&self.output.stdout, &other.output.stdout),
+            stderr: StreamDiff::new(&self.output.stderr, &other.output.stderr),
+        }
+    }
+}
+
+/// The differences between two [NamedOutput]s, see [NamedOutput::diff]
+///
+/// Displays as a unified diff of stdout and stderr, preceded by the exit statuses when they
+/// differ. Displays as an empty string when nothing changed.
+///
+/// Output is compared byte for byte, so a missing trailing newline or `\r\n` line endings
+/// count as changes. A last line without a newline is followed by `\ No newline at end of
+/// file` like `diff -u` shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputDiff {
+    old_name: String,
+    new_name: String,
+    old_status: ExitStatus,
+    new_status: ExitStatus,
+    stdout: StreamDiff,
+    stderr: StreamDiff,
+}
+
+/// The unified diff hunks of one stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StreamDiff {
+    changed: bool,
+    hunks: String,
+}
+
+impl StreamDiff {
+    fn new(old: &[u8], new: &[u8]) -> Self {
+        let changed = old != new;
+        Self {
+            changed,
+            hunks: if changed {
+                unified(&String::from_utf8_lossy(old), &String::from_utf8_lossy(new))
+            } else {
+                String::new()
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for StreamDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.hunks.is_empty() {
+            writeln!(f, "Bytes that are not valid UTF-8 changed")
+        } else {
+            f.write_str(&self.hunks)
+        }
+    }
+}
+
+impl OutputDiff {
+    /// Returns true when the status, stdout and stderr are all the same
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        !self.status_changed() && !self.stdout_changed() && !self.stderr_changed()
+    }
+
+    /// Returns true when the exit status changed
+    #[must_use]
+    pub fn status_changed(&self) -> bool {
+        self.old_status != self.new_status
+    }
+
+    /// Returns true when stdout changed
+    #[must_use]
+    pub fn stdout_changed(&self) -> bool {
+        self.stdout.changed
+    }
+
+    /// Returns true when stderr changed
+    #[must_use]
+    pub fn stderr_changed(&self) -> bool {
+        self.stderr.changed
+    }
+}
+
+impl std::fmt::Display for OutputDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "--- {}", self.old_name)?;
+        writeln!(f, "+++ {}", self.new_name)?;
+        if self.status_changed() {
+            writeln!(f, "status: {} => {}", self.old_status, self.new_status)?;
+        }
+        if self.stdout_changed() {
+            write!(f, "stdout:\n{}", self.stdout)?;
+        }
+        if self.stderr_changed() {
+            write!(f, "stderr:\n{}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Unified diff hunks of two texts, empty when they are the same
+///
+/// Lines keep their line endings so that `\r\n` and a missing final newline are changes.
+fn unified(old: &str, new: &str) -> String {
+    let old = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = shortest_edits(&old, &new);
+
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Same(..)))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    // Group changes that are close enough for their context to touch
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for index in changes {
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + CONTEXT_LINES + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = String::new();
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_lines = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Added(_)))
+            .count();
+        let new_lines = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Removed(_)))
+            .count();
+        let (old_start, new_start) = position(&edits[..start]);
+        let _ = writeln!(
+            out,
+            "@@ -{},{old_lines} +{},{new_lines} @@",
+            range_start(old_start, old_lines),
+            range_start(new_start, new_lines)
+        );
+        for edit in hunk {
+            let (prefix, line) = match *edit {
+                Edit::Same(old_index, _) => (' ', old[old_index]),
+                Edit::Removed(old_index) => ('-', old[old_index]),
+                Edit::Added(new_index) => ('+', new[new_index]),
+            };
+            let _ = match line.strip_suffix('\n') {
+                Some(line) => writeln!(out, "{prefix}{line}"),
+                None => writeln!(out, "{prefix}{line}\n\\ No newline at end of file"),
+            };
+        }
+    }
+    out
+}
+
+/// Zero based line in each text where the hunk starts
+fn position(before: &[Edit]) -> (usize, usize) {
+    let old = before
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Added(_)))
+        .count();
+    let new = before
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Removed(_)))
+        .count();
+    (old, new)
+}
+
+/// Unified diffs number lines from 1, and an empty range names the line before it
+fn range_start(start: usize, len: usize) -> usize {
+    if len == 0 {
+        start
+    } else {
+        start + 1
+    }
+}
+
+/// The shortest list of edits turning `old` into `new`, using Myers' algorithm
+///
+/// Uses the linear space variant that splits the problem at the middle of an optimal path, so
+/// memory grows with the number of lines rather than lines times differences.
+fn shortest_edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    push_edits(old, new, (0, 0), &mut edits);
+    edits
+}
+
+/// Appends the edits for `old` and `new`, which start at `offset` in the full texts
+fn push_edits(old: &[&str], new: &[&str], offset: (usize, usize), edits: &mut Vec<Edit>) {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let (x, y) = offset;
+    edits.extend((0..prefix).map(|i| Edit::Same(x + i, y + i)));
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let (x, y) = (x + prefix, y + prefix);
+    if old_middle.is_empty() || new_middle.is_empty() {
+        edits.extend((0..old_middle.len()).map(|i| Edit::Removed(x + i)));
+        edits.extend((0..new_middle.len()).map(|i| Edit::Added(y + i)));
+    } else {
+        let (split_x, split_y) = middle(old_middle, new_middle);
+        push_edits(
+            &old_middle[..split_x],
+            &new_middle[..split_y],
+            (x, y),
+            edits,
+        );
+        push_edits(
+            &old_middle[split_x..],
+            &new_middle[split_y..],
+            (x + split_x, y + split_y),
+            edits,
+        );
+    }
+
+    let (x, y) = (x + old_middle.len(), y + new_middle.len());
+    edits.extend((0..suffix).map(|i| Edit::Same(x + i, y + i)));
+}
+
+/// A point on a shortest edit path, found by searching from both ends until the paths meet
+///
+/// Both texts must be non-empty and differ in their first and last lines, so the point is
+/// never at either end and each half is smaller than the whole.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn middle(old: &[&str], new: &[&str]) -> (usize, usize) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m + 1) / 2;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    // Furthest x reached on each diagonal k = x - y, indexed by k + max, -1 when not reached.
+    // The backward search counts x and y from the end of the texts.
+    let mut forward = vec![-1; 2 * max as usize + 2];
+    let mut backward = forward.clone();
+    forward[max as usize + 1] = 0;
+    backward[max as usize + 1] = 0;
+    // Diagonals trimmed from each end of the search once their paths leave the texts
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max {
+        for k in (-d + forward_start..=d - forward_end).step_by(2) {
+            let index = (k + max) as usize;
+            let mut x = if k == -d || (k != d && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index] = x;
+            if x > n {
+                forward_end += 2;
+                continue;
+            }
+            if y > m {
+                forward_start += 2;
+                continue;
+            }
+            let reverse = delta - k + max;
+            if odd
+                && (0..2 * max + 2).contains(&reverse)
+                && backward[reverse as usize] != -1
+                && x >= n - backward[reverse as usize]
+            {
+                return (x as usize, y as usize);
+            }
+        }
+        for k in (-d + backward_start..=d - backward_end).step_by(2) {
+            let index = (k + max) as usize;
+            let mut x = if k == -d || (k != d && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index] = x;
+            if x > n {
+                backward_end += 2;
+                continue;
+            }
+            if y > m {
+                backward_start += 2;
+                continue;
+            }
+            let ahead = delta - k + max;
+            if !odd
+                && (0..2 * max + 2).contains(&ahead)
+                && forward[ahead as usize] != -1
+                && forward[ahead as usize] >= n - x
+            {
+                let x = forward[ahead as usize];
+                return (x as usize, (x - (ahead - max)) as usize);
+            }
+        }
+    }
+    // Only reached when nothing is in common, which the paths would have found sooner
+    (n as usize, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Output;
+
+    fn named(name: &str, code: i32, stdout: &str, stderr: &str) -> NamedOutput {
+        NamedOutput::new(
+            name,
+            Output {
+                status: ExitStatus::from_raw(code << 8),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_same_output_has_empty_diff() {
+        let diff = named("make", 0, "a\nb\n", "").diff(&named("make", 0, "a\nb\n", ""));
+        assert!(diff.is_empty());
+        assert_eq!("", diff.to_string());
+    }
+
+    #[test]
+    fn test_unified_diff_with_context() {
+        let old = (1..=20).map(|i| format!("{i}\n")).collect::<String>();
+        let new = (1..=21)
+            .filter(|i| *i != 15)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                i => format!("{i}\n"),
+            })
+            .collect::<String>();
+
+        let diff = named("seq", 0, &old, "").diff(&named("seq", 1, &new, "warning\n"));
+        assert_eq!(
+            "--- seq
++++ seq
+status: exit status: 0 => exit status: 1
+stdout:
+@@ -1,5 +1,5 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+@@ -12,9 +12,9 @@
+ 12
+ 13
+ 14
+-15
+ 16
+ 17
+ 18
+ 19
+ 20
++21
+stderr:
+@@ -0,0 +1,1 @@
++warning
+",
+            diff.to_string()
+        );
+    }
+
+    #[test]
+    fn test_shortest_edits() {
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let edits = shortest_edits(&old, &new);
+
+        let changes = edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Same(..)))
+            .count();
+        assert_eq!(5, changes);
+
+        let rebuilt = edits
+            .iter()
+            .filter_map(|edit| match *edit {
+                Edit::Same(x, _) => Some(old[x]),
+                Edit::Added(y) => Some(new[y]),
+                Edit::Removed(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(new.to_vec(), rebuilt);
+    }
+
+    #[test]
+    fn test_shortest_edits_match_longest_common_subsequence() {
+        // Small pseudo random texts, checked against the quadratic dynamic programming answer
+        let mut seed = 7_u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as usize
+        };
+        for _ in 0..500 {
+            let old = (0..next() % 9)
+                .map(|_| ["a", "b", "c"][next() % 3])
+                .collect::<Vec<_>>();
+            let new = (0..next() % 9)
+                .map(|_| ["a", "b", "c"][next() % 3])
+                .collect::<Vec<_>>();
+
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for x in (0..old.len()).rev() {
+                for y in (0..new.len()).rev() {
+                    lcs[x][y] = if old[x] == new[y] {
+                        lcs[x + 1][y + 1] + 1
+                    } else {
+                        lcs[x + 1][y].max(lcs[x][y + 1])
+                    };
+                }
+            }
+
+            let edits = shortest_edits(&old, &new);
+            let same = edits
+                .iter()
+                .filter(|edit| matches!(edit, Edit::Same(..)))
+                .count();
+            assert_eq!(lcs[0][0], same, "{old:?} {new:?}");
+            let rebuilt = edits
+                .iter()
+                .filter_map(|edit| match *edit {
+                    Edit::Same(x, y) => {
+                        assert_eq!(old[x], new[y]);
+                        Some(new[y])
+                    }
+                    Edit::Added(y) => Some(new[y]),
+                    Edit::Removed(_) => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(new, rebuilt, "{old:?} {new:?}");
+        }
+    }
+
+    #[test]
+    fn test_line_endings_are_changes() {
+        let diff = named("cat", 0, "a\nb\n", "").diff(&named("cat", 0, "a\nb", ""));
+        assert!(diff.stdout_changed());
+        assert_eq!(
+            "--- cat\n+++ cat\nstdout:\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n",
+            diff.to_string()
+        );
+
+        let diff = named("cat", 0, "a\n", "").diff(&named("cat", 0, "a\r\n", ""));
+        assert!(diff.stdout_changed());
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_utf8_changes_are_reported() {
+        let output = |stdout: &[u8]| {
+            NamedOutput::new(
+                "cat",
+                Output {
+                    status: ExitStatus::from_raw(0),
+                    stdout: stdout.to_vec(),
+                    stderr: Vec::new(),
+                },
+            )
+        };
+        let diff = output(b"caf\xe9\n").diff(&output(b"caf\xff\n"));
+        assert!(diff.stdout_changed());
+        assert!(diff
+            .to_string()
+            .ends_with("stdout:\nBytes that are not valid UTF-8 changed\n"));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+use std::io::Write;
+
+/// An object safe way to run a named command, for storing different commands together
+///
+/// [CommandWithName] has generic methods so it can't be used as `dyn CommandWithName`. This
+/// trait is implemented for every [CommandWithName] and takes writers as trait objects instead.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandSpec, RunnableCommand};
+/// use std::process::Command;
+///
+/// let mut plan: Vec<Box<dyn RunnableCommand>> = vec![
+///     Box::new(Command::new("true")),
+///     Box::new(CommandSpec::new(Command::new("echo")).with_name("say hello")),
+/// ];
+///
+/// let mut stdout = Vec::new();
+/// for command in &mut plan {
+///     let output = command.run_streamed(&mut stdout, &mut std::io::sink()).unwrap();
+///     println!("Ran `{}`", output.name());
+/// }
+/// assert_eq!("say hello", plan[1].display_name());
+/// ```
+pub trait RunnableCommand {
+    /// See [CommandWithName::name]
+    fn display_name(&mut self) -> String;
+
+    /// See [CommandWithName::named_output]
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command.
+    /// Returns `CmdError::NonZeroExitNotStreamed` if the exit code is not zero.
+    fn run(&mut self) -> Result<NamedOutput, CmdError>;
+
+    /// See [CommandWithName::stream_output]
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    fn run_streamed(
+        &mut self,
+        stdout_write: &mut (dyn Write + Send),
+        stderr_write: &mut (dyn Write + Send),
+    ) -> Result<NamedOutput, CmdError>;
+}
+
+impl<T: CommandWithName> RunnableCommand for T {
+    fn display_name(&mut self) -> String {
+        self.name()
+    }
+
+    fn run(&mut self) -> Result<NamedOutput, CmdError> {
+        self.named_output()
+    }
+
+    fn run_streamed(
+        &mut self,
+        stdout_write: &mut (dyn Write + Send),
+        stderr_write: &mut (dyn Write + Send),
+    ) -> Result<NamedOutput, CmdError> {
+        self.stream_output(stdout_write, stderr_write)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_boxed_commands_run_uniformly() {
+        let mut failing = Command::new("bash");
+        failing.args(["-c", "exit 3"]);
+        let mut plan: Vec<Box<dyn RunnableCommand>> =
+            vec![Box::new(Command::new("true")), Box::new(failing)];
+
+        let results = plan
+            .iter_mut()
+            .map(|command| command.run())
+            .collect::<Vec<_>>();
+
+        assert!(results[0].is_ok());
+        assert_eq!(
+            Some(3),
+            results[1].as_ref().unwrap_err().status().code(),
+            "{results:?}"
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use crate::{CmdError, Exchange, NamedOutput, Recorder, Stream};
+use regex::Regex;
+use std::io::{self, Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A script of prompts to wait for and lines to send in response
+///
+/// Pass to [crate::CommandWithName::interact]. Each step waits for output on stdout or stderr
+/// to match the prompt, then writes the response followed by a newline to stdin. Output only
+/// has to match once, prompts do not need to end with a newline. Every exchange is recorded
+/// and available via [NamedOutput::transcript].
+///
+/// Stdin, stdout and stderr are pipes, not a terminal. Tools that refuse to prompt unless
+/// attached to a terminal will not work with this.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Interaction};
+/// use regex::Regex;
+/// use std::process::Command;
+///
+/// let output = Command::new("bash")
+///     .args(["-c", "printf 'Continue? [y/N] ' && read answer && echo \"answer: $answer\""])
+///     .interact(
+///         Interaction::new().expect(Regex::new(r"Continue\? \[y/N\] $").unwrap(), "y"),
+///     )
+///     .unwrap();
+///
+/// assert_eq!("Continue? [y/N] answer: y\n", output.stdout_lossy());
+/// assert_eq!("y", output.transcript()[0].response);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Interaction {
+    steps: Vec<(Regex, String)>,
+    timeout: Duration,
+    /// Secrets to mask in the recorded input, `None` when input is not recorded
+    record_input: Option<Vec<String>>,
+    recorder: Option<Recorder>,
+}
+
+impl Default for Interaction {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            timeout: Duration::from_secs(30),
+            record_input: None,
+            recorder: None,
+        }
+    }
+}
+
+impl Interaction {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for output matching `prompt`, then send `response` followed by a newline
+    #[must_use]
+    pub fn expect(mut self, prompt: Regex, response: impl Into<String>) -> Self {
+        self.steps.push((prompt, response.into()));
+        self
+    }
+
+    /// How long to wait for each prompt, defaults to 30 seconds
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Record output and responses with their timing, see [Recorder]
+    ///
+    /// Responses are masked the same as with [Interaction::record_input_redacting].
+    #[must_use]
+    pub fn recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Keep a copy of the bytes sent to stdin, see [NamedOutput::stdin]
+    ///
+    /// The copy is shown in errors, use [Interaction::record_input_redacting] when a response
+    /// is a password or token.
+    #[must_use]
+    pub fn record_input(self) -> Self {
+        self.record_input_redacting(std::iter::empty::<String>())
+    }
+
+    /// Keep a copy of the bytes sent to stdin with the given secret values masked
+    ///
+    /// The command still receives the real values. See [crate::redact] for how values are
+    /// masked.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, Interaction};
+    /// use regex::Regex;
+    /// use std::process::Command;
+    ///
+    /// let password = "hunter2";
+    /// let error = Command::new("bash")
+    ///     .args(["-c", "printf 'User: ' && read user && printf 'Password: ' && read pass && exit 1"])
+    ///     .interact(
+    ///         Interaction::new()
+    ///             .expect(Regex::new("User: $").unwrap(), "admin")
+    ///             .expect(Regex::new("Password: $").unwrap(), password)
+    ///             .record_input_redacting([password]),
+    ///     )
+    ///     .unwrap_err();
+    ///
+    /// assert!(error.to_string().contains("stdin: admin\n****\n"));
+    /// assert!(!error.to_string().contains(password));
+    /// ```
+    #[must_use]
+    pub fn record_input_redacting<I, S>(mut self, secrets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.record_input = Some(
+            secrets
+                .into_iter()
+                .map(|secret| secret.as_ref().to_string())
+                .collect(),
+        );
+        self
+    }
+}
+
+pub(crate) fn interact(
+    name: String,
+    command: &mut Command,
+    interaction: Interaction,
+) -> Result<NamedOutput, CmdError> {
+    let run_id = crate::next_run_id();
+    let mut child = crate::command::spawn(
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .map_err(|error| CmdError::SystemError(name.clone(), error, Some(run_id)))?;
+    let mut stdin = child.stdin.take();
+    let session = Session {
+        recorder: interaction.recorder.clone(),
+        ..Session::default()
+    };
+
+    let mut transcript = Vec::new();
+    let mut input = Vec::new();
+    let (failure, status) = thread::scope(|scope| {
+        let session = &session;
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|stdout| scope.spawn(move || session.read(stdout, Stream::Stdout))),
+            child
+                .stderr
+                .take()
+                .map(|stderr| scope.spawn(move || session.read(stderr, Stream::Stderr))),
+        ];
+        for _ in readers.iter().filter(|reader| reader.is_none()) {
+            session.close();
+        }
+
+        let mut failure = None;
+        for (prompt, response) in &interaction.steps {
+            let result = session
+                .wait_for(prompt, interaction.timeout)
+                .and_then(|matched| {
+                    stdin
+                        .as_mut()
+                        .map_or(Ok(()), |stdin| writeln!(stdin, "{response}"))
+                        .map(|()| matched)
+                        .map_err(|error| format!("Could not respond to `{prompt}`. {error}"))
+                });
+            match result {
+                Ok(matched) => {
+                    let secrets = interaction.record_input.as_deref().unwrap_or_default();
+                    let sent = format!("{}\n", crate::redact(response, secrets));
+                    if interaction.record_input.is_some() {
+                        input.extend(sent.bytes());
+                    }
+                    if let Some(recorder) = &interaction.recorder {
+                        recorder.input(sent.as_bytes());
+                    }
+                    transcript.push(Exchange {
+                        prompt: matched,
+                        response: response.clone(),
+                    });
+                }
+                Err(reason) => {
+                    let _ = child.kill();
+                    failure = Some(reason);
+                    break;
+                }
+            }
+        }
+        drop(stdin.take());
+
+        let drained = readers
+            .into_iter()
+            .flatten()
+            .try_for_each(|reader| match reader.join() {
+                Ok(result) => result,
+                Err(error) => std::panic::resume_unwind(error),
+            });
+        (failure, drained.and_then(|()| child.wait()))
+    });
+
+    let status =
+        status.map_err(|error| CmdError::SystemError(name.clone(), error, Some(run_id)))?;
+    let buffers = session.into_buffers();
+    let mut named_output = NamedOutput::for_run(
+        name,
+        run_id,
+        Output {
+            status,
+            stdout: buffers.stdout,
+            stderr: buffers.stderr,
+        },
+    );
+    named_output.extras.transcript = transcript;
+    named_output.extras.stdin = interaction.record_input.map(|_| input);
+    match failure {
+        Some(reason) => Err(CmdError::InteractionFailed(named_output, reason)),
+        None => named_output.nonzero_captured(),
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    buffers: Mutex<Buffers>,
+    changed: Condvar,
+    recorder: Option<Recorder>,
+}
+
+#[derive(Default)]
+struct Buffers {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    /// Output from either stream that has not been matched by a prompt yet
+    unmatched: String,
+    /// Trailing bytes of an incomplete UTF-8 character, per stream
+    partial: [Vec<u8>; 2],
+    closed: usize,
+}
+
+impl Session {
+    fn read(&self, mut reader: impl Read, stream: Stream) -> io::Result<()> {
+        let mut buf = [0; 8192];
+        let result = loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(len) => {
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(stream.into(), &buf[..len]);
+                    }
+                    let mut buffers = self.lock();
+                    match stream {
+                        Stream::Stdout => buffers.stdout.extend_from_slice(&buf[..len]),
+                        Stream::Stderr => buffers.stderr.extend_from_slice(&buf[..len]),
+                    }
+                    buffers.push_unmatched(stream, &buf[..len]);
+                    self.changed.notify_all();
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => break Err(error),
+            }
+        };
+        self.close();
+        result
+    }
+
+    fn close(&self) {
+        self.lock().closed += 1;
+        self.changed.notify_all();
+    }
+
+    /// Waits for unmatched output to match `prompt`, consuming output up to the end of the match
+    fn wait_for(&self, prompt: &Regex, timeout: Duration) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        let mut buffers = self.lock();
+        loop {
+            if let Some(found) = prompt.find(&buffers.unmatched) {
+                let matched = found.as_str().to_string();
+                let end = found.end();
+                buffers.unmatched.drain(..end);
+                return Ok(matched);
+            }
+
+            let reason = if buffers.closed >= 2 {
+                format!("Output closed before a prompt matched `{prompt}`")
+            } else if Instant::now() >= deadline {
+                format!("Timed out after {timeout:?} waiting for a prompt to match `{prompt}`")
+            } else {
+                buffers = self
+                    .changed
+                    .wait_timeout(buffers, deadline - Instant::now())
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+                continue;
+            };
+            return Err(match buffers.unmatched.trim() {
+                "" => reason,
+                unexpected => format!("{reason}, unexpected output: {unexpected:?}"),
+            });
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buffers> {
+        self.buffers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn into_buffers(self) -> Buffers {
+        self.buffers
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Buffers {
+    /// Decodes output for prompt matching, holding back a character split across reads
+    fn push_unmatched(&mut self, stream: Stream, bytes: &[u8]) {
+        let index = match stream {
+            Stream::Stdout => 0,
+            Stream::Stderr => 1,
+        };
+        let mut pending = std::mem::take(&mut self.partial[index]);
+        pending.extend_from_slice(bytes);
+
+        let complete = match std::str::from_utf8(&pending) {
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            _ => pending.len(),
+        };
+        self.unmatched
+            .push_str(&String::from_utf8_lossy(&pending[..complete]));
+        self.partial[index] = pending.split_off(complete);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_character_split_across_reads() {
+        let mut buffers = Buffers::default();
+        let snowman = "☃".as_bytes();
+
+        buffers.push_unmatched(Stream::Stdout, &snowman[..1]);
+        buffers.push_unmatched(Stream::Stderr, b"ok ");
+        buffers.push_unmatched(Stream::Stdout, &snowman[1..]);
+
+        assert_eq!("ok ☃", buffers.unmatched);
+    }
+
+    #[test]
+    fn test_timeout_reports_unexpected_prompt() {
+        let error = interact(
+            "installer".to_string(),
+            Command::new("bash").args(["-c", "printf 'Overwrite? ' && sleep 100"]),
+            Interaction::new()
+                .expect(Regex::new("Continue\\?").unwrap(), "y")
+                .timeout(Duration::from_millis(200)),
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, CmdError::InteractionFailed(_, _)));
+        assert!(
+            error.to_string().contains(
+                "Timed out after 200ms waiting for a prompt to match `Continue\\?`, unexpected output: \"Overwrite?\""
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_transcript_records_each_exchange() {
+        let output = interact(
+            "wizard".to_string(),
+            Command::new("bash").args([
+                "-c",
+                "printf 'name: ' && read name && printf 'color: ' && read color && echo \"$name likes $color\"",
+            ]),
+            Interaction::new()
+                .expect(Regex::new("name: $").unwrap(), "Richard")
+                .expect(Regex::new("color: $").unwrap(), "red"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Exchange {
+                    prompt: "name: ".to_string(),
+                    response: "Richard".to_string()
+                },
+                Exchange {
+                    prompt: "color: ".to_string(),
+                    response: "red".to_string()
+                }
+            ],
+            output.transcript()
+        );
+        assert_eq!("name: color: Richard likes red\n", output.stdout_lossy());
+    }
+}
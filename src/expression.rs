@@ -0,0 +1,79 @@
+use crate::{CmdError, CommandSpec, NamedOutput};
+
+/// Run a [duct::Expression] with a name so failures become a [CmdError]
+///
+/// Pipelines and redirections keep working as usual. Output that is not redirected elsewhere
+/// is captured and shown in the error.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::ExpressionWithName;
+///
+/// let expression = duct::cmd!("echo", "hello").pipe(duct::cmd!("false"));
+///
+/// let error = expression.named_output("echo hello | false").unwrap_err();
+/// assert_eq!("echo hello | false", error.name());
+/// ```
+pub trait ExpressionWithName {
+    /// Runs the expression capturing stdout and stderr
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the expression.
+    /// Returns `CmdError::NonZeroExitNotStreamed` if the exit code is not zero.
+    fn named_output(&self, name: impl AsRef<str>) -> Result<NamedOutput, CmdError>;
+}
+
+impl ExpressionWithName for duct::Expression {
+    fn named_output(&self, name: impl AsRef<str>) -> Result<NamedOutput, CmdError> {
+        let name = name.as_ref().to_string();
+        self.stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, None))
+            .map(|output| NamedOutput::new(name, output))
+            .and_then(NamedOutput::nonzero_captured)
+    }
+}
+
+/// Converts the program, arguments, env changes and working directory into an expression
+impl From<&CommandSpec> for duct::Expression {
+    fn from(spec: &CommandSpec) -> Self {
+        let command = spec.command();
+        let mut expression = duct::cmd(command.get_program(), command.get_args());
+        for (key, value) in command.get_envs() {
+            expression = match value {
+                Some(value) => expression.env(key, value),
+                None => expression.env_remove(key),
+            };
+        }
+        if let Some(dir) = command.get_current_dir() {
+            expression = expression.dir(dir);
+        }
+        expression
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::process::Command;
+
+    #[test]
+    fn test_spec_into_expression() {
+        let mut command = Command::new("bash");
+        command
+            .args(["-c", "echo $GREETING from $PWD"])
+            .env("GREETING", "hello")
+            .current_dir("/");
+        let mut spec = CommandSpec::new(command);
+
+        let output = duct::Expression::from(&spec)
+            .named_output(spec.name())
+            .unwrap();
+        assert_eq!("hello from /\n", output.stdout_lossy());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{CommandWithName, FunRunner, NamedCommand, Quoting};
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory of fake executables to put in front of the `PATH` in tests
+///
+/// Simulate tools such as `docker` being present, failing or missing without installing
+/// them. Each fake is a shell script that records how it was called, see
+/// [FakeExecutable::calls]. Commands see the fakes through [FakeBin::apply] or every command
+/// run by [FakeBin::runner]. The directory is removed when the `FakeBin` is dropped.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, FakeBin};
+/// use std::process::Command;
+///
+/// let mut bin = FakeBin::new().unwrap();
+/// let docker = bin.fail("docker", 1, "Cannot connect to the Docker daemon").unwrap();
+/// bin.hide("podman");
+///
+/// let runner = bin.runner();
+/// let error = runner
+///     .run(&mut Command::new("docker").arg("ps"), |cmd| cmd.named_output())
+///     .unwrap_err();
+/// assert!(error.to_string().contains("Cannot connect to the Docker daemon"));
+/// assert_eq!(vec![vec!["ps".to_string()]], docker.calls());
+///
+/// let error = runner
+///     .run(&mut Command::new("podman"), |cmd| cmd.named_output())
+///     .unwrap_err();
+/// assert!(error.to_string().starts_with("Could not run command `podman`"));
+/// ```
+#[derive(Debug)]
+pub struct FakeBin {
+    dir: PathBuf,
+    hidden: Vec<OsString>,
+}
+
+impl FakeBin {
+    /// Create an empty directory for fakes under the system temp dir
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn new() -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "fun_run_fake_bin_{}_{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            hidden: Vec::new(),
+        })
+    }
+
+    /// The directory holding the fakes
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write a fake that runs `body` as a `sh` script, with arguments in `"$@"`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script cannot be written.
+    pub fn script(&self, name: &str, body: &str) -> io::Result<FakeExecutable> {
+        let path = self.dir.join(name);
+        let calls = self.dir.join(format!(".{name}.calls"));
+        let script = format!(
+            "#!/bin/sh\n{{ for arg in \"$@\"; do printf '%s\\0' \"$arg\"; done; printf '\\n'; }} >> {calls}\n{body}\n",
+            calls = Quoting::Posix.quote(calls.as_os_str())
+        );
+        std::fs::write(&path, script)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(FakeExecutable { path, calls })
+    }
+
+    /// Write a fake that prints `stdout` and exits successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script cannot be written.
+    pub fn succeed(&self, name: &str, stdout: &str) -> io::Result<FakeExecutable> {
+        self.script(
+            name,
+            &format!("printf '%s' {}", Quoting::Posix.quote(stdout.as_ref())),
+        )
+    }
+
+    /// Write a fake that prints `stderr` and exits with `code`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script cannot be written.
+    pub fn fail(&self, name: &str, code: i32, stderr: &str) -> io::Result<FakeExecutable> {
+        self.script(
+            name,
+            &format!(
+                "printf '%s' {} >&2\nexit {code}",
+                Quoting::Posix.quote(stderr.as_ref())
+            ),
+        )
+    }
+
+    /// Make a real executable appear to be missing
+    ///
+    /// Leaves out every directory of the `PATH` that contains an executable with this name.
+    /// Other executables in those directories disappear too, so prefer hiding tools that
+    /// live in their own directory or write fakes for anything else you need from there.
+    pub fn hide(&mut self, name: impl Into<OsString>) {
+        self.hidden.push(name.into());
+    }
+
+    /// The fakes directory followed by the parent's `PATH`, without hidden executables
+    #[must_use]
+    pub fn path_env(&self) -> OsString {
+        let parent = std::env::var_os("PATH").unwrap_or_default();
+        let dirs =
+            std::iter::once(self.dir.clone()).chain(std::env::split_paths(&parent).filter(|dir| {
+                !self
+                    .hidden
+                    .iter()
+                    .any(|name| crate::command::is_executable(&dir.join(name)))
+            }));
+        std::env::join_paths(dirs).unwrap_or(parent)
+    }
+
+    /// Set the command's `PATH` to [FakeBin::path_env], leaving its name unchanged
+    pub fn apply<'a>(&self, command: &'a mut Command) -> NamedCommand<'a> {
+        let name = command.name();
+        command.env("PATH", self.path_env());
+        command.named(name)
+    }
+
+    /// A runner that sets the `PATH` of every command to [FakeBin::path_env]
+    ///
+    /// Keep the `FakeBin` alive for as long as the runner is used.
+    #[must_use]
+    pub fn runner(&self) -> FunRunner {
+        FunRunner::new().env("PATH", self.path_env())
+    }
+}
+
+impl Drop for FakeBin {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A fake written by [FakeBin]
+#[derive(Debug, Clone)]
+pub struct FakeExecutable {
+    path: PathBuf,
+    calls: PathBuf,
+}
+
+impl FakeExecutable {
+    /// Where the fake was written
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The arguments of every call so far, oldest first
+    #[must_use]
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        let contents = std::fs::read(&self.calls).unwrap_or_default();
+        let mut calls = Vec::new();
+        let mut args = Vec::new();
+        let mut rest = contents.as_slice();
+        while let Some((&first, tail)) = rest.split_first() {
+            if first == b'\n' {
+                calls.push(std::mem::take(&mut args));
+                rest = tail;
+            } else {
+                let end = rest
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .unwrap_or(rest.len());
+                args.push(String::from_utf8_lossy(&rest[..end]).to_string());
+                rest = rest.get(end + 1..).unwrap_or_default();
+            }
+        }
+        calls
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fake_records_calls_and_output() {
+        let bin = FakeBin::new().unwrap();
+        let git = bin.succeed("git", "main\n").unwrap();
+
+        let output = bin
+            .apply(
+                Command::new("git")
+                    .args(["rev-parse", "--abbrev-ref", "HEAD"])
+                    .arg("two words\nand a line"),
+            )
+            .named_output()
+            .unwrap();
+        assert_eq!("main\n", output.stdout_lossy());
+        assert!(output.name().starts_with("git rev-parse"));
+
+        bin.apply(&mut Command::new("git")).named_output().unwrap();
+        assert_eq!(
+            vec![
+                vec![
+                    "rev-parse".to_string(),
+                    "--abbrev-ref".to_string(),
+                    "HEAD".to_string(),
+                    "two words\nand a line".to_string()
+                ],
+                Vec::new()
+            ],
+            git.calls()
+        );
+
+        let dir = bin.dir().to_path_buf();
+        drop(bin);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_hide_removes_directories_from_path() {
+        let mut bin = FakeBin::new().unwrap();
+        bin.hide("sh");
+        let path = bin.path_env();
+        assert!(std::env::split_paths(&path).all(|dir| !dir.join("sh").exists()));
+        assert_eq!(
+            Some(bin.dir().to_path_buf()),
+            std::env::split_paths(&path).next()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::process::Command;
+
+/// Rejects commands before they are spawned, for commands built from user config
+///
+/// Denied programs are matched by their exact path or by file name, so denying `rm` also
+/// denies `/bin/rm`. Once a program is allowed with [CommandGuard::allow_program] every program
+/// not allowed is rejected. Allowing `git` only allows `git` looked up on the `PATH`, a program
+/// with a path such as `/tmp/git` has to be allowed by that exact path. Denials take precedence over the allowlist. Rejected commands return
+/// `CmdError::Rejected` with the reason.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CmdError, CommandGuard, CommandWithName, FunRunner};
+/// use regex::Regex;
+/// use std::process::Command;
+///
+/// let runner = FunRunner::new().guard(
+///     CommandGuard::new()
+///         .allow_program("git")
+///         .allow_program("echo")
+///         .deny_arg(Regex::new("^--force").unwrap()),
+/// );
+///
+/// let error = runner
+///     .run(Command::new("curl").arg("example.com"), |cmd| cmd.named_output())
+///     .unwrap_err();
+/// assert!(matches!(error, CmdError::Rejected(..)));
+/// assert_eq!(
+///     "Not running command `curl example.com`. Rejected, `curl` is not an allowed program",
+///     error.to_string()
+/// );
+///
+/// let error = runner
+///     .run(Command::new("git").args(["push", "--force"]), |cmd| cmd.named_output())
+///     .unwrap_err();
+/// assert!(error.to_string().ends_with("argument `--force` matches denied pattern `^--force`"));
+///
+/// assert!(runner.run(Command::new("echo").arg("hi"), |cmd| cmd.named_output()).is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CommandGuard {
+    allowed: Vec<OsString>,
+    denied: Vec<OsString>,
+    #[cfg(feature = "regex")]
+    denied_args: Vec<Regex>,
+}
+
+impl CommandGuard {
+    /// Every command is allowed until programs or arguments are added
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow this program, rejecting every program that is not allowed
+    ///
+    /// A file name such as `git` does not allow a path ending in it, such as `./git`.
+    #[must_use]
+    pub fn allow_program(mut self, program: impl Into<OsString>) -> Self {
+        self.allowed.push(program.into());
+        self
+    }
+
+    /// Reject this program, even if it is allowed
+    #[must_use]
+    pub fn deny_program(mut self, program: impl Into<OsString>) -> Self {
+        self.denied.push(program.into());
+        self
+    }
+
+    /// Reject commands with any argument matching the pattern
+    ///
+    /// Arguments that are not UTF-8 are matched lossily.
+    #[must_use]
+    #[cfg(feature = "regex")]
+    pub fn deny_arg(mut self, pattern: Regex) -> Self {
+        self.denied_args.push(pattern);
+        self
+    }
+
+    /// Returns the reason the command is rejected, if it is
+    #[must_use]
+    pub fn check(&self, command: &Command) -> Option<String> {
+        let program = command.get_program();
+        let display = program.to_string_lossy();
+        if self
+            .denied
+            .iter()
+            .any(|denied| matches_file_name(program, denied))
+        {
+            return Some(format!("`{display}` is a denied program"));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|allowed| program == allowed) {
+            return Some(format!("`{display}` is not an allowed program"));
+        }
+        self.denied_arg(command)
+    }
+
+    #[cfg(feature = "regex")]
+    fn denied_arg(&self, command: &Command) -> Option<String> {
+        command.get_args().find_map(|arg| {
+            let arg = arg.to_string_lossy();
+            self.denied_args
+                .iter()
+                .find(|pattern| pattern.is_match(&arg))
+                .map(|pattern| format!("argument `{arg}` matches denied pattern `{pattern}`"))
+        })
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[allow(clippy::unused_self)]
+    fn denied_arg(&self, _command: &Command) -> Option<String> {
+        None
+    }
+
+    /// Run the command with `f` unless it is rejected
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::Rejected` if the command is rejected, otherwise any error returned
+    /// by `f`.
+    pub fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        self.guard(command)?;
+        f(command)
+    }
+
+    /// Returns an error if the command is rejected
+    pub(crate) fn guard<C: CommandWithName>(&self, command: &mut C) -> Result<(), CmdError> {
+        match self.check(command.mut_cmd()) {
+            Some(reason) => Err(CmdError::Rejected(command.name(), reason)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns true if `program` is `pattern` or has it as its file name
+fn matches_file_name(program: &OsStr, pattern: &OsStr) -> bool {
+    program == pattern || Path::new(program).file_name() == Some(pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_denials_take_precedence() {
+        let guard = CommandGuard::new()
+            .allow_program("rm")
+            .allow_program("ls")
+            .deny_program("/bin/rm");
+
+        assert_eq!(
+            Some("`/bin/rm` is a denied program".to_string()),
+            guard.check(&Command::new("/bin/rm"))
+        );
+        assert_eq!(None, guard.check(&Command::new("rm")));
+        assert_eq!(None, guard.check(&Command::new("ls")));
+
+        let guard = CommandGuard::new().deny_program("rm");
+        assert!(guard.check(&Command::new("/bin/rm")).is_some());
+        assert_eq!(None, guard.check(&Command::new("ls")));
+    }
+
+    #[test]
+    fn test_allowed_file_name_does_not_allow_other_paths() {
+        let guard = CommandGuard::new()
+            .allow_program("git")
+            .allow_program("/usr/bin/ls");
+
+        assert_eq!(None, guard.check(&Command::new("git")));
+        assert_eq!(
+            Some("`/tmp/evil/git` is not an allowed program".to_string()),
+            guard.check(&Command::new("/tmp/evil/git"))
+        );
+        assert!(guard.check(&Command::new("./git")).is_some());
+        assert_eq!(None, guard.check(&Command::new("/usr/bin/ls")));
+        assert!(guard.check(&Command::new("ls")).is_some());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{CommandWithName, NamedCommand};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::process::Command;
+
+/// Runs a command with a cleared environment and only the variables you list
+///
+/// Starts from a minimal `PATH`, `HOME` copied from the parent (or `/`) and `LANG=C.UTF-8`.
+/// Use [HermeticEnv::allow] to copy variables from the parent and [HermeticEnv::env] to set
+/// them. Applying it names the command after the `env -i` invocation that reproduces it.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, HermeticEnv};
+/// use std::process::Command;
+///
+/// let mut command = Command::new("printenv");
+/// let mut cmd = HermeticEnv::new()
+///     .env("RAILS_ENV", "production")
+///     .apply(&mut command);
+///
+/// assert!(cmd.name().starts_with("env -i HOME="));
+/// assert!(cmd.name().ends_with("LANG=C.UTF-8 PATH=/usr/local/bin:/usr/bin:/bin RAILS_ENV=production printenv"));
+///
+/// let output = cmd.named_output().unwrap();
+/// assert!(output.stdout_lossy().contains("RAILS_ENV=production\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HermeticEnv {
+    env: BTreeMap<OsString, OsString>,
+}
+
+impl Default for HermeticEnv {
+    fn default() -> Self {
+        let home = std::env::var_os("HOME").unwrap_or_else(|| OsString::from("/"));
+        Self {
+            env: BTreeMap::from([
+                (OsString::from("PATH"), OsString::from(DEFAULT_PATH)),
+                (OsString::from("HOME"), home),
+                (OsString::from("LANG"), OsString::from("C.UTF-8")),
+            ]),
+        }
+    }
+}
+
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+impl HermeticEnv {
+    /// The default minimal environment
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from nothing, not even the defaults
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// Copy a variable from the parent environment if it is set
+    #[must_use]
+    pub fn allow(mut self, key: impl Into<OsString>) -> Self {
+        let key = key.into();
+        if let Some(value) = std::env::var_os(&key) {
+            self.env.insert(key, value);
+        }
+        self
+    }
+
+    /// Set a variable
+    #[must_use]
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Remove a variable, including a default
+    #[must_use]
+    pub fn remove(mut self, key: impl Into<OsString>) -> Self {
+        self.env.remove(&key.into());
+        self
+    }
+
+    /// The variables the command will see
+    #[must_use]
+    pub fn vars(&self) -> &BTreeMap<OsString, OsString> {
+        &self.env
+    }
+
+    /// Clear the command's environment, set the variables and name it
+    pub fn apply<'a>(&self, command: &'a mut Command) -> NamedCommand<'a> {
+        command.env_clear().envs(&self.env);
+        let name = format!(
+            "env -i {}",
+            crate::display_with_env_keys(command, &self.env, self.env.keys())
+        );
+        command.named(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parent_env_is_not_inherited() {
+        let mut command = Command::new("printenv");
+        let output = HermeticEnv::empty()
+            .env("PATH", DEFAULT_PATH)
+            .allow("FUN_RUN_NOT_SET_ANYWHERE")
+            .apply(&mut command)
+            .named_output()
+            .unwrap();
+
+        assert_eq!(format!("PATH={DEFAULT_PATH}\n"), output.stdout_lossy());
+        assert_eq!(
+            format!("env -i PATH={DEFAULT_PATH} printenv"),
+            output.name()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{StepReport, StepResult};
+use std::io::Write;
+use std::time::Duration;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+details{border:1px solid #ddd;border-radius:4px;margin:.5em 0;padding:.5em}\
+summary{cursor:pointer;display:flex;gap:1em;align-items:center}\
+.status{font-weight:bold;width:7em}\
+.succeeded{color:#1a7f37}.failed{color:#cf222e}.skipped{color:#6e7781}\
+.name{font-family:monospace;flex:1}\
+.timeline{position:relative;width:30%;height:.8em;background:#f0f0f0}\
+.bar{position:absolute;height:100%;background:#0969da}\
+pre{background:#f6f8fa;padding:.5em;overflow-x:auto}";
+
+/// Write the steps of a batch as a standalone HTML page
+///
+/// Each step is a collapsible section with its status, duration and a bar showing when it ran
+/// relative to the whole batch. Failed steps start expanded.
+pub(crate) fn write_html_report(
+    steps: &[StepReport],
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    let total = steps
+        .iter()
+        .map(|step| step.start + step.duration)
+        .max()
+        .unwrap_or_default();
+    let count = |status| {
+        steps
+            .iter()
+            .filter(|step| status_of(&step.result) == status)
+            .count()
+    };
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>Batch report</title><style>{STYLE}</style>")?;
+    writeln!(writer, "</head><body>")?;
+    writeln!(
+        writer,
+        "<h1>Batch report</h1><p>{} succeeded, {} failed, {} skipped in {}</p>",
+        count("succeeded"),
+        count("failed"),
+        count("skipped"),
+        seconds(total)
+    )?;
+
+    for step in steps {
+        let status = status_of(&step.result);
+        let percent = |duration: Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                duration.as_secs_f64() / total.as_secs_f64() * 100.0
+            }
+        };
+        writeln!(
+            writer,
+            "<details{open}><summary><span class=\"status {status}\">{status}</span>\
+             <span class=\"name\">{name}</span><span>{duration}</span>\
+             <span class=\"timeline\"><span class=\"bar\" style=\"left:{left:.2}%;width:{width:.2}%\"></span></span>\
+             </summary>",
+            open = if status == "failed" { " open" } else { "" },
+            name = escape(&step.name),
+            duration = seconds(step.duration),
+            left = percent(step.start),
+            width = percent(step.duration),
+        )?;
+        match &step.result {
+            StepResult::Succeeded(output) => {
+                write_pre(&mut writer, "stdout", &output.stdout_lossy())?;
+                write_pre(&mut writer, "stderr", &output.stderr_lossy())?;
+            }
+            StepResult::Failed(error) => write_pre(&mut writer, "error", &error.to_string())?,
+            StepResult::Skipped(reason) => writeln!(writer, "<p>{}</p>", escape(reason))?,
+            StepResult::DependencyFailed(failed) => writeln!(
+                writer,
+                "<p>Not run because <code>{}</code> failed</p>",
+                escape(failed)
+            )?,
+        }
+        writeln!(writer, "</details>")?;
+    }
+
+    writeln!(writer, "</body></html>")?;
+    writer.flush()
+}
+
+fn status_of(result: &StepResult) -> &'static str {
+    match result {
+        StepResult::Succeeded(_) => "succeeded",
+        StepResult::Failed(_) => "failed",
+        StepResult::Skipped(_) | StepResult::DependencyFailed(_) => "skipped",
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn write_pre(writer: &mut impl Write, label: &str, contents: &str) -> std::io::Result<()> {
+    if contents.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "<p>{label}:</p><pre>{}</pre>", escape(contents))
+}
+
+/// Escape text for use in HTML and XML elements and attribute values
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            "echo &quot;&lt;b&gt;&quot; &amp;&amp; echo &#39;hi&#39;",
+            escape(r#"echo "<b>" && echo 'hi'"#)
+        );
+    }
+
+    #[test]
+    fn test_timing_bars_are_relative_to_the_batch() {
+        let steps = [
+            StepReport {
+                name: "compile".to_string(),
+                run_id: None,
+                result: StepResult::Skipped("Not needed".to_string()),
+                start: Duration::from_secs(1),
+                duration: Duration::from_secs(1),
+            },
+            StepReport {
+                name: "<test>".to_string(),
+                run_id: None,
+                result: StepResult::DependencyFailed("compile".to_string()),
+                start: Duration::from_secs(2),
+                duration: Duration::from_secs(2),
+            },
+        ];
+
+        let mut out = Vec::new();
+        write_html_report(&steps, &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains("0 succeeded, 0 failed, 2 skipped in 4.00s"));
+        assert!(html.contains("left:25.00%;width:25.00%"));
+        assert!(html.contains("left:50.00%;width:50.00%"));
+        assert!(html.contains("&lt;test&gt;"));
+        assert!(!html.contains(" open>"));
+    }
+}
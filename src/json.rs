@@ -0,0 +1,235 @@
+use crate::{CmdError, NamedOutput};
+use serde::de::DeserializeOwned;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+
+/// Number of characters shown on either side of a JSON parse error
+const SNIPPET_RADIUS: usize = 40;
+
+impl NamedOutput {
+    /// Deserialize stdout as JSON
+    ///
+    /// On failure the error names the command and shows the part of stdout where
+    /// parsing failed rather than the entire output.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// #[derive(Debug, serde::Deserialize)]
+    /// struct Package {
+    ///     name: String,
+    /// }
+    ///
+    /// let package: Package = Command::new("echo")
+    ///     .arg(r#"{"name": "fun_run"}"#)
+    ///     .named_output()
+    ///     .and_then(|output| output.stdout_json())
+    ///     .unwrap();
+    /// assert_eq!("fun_run", package.name);
+    ///
+    /// let error = Command::new("echo")
+    ///     .arg(r#"{"name": ?}"#)
+    ///     .named_output()
+    ///     .and_then(|output| output.stdout_json::<Package>())
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     r#"Could not parse stdout of command `echo "{\"name\": ?}"`. expected value at line 1 column 10
+    /// stdout: {"name": ?}
+    ///                  ^"#,
+    ///     error.to_string()
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::ParseError` if stdout cannot be deserialized into `T`
+    pub fn stdout_json<T: DeserializeOwned>(&self) -> Result<T, CmdError> {
+        serde_json::from_slice(&self.output.stdout)
+            .map_err(|error| CmdError::ParseError(self.clone(), Box::new(error)))
+    }
+}
+
+/// Decodes newline delimited JSON (NDJSON) as it's written
+///
+/// Each complete, non-blank line is deserialized into `T` and handed to the callback as soon as
+/// it arrives. Lines that fail to deserialize are passed to the callback as an error rather than
+/// stopping the stream, so the command keeps running and its output is still fully captured.
+///
+/// Use with [crate::CommandWithName::stream_json_lines] or as a writer for
+/// [crate::CommandWithName::stream_output].
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, JsonLines};
+/// use std::process::Command;
+///
+/// #[derive(serde::Deserialize)]
+/// struct Event {
+///     step: u32,
+/// }
+///
+/// let mut steps = Vec::new();
+/// let output = Command::new("bash")
+///     .args(["-c", r#"echo '{"step": 1}' && echo '{"step": 2}'"#])
+///     .stream_output(
+///         JsonLines::new(|event: Result<Event, _>| steps.push(event.unwrap().step)),
+///         std::io::stderr(),
+///     )
+///     .unwrap();
+///
+/// assert_eq!(vec![1, 2], steps);
+/// assert_eq!(2, output.stdout_lossy().lines().count());
+/// ```
+pub struct JsonLines<T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(Result<T, serde_json::Error>),
+{
+    on_event: F,
+    buffer: Vec<u8>,
+    event: PhantomData<fn() -> T>,
+}
+
+impl<T, F> JsonLines<T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(Result<T, serde_json::Error>),
+{
+    /// Create a writer that calls `on_event` for every line written to it
+    pub fn new(on_event: F) -> Self {
+        Self {
+            on_event,
+            buffer: Vec::new(),
+            event: PhantomData,
+        }
+    }
+
+    fn decode(&mut self, line: &[u8]) {
+        if !line.trim_ascii().is_empty() {
+            (self.on_event)(serde_json::from_slice(line));
+        }
+    }
+}
+
+impl<T, F> Write for JsonLines<T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(Result<T, serde_json::Error>),
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(index) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line = self.buffer.drain(..=index).collect::<Vec<u8>>();
+            self.decode(&line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T, F> Drop for JsonLines<T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(Result<T, serde_json::Error>),
+{
+    fn drop(&mut self) {
+        let line = std::mem::take(&mut self.buffer);
+        self.decode(&line);
+    }
+}
+
+/// Renders the line where JSON parsing failed with a caret under the failing column
+///
+/// Long lines (such as minified JSON) are trimmed to the characters around the error.
+pub(crate) fn snippet(contents: &str, error: &serde_json::Error) -> String {
+    let line = contents
+        .lines()
+        .nth(error.line().saturating_sub(1))
+        .unwrap_or_default();
+    // serde_json counts columns in bytes, the caret goes under the character containing it
+    let mut byte = error.column().saturating_sub(1).min(line.len());
+    while !line.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    let column = line[..byte].chars().count();
+    let line = line.chars().collect::<Vec<char>>();
+
+    let start = column.saturating_sub(SNIPPET_RADIUS);
+    let end = line.len().min(column + SNIPPET_RADIUS);
+    let prefix = if start > 0 { "..." } else { "" };
+    let suffix = if end < line.len() { "..." } else { "" };
+    let window = line[start..end].iter().collect::<String>();
+
+    let indent = " ".repeat("stdout: ".len() + prefix.len() + column - start);
+    format!("{prefix}{window}{suffix}\n{indent}^")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_str_eq;
+
+    #[test]
+    fn test_json_lines_across_writes() {
+        let mut events = Vec::new();
+        let mut errors = 0;
+        let mut writer = JsonLines::new(|event: Result<u32, _>| match event {
+            Ok(value) => events.push(value),
+            Err(_) => errors += 1,
+        });
+
+        writer.write_all(b"1\n2").unwrap();
+        writer.write_all(b"2\n\noops\n3").unwrap();
+        drop(writer);
+
+        assert_eq!(vec![1, 22, 3], events);
+        assert_eq!(1, errors);
+    }
+
+    #[test]
+    fn test_snippet_trims_long_lines() {
+        let contents = format!("[{}oops]", "1, ".repeat(30));
+        let error = serde_json::from_str::<Vec<u32>>(&contents).unwrap_err();
+
+        assert_str_eq!(
+            format!(
+                "... {}oops]\n{}^",
+                "1, ".repeat(13),
+                " ".repeat("stdout: ... ".len() + "1, ".len() * 13)
+            ),
+            snippet(&contents, &error)
+        );
+    }
+
+    #[test]
+    fn test_snippet_on_later_line() {
+        let contents = "{\n  \"a\": 1,\n  \"b\": ?\n}";
+        let error = serde_json::from_str::<serde_json::Value>(contents).unwrap_err();
+
+        assert_str_eq!(
+            format!("  \"b\": ?\n{}^", " ".repeat("stdout:   \"b\": ".len())),
+            snippet(contents, &error)
+        );
+    }
+
+    #[test]
+    fn test_snippet_caret_after_non_ascii() {
+        let contents = r#"{"café": "☃☃", ?}"#;
+        let error = serde_json::from_str::<serde_json::Value>(contents).unwrap_err();
+
+        assert_str_eq!(
+            format!(
+                "{contents}\n{}^",
+                " ".repeat("stdout: ".len() + r#"{"café": "☃☃", "#.chars().count())
+            ),
+            snippet(contents, &error)
+        );
+    }
+}
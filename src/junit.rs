@@ -0,0 +1,150 @@
+use crate::{StepReport, StepResult};
+use std::io::Write;
+use std::time::Duration;
+
+/// Write the steps of a batch as JUnit XML, one test case per step
+///
+/// Failed steps carry the error as a `<failure>`, steps that did not run are `<skipped>`.
+pub(crate) fn write_junit_xml(
+    suite: &str,
+    steps: &[StepReport],
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    let failures = steps
+        .iter()
+        .filter(|step| matches!(step.result, StepResult::Failed(_)))
+        .count();
+    let skipped = steps
+        .iter()
+        .filter(|step| {
+            matches!(
+                step.result,
+                StepResult::Skipped(_) | StepResult::DependencyFailed(_)
+            )
+        })
+        .count();
+    let time = seconds(
+        steps
+            .iter()
+            .map(|step| step.start + step.duration)
+            .max()
+            .unwrap_or_default(),
+    );
+    let counts = format!(
+        "tests=\"{}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{time}\"",
+        steps.len()
+    );
+    let suite = escape(suite);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<testsuites name="{suite}" {counts}>"#)?;
+    writeln!(writer, r#"  <testsuite name="{suite}" {counts}>"#)?;
+    for step in steps {
+        write!(
+            writer,
+            r#"    <testcase name="{name}" classname="{suite}" time="{time}""#,
+            name = escape(&step.name),
+            time = seconds(step.duration),
+        )?;
+        match &step.result {
+            StepResult::Succeeded(output) => {
+                let stdout = output.stdout_lossy();
+                let stderr = output.stderr_lossy();
+                if stdout.is_empty() && stderr.is_empty() {
+                    writeln!(writer, "/>")?;
+                    continue;
+                }
+                writeln!(writer, ">")?;
+                if !stdout.is_empty() {
+                    writeln!(writer, "      <system-out>{}</system-out>", escape(&stdout))?;
+                }
+                if !stderr.is_empty() {
+                    writeln!(writer, "      <system-err>{}</system-err>", escape(&stderr))?;
+                }
+            }
+            StepResult::Failed(error) => {
+                let error = error.to_string();
+                writeln!(writer, ">")?;
+                writeln!(
+                    writer,
+                    r#"      <failure message="{message}">{error}</failure>"#,
+                    message = escape(error.lines().next().unwrap_or_default()),
+                    error = escape(&error),
+                )?;
+            }
+            StepResult::Skipped(reason) => {
+                writeln!(writer, ">")?;
+                writeln!(writer, r#"      <skipped message="{}"/>"#, escape(reason))?;
+            }
+            StepResult::DependencyFailed(failed) => {
+                writeln!(writer, ">")?;
+                writeln!(
+                    writer,
+                    r#"      <skipped message="Not run because `{}` failed"/>"#,
+                    escape(failed)
+                )?;
+            }
+        }
+        writeln!(writer, "    </testcase>")?;
+    }
+    writeln!(writer, "  </testsuite>")?;
+    writeln!(writer, "</testsuites>")?;
+    writer.flush()
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+/// Escape text for XML, replacing control characters that XML 1.0 does not allow
+fn escape(text: &str) -> String {
+    crate::html::escape(text)
+        .chars()
+        .map(|c| match c {
+            '\t' | '\n' | '\r' => c,
+            c if c < ' ' => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_control_characters() {
+        assert_eq!(
+            "\u{1b}[31m&lt;red&gt;".replace('\u{1b}', "\u{fffd}"),
+            escape("\u{1b}[31m<red>")
+        );
+        assert_eq!("tab\tline\n", escape("tab\tline\n"));
+    }
+
+    #[test]
+    fn test_skipped_steps() {
+        let steps = [StepReport {
+            name: "deploy".to_string(),
+            run_id: None,
+            result: StepResult::DependencyFailed("build & test".to_string()),
+            start: Duration::from_millis(1500),
+            duration: Duration::ZERO,
+        }];
+
+        let mut out = Vec::new();
+        write_junit_xml("nightly", &steps, &mut out).unwrap();
+
+        assert_eq!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="nightly" tests="1" failures="0" errors="0" skipped="1" time="1.500">
+  <testsuite name="nightly" tests="1" failures="0" errors="0" skipped="1" time="1.500">
+    <testcase name="deploy" classname="nightly" time="0.000">
+      <skipped message="Not run because `build &amp; test` failed"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#,
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -1,19 +1,137 @@
 #![doc = include_str!("../README.md")]
 
-use command::output_and_write_streams;
+#[cfg(feature = "regex")]
+use abort::AbortState;
+use child::Readiness;
+#[cfg(feature = "regex")]
+use child::Ready;
+use command::output_and_write_streams_on_spawn;
+use progress::ProgressTracker;
+#[cfg(feature = "regex")]
 use regex::Regex;
-use std::ffi::OsString;
+use signals::SignalForwarding;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
 use std::io::Write;
-use std::os::unix::process::ExitStatusExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::Command;
 use std::process::ExitStatus;
 use std::process::Output;
-use std::sync::LazyLock;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 #[cfg(feature = "which_problem")]
 use which_problem::Which;
 
+#[cfg(feature = "regex")]
+mod abort;
+pub mod assert;
+mod backend;
+mod batch;
+mod breaker;
+#[cfg(feature = "regex")]
+mod capture;
+mod chaos;
+mod child;
+mod chroot;
 mod command;
+#[cfg(feature = "flate2")]
+mod compressed;
+mod confirm;
+#[cfg(feature = "container")]
+mod container;
+mod decorate;
+#[cfg(unix)]
+mod detach;
+#[cfg(feature = "miette")]
+mod diagnostic;
+mod diff;
+mod dynamic;
+#[cfg(feature = "regex")]
+mod expect;
+#[cfg(feature = "duct")]
+mod expression;
+mod fake;
+mod guard;
+mod hermetic;
+mod html;
+#[cfg(feature = "serde")]
+mod json;
+mod junit;
+mod mux;
+mod orphans;
+mod persist;
+mod policy;
+mod progress;
+mod recording;
+mod runner;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+mod sandbox;
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+mod seccomp;
+mod shell;
+mod signals;
+mod spec;
+#[cfg(feature = "ssh")]
+mod ssh;
+mod supervise;
+mod template;
+mod terminal;
+mod throttle;
+#[cfg(feature = "serde")]
+mod trace;
+mod transform;
+mod validate;
+
+pub use backend::{BackendIo, ExecutionBackend, Local};
+pub use batch::{Batch, RunReport, StepReport, StepResult};
+pub use breaker::CircuitBreaker;
+#[cfg(feature = "regex")]
+pub use capture::{CapturingWriter, LineCaptures};
+pub use chaos::{Chaos, Fault};
+pub use child::{NamedChild, NewOutput};
+pub use chroot::Chroot;
+#[cfg(feature = "flate2")]
+pub use compressed::CompressedOutput;
+pub use confirm::Confirm;
+#[cfg(feature = "container")]
+pub use container::Container;
+pub use decorate::Decorations;
+#[cfg(unix)]
+pub use detach::Detached;
+pub use diff::OutputDiff;
+pub use dynamic::RunnableCommand;
+#[cfg(feature = "regex")]
+pub use expect::Interaction;
+#[cfg(feature = "duct")]
+pub use expression::ExpressionWithName;
+pub use fake::{FakeBin, FakeExecutable};
+pub use guard::CommandGuard;
+pub use hermetic::HermeticEnv;
+#[cfg(feature = "serde")]
+pub use json::JsonLines;
+pub use mux::{LineMultiplexer, MultiplexedWriter};
+pub use orphans::terminate_children_on_exit;
+pub use policy::{ExitDecision, ExitPolicy};
+pub use progress::{ByteProgress, OutputStats, PhaseTimings};
+pub use recording::{Channel, RecordedEvent, Recorder, Recording, RecordingWriter};
+pub use runner::{FunRunner, RunRecord};
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub use sandbox::Sandbox;
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+pub use seccomp::Seccomp;
+pub use shell::{shell, Shell};
+pub use signals::Signal;
+pub use spec::{CommandSpec, ParseCommandError};
+#[cfg(feature = "ssh")]
+pub use ssh::Ssh;
+pub use supervise::{Supervised, Supervisor};
+pub use template::{ErrorFormat, ErrorTemplate, NameQuote};
+pub use terminal::{NameFit, TerminalSize};
+pub use throttle::Throttle;
+pub use transform::{LineTransform, TransformWriter};
+pub use validate::{validate, ValidationError};
 
 /// Rename your commands:
 ///
@@ -29,10 +147,10 @@ mod command;
 ///
 /// match result {
 ///     Ok(output) => {
-///         assert_eq!("bundle install", &output.name())
+///         assert_eq!("bundle install", output.name())
 ///     },
 ///     Err(varient) => {
-///         assert_eq!("bundle install", &varient.name())
+///         assert_eq!("bundle install", varient.name())
 ///     }
 /// }
 /// ```
@@ -59,14 +177,14 @@ mod command;
 ///  match result {
 ///      Ok(output) => {
 ///          assert_eq!(
-///              "GEM_HOME=\"/usr/bin/local/.gems\" gem install bundler -v 2.4.1.7",
-///              &output.name()
+///              "GEM_HOME=/usr/bin/local/.gems gem install bundler -v 2.4.1.7",
+///              output.name()
 ///          )
 ///      }
 ///      Err(varient) => {
 ///          assert_eq!(
-///              "GEM_HOME=\"/usr/bin/local/.gems\" gem install bundler -v 2.4.1.7",
-///              &varient.name()
+///              "GEM_HOME=/usr/bin/local/.gems gem install bundler -v 2.4.1.7",
+///              varient.name()
 ///          )
 ///      }
 ///  }
@@ -80,6 +198,53 @@ pub trait CommandWithName {
     /// This is useful for passing to other libraries.
     fn mut_cmd(&mut self) -> &mut Command;
 
+    /// Returns the detailed name of the command used in audit logs and run history
+    ///
+    /// Renaming with [CommandWithName::named] only changes the display name, the log name
+    /// stays the full command with the keys of every environment variable set on it. Values
+    /// are masked since they often hold tokens, use [NamedCommand::with_env_values_in_log_name]
+    /// to include them. Use [NamedCommand::with_log_name] or
+    /// [crate::CommandSpec::with_log_name] to change it.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("bin/bundle");
+    /// command.args(["install", "--jobs", "4"]).env("BUNDLE_FROZEN", "1");
+    ///
+    /// let mut cmd = command.named("bundle install");
+    /// assert_eq!("bundle install", cmd.name());
+    /// assert_eq!("BUNDLE_FROZEN=**** bin/bundle install --jobs 4", cmd.log_name());
+    /// ```
+    fn log_name(&mut self) -> String {
+        display_with_set_env_keys(self.mut_cmd())
+    }
+
+    /// The program and arguments for structured logs, such as the audit log of a [FunRunner]
+    ///
+    /// Secret values given to [CommandWithName::named_redacting] are masked in every argument.
+    /// Arguments that are not UTF-8 are converted lossily.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("git");
+    /// command.args(["commit", "-m", "wip"]);
+    ///
+    /// assert_eq!(vec!["git", "commit", "-m", "wip"], command.log_argv());
+    /// ```
+    fn log_argv(&mut self) -> Vec<String> {
+        let cmd = self.mut_cmd();
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
     /// Rename a command via a given string
     ///
     /// This can be useful if a part of the command is distracting or surprising or if you
@@ -102,7 +267,42 @@ pub trait CommandWithName {
     fn named(&mut self, s: impl AsRef<str>) -> NamedCommand<'_> {
         let name = s.as_ref().to_string();
         let command = self.mut_cmd();
-        NamedCommand { name, command }
+        NamedCommand {
+            name: OnceLock::from(name),
+            log_name: None,
+            secrets: Vec::new(),
+            command,
+        }
+    }
+
+    /// Name the command after itself, computing the name once when it's first needed
+    ///
+    /// The name of a plain `&mut Command` is computed on every call to
+    /// [CommandWithName::name], which walks and quotes every argument. Running the returned
+    /// command repeatedly reuses the cached name. Changes made to the command afterwards are
+    /// not reflected in the name.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("echo");
+    /// command.args(["hello", "world"]);
+    ///
+    /// let mut cmd = command.cache_name();
+    /// for _ in 0..3 {
+    ///     let output = cmd.named_output().unwrap();
+    ///     assert_eq!("echo hello world", output.name());
+    /// }
+    /// ```
+    fn cache_name(&mut self) -> NamedCommand<'_> {
+        NamedCommand {
+            name: OnceLock::new(),
+            log_name: None,
+            secrets: Vec::new(),
+            command: self.mut_cmd(),
+        }
     }
 
     /// Rename a command via a given function
@@ -130,6 +330,165 @@ pub trait CommandWithName {
         self.named(name)
     }
 
+    /// Rename a command, masking any of the given secret values that appear in its name
+    ///
+    /// Use this when a token or password is passed as an argument, so it does not leak into
+    /// logs or error messages. See [redact] for details.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let token = "hunter2";
+    /// let mut command = std::process::Command::new("deploy");
+    /// command.args(["--password", token]);
+    ///
+    /// let mut cmd = command.named_redacting([token]);
+    /// assert_eq!("deploy --password ****", cmd.name());
+    /// assert_eq!(vec!["deploy", "--password", "****"], cmd.log_argv());
+    ///
+    /// let error = cmd.named_output().unwrap_err();
+    /// assert!(!error.to_string().contains(token));
+    /// ```
+    ///
+    /// Helpers called on the result, such as [CommandWithName::umask], keep masking:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("deploy");
+    /// command.args(["--password", "hunter2"]);
+    ///
+    /// let mut cmd = command.named_redacting(["hunter2"]);
+    /// let mut cmd = cmd.c_locale();
+    /// let mut cmd = cmd.with_path_prepended(["/layers/bin"]);
+    /// let mut cmd = cmd.with_path_appended(["/usr/local/bin"]);
+    /// let mut cmd = cmd.umask(0o022);
+    /// assert_eq!(vec!["deploy", "--password", "****"], cmd.log_argv());
+    /// assert!(!cmd.name().contains("hunter2"));
+    /// assert!(!cmd.log_name().contains("hunter2"));
+    /// ```
+    fn named_redacting<I, S>(&mut self, secrets: I) -> NamedCommand<'_>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let name = self.name();
+        let mut named = self.named("");
+        named.secrets.extend(
+            secrets
+                .into_iter()
+                .map(|secret| secret.as_ref().to_string()),
+        );
+        named.name = OnceLock::from(redact(&name, &named.secrets));
+        named.log_name = named
+            .log_name
+            .take()
+            .map(|log_name| redact(&log_name, &named.secrets));
+        named
+    }
+
+    /// Force the C locale so output is not translated, noting it in the name
+    ///
+    /// Sets `LC_ALL=C` and `LANG=C.UTF-8` so tools such as `git`, `ls` or compilers print
+    /// untranslated messages that are safe to parse.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("git");
+    /// command.arg("status");
+    ///
+    /// let mut cmd = command.c_locale();
+    /// assert_eq!("LC_ALL=C git status", cmd.name());
+    /// ```
+    fn c_locale(&mut self) -> NamedCommand<'_> {
+        let name = format!("LC_ALL=C {}", self.name());
+        self.mut_cmd().env("LC_ALL", "C").env("LANG", "C.UTF-8");
+        self.named(name)
+    }
+
+    /// Add directories to the front of the command's `PATH`, noting it in the name
+    ///
+    /// Starts from the `PATH` set on the command, or the parent's `PATH` if none is set, even
+    /// after [Command::env_clear]. The name shows the change as `PATH=<dirs>:$PATH`.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("bundle");
+    /// command.arg("install").env("PATH", "/usr/bin");
+    ///
+    /// let mut cmd = command.with_path_prepended(["/layers/ruby/bin", "/layers/gems/bin"]);
+    /// assert_eq!("PATH=/layers/ruby/bin:/layers/gems/bin:$PATH bundle install", cmd.name());
+    /// assert_eq!(
+    ///     Some(std::ffi::OsStr::new("/layers/ruby/bin:/layers/gems/bin:/usr/bin")),
+    ///     cmd.mut_cmd().get_envs().find(|(key, _)| *key == "PATH").and_then(|(_, value)| value)
+    /// );
+    /// ```
+    fn with_path_prepended<I, P>(&mut self, dirs: I) -> NamedCommand<'_>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<std::path::Path>,
+    {
+        let dirs = join_dirs(dirs);
+        let name = format!("PATH={}:$PATH {}", Quoting::Debug.quote(&dirs), self.name());
+        let path = join_dirs([dirs, command::path_env(self.mut_cmd()).unwrap_or_default()]);
+        self.mut_cmd().env("PATH", path);
+        self.named(name)
+    }
+
+    /// Add directories to the end of the command's `PATH`, noting it in the name
+    ///
+    /// See [CommandWithName::with_path_prepended], the name shows `PATH=$PATH:<dirs>`.
+    fn with_path_appended<I, P>(&mut self, dirs: I) -> NamedCommand<'_>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<std::path::Path>,
+    {
+        let dirs = join_dirs(dirs);
+        let name = format!("PATH=$PATH:{} {}", Quoting::Debug.quote(&dirs), self.name());
+        let path = join_dirs([command::path_env(self.mut_cmd()).unwrap_or_default(), dirs]);
+        self.mut_cmd().env("PATH", path);
+        self.named(name)
+    }
+
+    /// Set the umask of the command before it starts
+    ///
+    /// Files the command creates get predictable permissions regardless of the parent's
+    /// umask. The name is unchanged.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let output = std::process::Command::new("bash")
+    ///     .args(["-c", "umask"])
+    ///     .umask(0o027)
+    ///     .named_output()
+    ///     .unwrap();
+    ///
+    /// assert_eq!("0027", output.stdout_lossy().trim());
+    /// ```
+    fn umask(&mut self, mask: u32) -> NamedCommand<'_> {
+        let name = self.name();
+        let mask = mask as libc::mode_t;
+        // SAFETY: umask is async-signal-safe and does not allocate
+        unsafe {
+            self.mut_cmd().pre_exec(move || {
+                libc::umask(mask);
+                Ok(())
+            });
+        }
+        self.named(name)
+    }
+
     /// Runs the command without streaming
     ///
     /// # Errors
@@ -138,13 +497,10 @@ pub trait CommandWithName {
     /// Returns `CmdError::NonZeroExitNotStreamed` if the exit code is not zero.
     fn named_output(&mut self) -> Result<NamedOutput, CmdError> {
         let name = self.name();
-        self.mut_cmd()
-            .output()
-            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error))
-            .map(|output| NamedOutput {
-                name: name.clone(),
-                output,
-            })
+        let run_id = next_run_id();
+        command::output(self.mut_cmd())
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|output| NamedOutput::for_run(name, run_id, output))
             .and_then(NamedOutput::nonzero_captured)
     }
 
@@ -163,169 +519,2033 @@ pub trait CommandWithName {
         OW: Write + Send,
         EW: Write + Send,
     {
-        let name = &self.name();
+        let name = self.name();
+        let run_id = next_run_id();
         let cmd = self.mut_cmd();
 
-        output_and_write_streams(cmd, stdout_write, stderr_write)
-            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error))
-            .map(|output| NamedOutput {
-                name: name.clone(),
-                output,
-            })
+        command::output_and_write_streams(cmd, stdout_write, stderr_write)
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
             .and_then(NamedOutput::nonzero_streamed)
     }
-}
-
-impl CommandWithName for Command {
-    fn name(&mut self) -> String {
-        crate::display(self)
-    }
-
-    fn mut_cmd(&mut self) -> &mut Command {
-        self
-    }
-}
-
-impl CommandWithName for &mut Command {
-    fn name(&mut self) -> String {
-        crate::display(self)
-    }
-
-    fn mut_cmd(&mut self) -> &mut Command {
-        self
-    }
-}
-
-/// It's a command, with a name
-///
-/// This struct allows us to re-name an existing [Command] via the [CommandWithName] trait associated
-/// functions. When one of those functions such as [CommandWithName::named_fn] or [CommandWithName::named]
-/// are called, Rust needs somewhere for the new name string to live, so we move it over into this struct
-/// which also implements [CommandWithName]. You can gain access to the original [Command] reference
-/// via `CommandWithName::mut_cmd`
-pub struct NamedCommand<'a> {
-    name: String,
-    command: &'a mut Command,
-}
-
-impl CommandWithName for NamedCommand<'_> {
-    fn name(&mut self) -> String {
-        self.name.to_string()
-    }
-
-    fn mut_cmd(&mut self) -> &mut Command {
-        self.command
-    }
-}
-
-/// Holds a the `Output` of a command's execution along with it's "name"
-///
-/// When paired with `CmdError` a `Result<NamedOutput, CmdError>` will retain the
-/// "name" of the command regardless of succss or failure.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct NamedOutput {
-    name: String,
-    output: Output,
-}
 
-impl NamedOutput {
-    /// Check status and convert into an error if nonzero (include output in error)
+    /// Runs the command and streams to the given writers, reporting bytes read as they arrive
     ///
-    /// Because the [NamedOutput] does not contain information about whether it was originally
-    /// streamed or not, use this associated function when the output has not been made
-    /// available to the user. This has the effect of showing it in the event of [CmdError].
+    /// `on_progress` is called with the running totals after every chunk of output is written,
+    /// from whichever thread is reading that stream.
     ///
-    /// If the output was streamed to the user use [NamedOutput::nonzero_streamed]
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "head -c 4096 /dev/zero"])
+    ///     .stream_output_with_progress(std::io::sink(), std::io::stderr(), |progress| {
+    ///         eprint!("\rdownloaded {} bytes", progress.stdout);
+    ///     })
+    ///     .unwrap();
+    ///
+    /// assert_eq!(4096, output.stdout_bytes());
+    /// ```
     ///
     /// # Errors
     ///
-    /// Returns an error if the status is not zero
-    pub fn nonzero_captured(self) -> Result<NamedOutput, CmdError> {
-        nonzero_captured(self.name, self.output)
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    fn stream_output_with_progress<OW, EW, F>(
+        &mut self,
+        stdout_write: OW,
+        stderr_write: EW,
+        on_progress: F,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        OW: Write + Send,
+        EW: Write + Send,
+        F: FnMut(ByteProgress) + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+        let tracker = ProgressTracker::new(on_progress);
+
+        command::output_and_write_streams(
+            cmd,
+            tracker.writer(Stream::Stdout, stdout_write),
+            tracker.writer(Stream::Stderr, stderr_write),
+        )
+        .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+        .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+        .and_then(NamedOutput::nonzero_streamed)
     }
 
-    /// Check status and convert into an error if nonzero (hide output in error)
+    /// Runs the command and captures its output, calling `on_large` once when the output
+    /// grows past `threshold` bytes
     ///
-    /// Because the [NamedOutput] does not contain information about whether it was originally
-    /// streamed or not, use this associated function when the output has was streamed to the user.
-    /// This has the effect of hiding the output in the event of [CmdError] to prevent including
-    /// duplicate information twice.
+    /// Gives soft feedback about a command that produces a lot of output, such as suggesting
+    /// to stream it, while the command keeps running and nothing is dropped. The callback
+    /// receives the totals at the time the threshold was crossed, from whichever thread is
+    /// reading that stream. Unlike [CommandWithName::named_output] stdin is left as set on the
+    /// command, inherited unless you pass `Stdio::null()` to close it.
     ///
-    /// If the output was not streamed to the user use [NamedOutput::nonzero_captured]
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::{Command, Stdio};
+    ///
+    /// let mut warnings = 0;
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "head -c 100000 /dev/zero"])
+    ///     .stdin(Stdio::null())
+    ///     .named_output_with_size_warning(1024, |progress| {
+    ///         warnings += 1;
+    ///         eprintln!("`head` produced {} bytes so far, consider streaming", progress.total());
+    ///     })
+    ///     .unwrap();
+    ///
+    /// assert_eq!(1, warnings);
+    /// assert_eq!(100_000, output.stdout_bytes());
+    /// ```
     ///
     /// # Errors
     ///
-    /// Returns an error if the status is not zero
-    pub fn nonzero_streamed(self) -> Result<NamedOutput, CmdError> {
-        nonzero_streamed(self.name, self.output)
-    }
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitNotStreamed` if the exit code is not zero.
+    fn named_output_with_size_warning<F>(
+        &mut self,
+        threshold: usize,
+        on_large: F,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        F: FnOnce(ByteProgress) + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+        let mut on_large = Some(on_large);
+        let tracker = ProgressTracker::new(|progress: ByteProgress| {
+            if progress.total() > threshold {
+                if let Some(on_large) = on_large.take() {
+                    on_large(progress);
+                }
+            }
+        });
 
-    /// Return the ExitStatus of the output
-    #[must_use]
-    pub fn status(&self) -> &ExitStatus {
-        &self.output.status
+        command::output_and_write_streams(
+            cmd,
+            tracker.writer(Stream::Stdout, std::io::sink()),
+            tracker.writer(Stream::Stderr, std::io::sink()),
+        )
+        .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+        .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+        .and_then(NamedOutput::nonzero_captured)
     }
 
-    /// Return raw stdout
-    #[must_use]
-    pub fn stdout(&self) -> &Vec<u8> {
-        &self.output.stdout
-    }
+    /// Runs the command and streams to the given writers, collecting lines that match patterns
+    ///
+    /// Matching lines are available via [NamedOutput::captured_lines], see [LineCaptures].
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    #[cfg(feature = "regex")]
+    fn stream_output_capturing<OW, EW>(
+        &mut self,
+        captures: LineCaptures,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        OW: Write + Send,
+        EW: Write + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
 
-    /// Return raw stderr
-    #[must_use]
-    pub fn stderr(&self) -> &Vec<u8> {
-        &self.output.stderr
+        command::output_and_write_streams(
+            cmd,
+            captures.writer(stdout_write),
+            captures.writer(stderr_write),
+        )
+        .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+        .map(|(output, stats)| {
+            let mut named_output =
+                NamedOutput::for_run(name.clone(), run_id, output).with_stats(stats);
+            named_output.extras.captured_lines = captures.lines();
+            named_output
+        })
+        .and_then(NamedOutput::nonzero_streamed)
     }
 
-    /// Return lossy stdout as a String
-    #[must_use]
-    pub fn stdout_lossy(&self) -> String {
-        String::from_utf8_lossy(&self.output.stdout).to_string()
-    }
+    /// Runs the command and streams to the given writers, killing it if a line matches a pattern
+    ///
+    /// Some tools print a fatal error and then hang instead of exiting. When any line of stdout
+    /// or stderr matches one of the patterns the child is killed and the error describes which
+    /// pattern matched. Processes spawned by the child that keep its output open will delay
+    /// the return until they exit.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use regex::Regex;
+    /// use std::process::Command;
+    ///
+    /// let error = Command::new("bash")
+    ///     .args(["-c", "echo 'FATAL: deadlock detected' >&2 && exec sleep 100"])
+    ///     .stream_output_abort_on(
+    ///         [Regex::new("^FATAL:").unwrap()],
+    ///         std::io::stdout(),
+    ///         std::io::stderr(),
+    ///     )
+    ///     .unwrap_err();
+    ///
+    /// assert!(error
+    ///     .to_string()
+    ///     .contains("stderr matched `^FATAL:`: FATAL: deadlock detected"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::OutputMatched` if a line matched one of the patterns.
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    #[cfg(feature = "regex")]
+    fn stream_output_abort_on<OW, EW>(
+        &mut self,
+        patterns: impl IntoIterator<Item = Regex>,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        OW: Write + Send,
+        EW: Write + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+        let state = AbortState::new(patterns.into_iter().collect());
+
+        let result = output_and_write_streams_on_spawn(
+            cmd,
+            state.writer(Stream::Stdout, stdout_write),
+            state.writer(Stream::Stderr, stderr_write),
+            |child| state.set_pid(child.id()),
+        );
+
+        result
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+            .and_then(|named_output| match state.into_match() {
+                Some(matched) => Err(CmdError::OutputMatched(named_output, Box::new(matched))),
+                None => named_output.nonzero_streamed(),
+            })
+    }
+
+    /// Runs the command, capturing output, and decides success, retry or failure via `policy`
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, ExitPolicy};
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let marker = std::env::temp_dir().join(format!("fun_run_policy_{}", std::process::id()));
+    /// // Exits with 75 (temporary failure) the first time, then succeeds
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "[ -f \"$0\" ] && echo ok || { touch \"$0\"; exit 75; }"])
+    ///     .arg(&marker)
+    ///     .named_output_with_policy(
+    ///         &ExitPolicy::success_on([0])
+    ///             .retry_on([75])
+    ///             .retry_delay(Duration::from_millis(10)),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!("ok\n", output.stdout_lossy());
+    /// # std::fs::remove_file(marker).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitNotStreamed` if the policy decides the run failed, or it
+    /// was still retryable after the maximum number of retries.
+    fn named_output_with_policy(&mut self, policy: &ExitPolicy) -> Result<NamedOutput, CmdError> {
+        let name = self.name();
+        policy.run(&name, self.mut_cmd())
+    }
+
+    /// Runs the command and streams both stdout and stderr, relaying Ctrl+C to the command
+    ///
+    /// While the command runs, SIGINT and SIGTERM received by this process are forwarded to the
+    /// command instead of terminating this process. The command stays in the foreground process
+    /// group so it can read from the terminal, and Ctrl+C typed at the terminal reaches it (and
+    /// anything it spawns) directly, so those signals aren't sent a second time. Only one
+    /// command forwards signals at a time, concurrent calls wait for the running one to finish.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use fun_run::{CmdError, CommandWithName};
+    /// use std::process::Command;
+    ///
+    /// let result = Command::new("bundle")
+    ///     .args(["exec", "rails", "console"])
+    ///     .stream_output_forwarding_signals(std::io::stdout(), std::io::stderr());
+    ///
+    /// match result {
+    ///     Ok(_) => println!("Done"),
+    ///     Err(error @ CmdError::Interrupted(..)) => eprintln!("Stopped: {error}"),
+    ///     Err(error) => panic!("{error}"),
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::Interrupted` if a signal was received while the command ran, even
+    /// when the command exits successfully in response.
+    /// Returns `CmdError::NonZeroExitAlreadyStreamed` if the exit code is not zero.
+    fn stream_output_forwarding_signals<OW, EW>(
+        &mut self,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        OW: Write + Send,
+        EW: Write + Send,
+    {
+        let name = self.name();
+        let forwarding = SignalForwarding::install()
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, None))?;
+        let run_id = next_run_id();
+
+        let result = output_and_write_streams_on_spawn(
+            self.mut_cmd(),
+            stdout_write,
+            stderr_write,
+            |child| {
+                forwarding.forward_to(child);
+            },
+        );
+        let received = forwarding.received();
+        drop(forwarding);
+
+        result
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+            .and_then(|named_output| match received {
+                Some(signal) => Err(CmdError::Interrupted(named_output, signal)),
+                None => named_output.nonzero_streamed(),
+            })
+    }
+
+    /// Runs the command in the background and returns once a line of output matches `pattern`
+    ///
+    /// Useful for starting servers in integration tests. Output is streamed to the given
+    /// writers and captured. After the command is ready, output continues to be drained in the
+    /// background, use [NamedChild::wait] to collect it.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use regex::Regex;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let mut server = Command::new("bash")
+    ///     .args(["-c", "echo 'Listening on port 5000' && exec sleep 100"])
+    ///     .spawn_until(
+    ///         Regex::new("^Listening on").unwrap(),
+    ///         Duration::from_secs(10),
+    ///         std::io::stdout(),
+    ///         std::io::stderr(),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// // Run tests against the server
+    ///
+    /// server.kill().unwrap();
+    /// let output: fun_run::NamedOutput = server.wait().unwrap_err().into();
+    /// assert_eq!("Listening on port 5000\n", output.stdout_lossy());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command.
+    /// Returns `CmdError::NotReady` if the output closes or the timeout elapses before a line
+    /// matches. The command is killed and the error includes all output captured so far.
+    #[cfg(feature = "regex")]
+    fn spawn_until<OW, EW>(
+        &mut self,
+        pattern: Regex,
+        timeout: Duration,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<NamedChild, CmdError>
+    where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        let name = self.name();
+        let readiness = Arc::new(Readiness::matching(pattern.clone()));
+        let child = NamedChild::spawn(
+            name,
+            self.mut_cmd(),
+            Arc::clone(&readiness),
+            stdout_write,
+            stderr_write,
+        )?;
+
+        match readiness.wait(timeout) {
+            Ready::Matched => Ok(child),
+            Ready::Closed => {
+                Err(child.not_ready(format!("Output closed before a line matched `{pattern}`")))
+            }
+            Ready::TimedOut => Err(child.not_ready(format!(
+                "Timed out after {timeout:?} waiting for a line to match `{pattern}`"
+            ))),
+        }
+    }
+
+    /// Starts the command and returns while its output streams in the background
+    ///
+    /// The first half of [CommandWithName::stream_output]: output is written to the given
+    /// writers and captured as it arrives while the caller does other work. Call
+    /// [NamedChild::wait] to finish, which returns the same result `stream_output` would.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let download = Command::new("bash")
+    ///     .args(["-c", "sleep 0.1 && echo downloaded"])
+    ///     .start(std::io::stdout(), std::io::stderr())
+    ///     .unwrap();
+    ///
+    /// let checksum: u64 = (0..1_000u64).sum();
+    ///
+    /// let output = download.wait().unwrap();
+    /// assert_eq!("downloaded\n", output.stdout_lossy());
+    /// assert_eq!(499_500, checksum);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command.
+    fn start<OW, EW>(&mut self, stdout_write: OW, stderr_write: EW) -> Result<NamedChild, CmdError>
+    where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        let name = self.name();
+        NamedChild::spawn(
+            name,
+            self.mut_cmd(),
+            Arc::new(Readiness::new()),
+            stdout_write,
+            stderr_write,
+        )
+    }
+
+    /// Runs the command in the background, detached from this process
+    ///
+    /// The command is started in a new session via a double fork so it keeps running after
+    /// this process exits and never reacquires a terminal. Stdin is closed, stdout and stderr
+    /// are appended to `stdout.log` and `stderr.log` in `dir`, and the pid and command name are
+    /// written to a `pid` file in `dir`.
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use fun_run::{CommandWithName, Detached};
+    /// use std::process::Command;
+    ///
+    /// let detached = Command::new("bundle")
+    ///     .args(["exec", "sidekiq"])
+    ///     .detach("tmp/sidekiq")
+    ///     .unwrap();
+    ///
+    /// println!("Started `{}` with pid {}", detached.name(), detached.pid());
+    /// println!("Logs at {}", detached.stdout_path().display());
+    ///
+    /// // Later, possibly from another process
+    /// let detached = Detached::from_dir("tmp/sidekiq").unwrap();
+    /// assert!(detached.is_running());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the log files or pidfile cannot be written, or the
+    /// system is unable to run the command.
+    #[cfg(unix)]
+    fn detach(&mut self, dir: impl AsRef<std::path::Path>) -> Result<Detached, CmdError> {
+        let name = self.name();
+        detach::detach(name, self.mut_cmd(), dir.as_ref())
+    }
+
+    /// Runs the command, answering prompts on its stdin as described by `interaction`
+    ///
+    /// Output is captured, not streamed. The exchanges are recorded in
+    /// [NamedOutput::transcript], see [Interaction] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::InteractionFailed` if the output closes or the timeout elapses
+    /// before a prompt matches. The command is killed and the error includes all output
+    /// and the output that did not match.
+    /// Returns `CmdError::NonZeroExitNotStreamed` if the exit code is not zero.
+    #[cfg(feature = "regex")]
+    fn interact(&mut self, interaction: Interaction) -> Result<NamedOutput, CmdError> {
+        let name = self.name();
+        expect::interact(name, self.mut_cmd(), interaction)
+    }
+
+    /// Runs the command, streams stdout to the given writer and quietly captures stderr
+    ///
+    /// Useful for commands where stdout is meant for the user and stderr is noise
+    /// unless something goes wrong. On failure the captured stderr is included in the error.
+    ///
+    /// Alternatively see [CommandWithName::stream_stderr_output]
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitStdoutStreamed` if the exit code is not zero.
+    fn stream_stdout_output<OW>(&mut self, stdout_write: OW) -> Result<NamedOutput, CmdError>
+    where
+        OW: Write + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+
+        command::output_and_write_streams(cmd, stdout_write, std::io::sink())
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+            .and_then(NamedOutput::nonzero_stdout_streamed)
+    }
+
+    /// Runs the command, streams stderr to the given writer and quietly captures stdout
+    ///
+    /// Useful for commands such as `ffmpeg` that report progress on stderr while
+    /// emitting data on stdout. On failure the captured stdout is included in the error.
+    ///
+    /// Alternatively see [CommandWithName::stream_stdout_output]
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitStderrStreamed` if the exit code is not zero.
+    fn stream_stderr_output<EW>(&mut self, stderr_write: EW) -> Result<NamedOutput, CmdError>
+    where
+        EW: Write + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+
+        command::output_and_write_streams(cmd, std::io::sink(), stderr_write)
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+            .and_then(NamedOutput::nonzero_stderr_streamed)
+    }
+
+    /// Runs the command, decoding each line of stdout as JSON while streaming stderr
+    ///
+    /// For tools that emit newline delimited JSON such as `cargo build --message-format=json`.
+    /// Every line is deserialized into `T` and passed to `on_event` as it arrives, see [JsonLines].
+    /// Stdout is still captured in the returned [NamedOutput].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let mut values = Vec::new();
+    /// Command::new("bash")
+    ///     .args(["-c", r#"echo '{"a": 1}' && echo '{"b": 2}'"#])
+    ///     .stream_json_lines(
+    ///         |event: Result<serde_json::Value, _>| values.push(event.unwrap()),
+    ///         std::io::stderr(),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(2, values.len());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitStderrStreamed` if the exit code is not zero.
+    #[cfg(feature = "serde")]
+    fn stream_json_lines<T, F, EW>(
+        &mut self,
+        on_event: F,
+        stderr_write: EW,
+    ) -> Result<NamedOutput, CmdError>
+    where
+        T: serde::de::DeserializeOwned,
+        F: FnMut(Result<T, serde_json::Error>) + Send,
+        EW: Write + Send,
+    {
+        let name = self.name();
+        let run_id = next_run_id();
+        let cmd = self.mut_cmd();
+
+        command::output_and_write_streams(cmd, JsonLines::new(on_event), stderr_write)
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|(output, stats)| NamedOutput::for_run(name, run_id, output).with_stats(stats))
+            .and_then(NamedOutput::nonzero_stderr_streamed)
+    }
+
+    /// Runs the command with stdout sent to null, capturing only stderr
+    ///
+    /// Useful for commands where stdout is an artifact that nobody will read, it's not
+    /// buffered in memory and the error display won't claim that stdout was empty.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let error = Command::new("bash")
+    ///     .args(["-c", "echo artifact && echo 'disk full' >&2 && exit 1"])
+    ///     .discard_stdout_output()
+    ///     .unwrap_err();
+    ///
+    /// assert!(error.to_string().contains("stdout: <discarded>\nstderr: disk full"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the system is unable to run the command
+    /// Returns `CmdError::NonZeroExitStdoutDiscarded` if the exit code is not zero.
+    fn discard_stdout_output(&mut self) -> Result<NamedOutput, CmdError> {
+        let name = self.name();
+        let run_id = next_run_id();
+        command::output_discarding_stdout(self.mut_cmd())
+            .map_err(|io_error| CmdError::SystemError(name.clone(), io_error, Some(run_id)))
+            .map(|output| NamedOutput::for_run(name, run_id, output))
+            .and_then(NamedOutput::nonzero_stdout_discarded)
+    }
+}
+
+impl CommandWithName for Command {
+    fn name(&mut self) -> String {
+        crate::display(self)
+    }
+
+    fn mut_cmd(&mut self) -> &mut Command {
+        self
+    }
+}
+
+impl CommandWithName for &mut Command {
+    fn name(&mut self) -> String {
+        crate::display(self)
+    }
+
+    fn mut_cmd(&mut self) -> &mut Command {
+        self
+    }
+}
+
+/// It's a command, with a name
+///
+/// This struct allows us to re-name an existing [Command] via the [CommandWithName] trait associated
+/// functions. When one of those functions such as [CommandWithName::named_fn] or [CommandWithName::named]
+/// are called, Rust needs somewhere for the new name string to live, so we move it over into this struct
+/// which also implements [CommandWithName]. You can gain access to the original [Command] reference
+/// via `CommandWithName::mut_cmd`
+pub struct NamedCommand<'a> {
+    /// Empty until first used when created with [CommandWithName::cache_name]
+    name: OnceLock<String>,
+    log_name: Option<String>,
+    /// Masked in [CommandWithName::log_argv], set by [CommandWithName::named_redacting]
+    secrets: Vec<String>,
+    command: &'a mut Command,
+}
+
+impl NamedCommand<'_> {
+    fn cached_name(&self) -> &str {
+        self.name
+            .get_or_init(|| render(self.command, Quoting::Debug))
+    }
+
+    /// Use a custom log name instead of deriving one from the command
+    ///
+    /// See [CommandWithName::log_name].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("bin/rails");
+    /// command.args(["db:migrate", "--trace"]);
+    ///
+    /// let mut cmd = command
+    ///     .named("rails db:migrate")
+    ///     .with_log_name("RAILS_ENV=production bin/rails db:migrate --trace");
+    /// assert_eq!("rails db:migrate", cmd.name());
+    /// assert_eq!("RAILS_ENV=production bin/rails db:migrate --trace", cmd.log_name());
+    /// ```
+    #[must_use]
+    pub fn with_log_name(mut self, log_name: impl AsRef<str>) -> Self {
+        self.log_name = Some(log_name.as_ref().to_string());
+        self
+    }
+
+    /// Include the values of environment variables set on the command in its log name
+    ///
+    /// See [CommandWithName::log_name]. Secrets given to [CommandWithName::named_redacting]
+    /// are still masked.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    ///
+    /// let mut command = std::process::Command::new("bin/rails");
+    /// command.arg("db:migrate").env("RAILS_ENV", "production");
+    ///
+    /// let mut cmd = command.named("rails db:migrate");
+    /// assert_eq!("RAILS_ENV=**** bin/rails db:migrate", cmd.log_name());
+    ///
+    /// let mut cmd = cmd.with_env_values_in_log_name();
+    /// assert_eq!("RAILS_ENV=production bin/rails db:migrate", cmd.log_name());
+    /// ```
+    #[must_use]
+    pub fn with_env_values_in_log_name(mut self) -> Self {
+        self.log_name = Some(redact(&display_with_set_env(self.command), &self.secrets));
+        self
+    }
+
+    /// Copy the command along with its custom name, see [clone_command] for what is copied
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let mut command = Command::new("bundle");
+    /// command.arg("install");
+    /// let named = command.named("bundle install --verbose");
+    ///
+    /// let (name, mut copy) = named.try_clone();
+    /// assert_eq!("bundle install --verbose", name);
+    /// assert_eq!("bundle install", fun_run::display(&mut copy));
+    /// ```
+    #[must_use]
+    pub fn try_clone(&self) -> (String, Command) {
+        (self.cached_name().to_string(), clone_command(self.command))
+    }
+}
+
+/// Displays the name, without needing `&mut` access
+///
+/// Example:
+///
+/// ```
+/// use fun_run::CommandWithName;
+///
+/// let mut command = std::process::Command::new("bundle");
+/// let cmd = command.arg("install").named("bundle install --quiet");
+///
+/// assert_eq!("Running `bundle install --quiet`", format!("Running `{cmd}`"));
+/// ```
+impl Display for NamedCommand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.cached_name())
+    }
+}
+
+impl CommandWithName for NamedCommand<'_> {
+    fn name(&mut self) -> String {
+        self.cached_name().to_string()
+    }
+
+    fn cache_name(&mut self) -> NamedCommand<'_> {
+        NamedCommand {
+            name: self.name.clone(),
+            log_name: self.log_name.clone(),
+            secrets: self.secrets.clone(),
+            command: self.command,
+        }
+    }
+
+    /// Keeps the log name and the secrets given to [CommandWithName::named_redacting]
+    fn named(&mut self, s: impl AsRef<str>) -> NamedCommand<'_> {
+        NamedCommand {
+            name: OnceLock::from(s.as_ref().to_string()),
+            log_name: self.log_name.clone(),
+            secrets: self.secrets.clone(),
+            command: self.command,
+        }
+    }
+
+    fn log_name(&mut self) -> String {
+        match &self.log_name {
+            Some(log_name) => log_name.clone(),
+            None => redact(&display_with_set_env_keys(self.command), &self.secrets),
+        }
+    }
+
+    fn log_argv(&mut self) -> Vec<String> {
+        std::iter::once(self.command.get_program())
+            .chain(self.command.get_args())
+            .map(|arg| redact(&arg.to_string_lossy(), &self.secrets))
+            .collect()
+    }
+
+    fn mut_cmd(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+/// Holds a the `Output` of a command's execution along with it's "name"
+///
+/// When paired with `CmdError` a `Result<NamedOutput, CmdError>` will retain the
+/// "name" of the command regardless of succss or failure.
+///
+/// Two outputs are equal when everything but their [NamedOutput::run_id] is equal.
+#[derive(Debug, Clone)]
+pub struct NamedOutput {
+    name: String,
+    run_id: u64,
+    output: Output,
+    extras: Box<Extras>,
+}
+
+/// Everything about a run besides its name and [Output]
+///
+/// Boxed so that [NamedOutput], and every [CmdError] holding one, stays small.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Extras {
+    captured_lines: BTreeMap<String, Vec<String>>,
+    transcript: Vec<Exchange>,
+    /// Bytes sent to stdin, see [NamedOutput::stdin]
+    stdin: Option<Vec<u8>>,
+    stats: Option<OutputStats>,
+    /// Lines shown under the command name in errors, see [NamedOutput::with_failure_context]
+    context: Vec<String>,
+    /// Wording of the non-zero exit error, see [NamedOutput::with_error_template]
+    template: Option<ErrorTemplate>,
+    /// How the name is fit into a width in errors, see [NamedOutput::with_name_fit]
+    name_fit: Option<(NameFit, usize)>,
+    /// Shown after captured output in errors, see [NamedOutput::with_streaming_hint]
+    streaming_hint: Option<String>,
+    /// Time from spawn to exit measured by a [FunRunner]
+    elapsed: Option<std::time::Duration>,
+    #[cfg(feature = "encoding_rs")]
+    encoding: Option<&'static encoding_rs::Encoding>,
+}
+
+impl PartialEq for NamedOutput {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.output == other.output && self.extras == other.extras
+    }
+}
+
+impl Eq for NamedOutput {}
+
+static NEXT_RUN_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+/// A new run ID, taken right before a command is spawned and passed along to its result
+pub(crate) fn next_run_id() -> u64 {
+    NEXT_RUN_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+/// The run ID of a finished run, including a run whose command could not be spawned
+pub(crate) fn result_run_id(result: &Result<NamedOutput, CmdError>) -> Option<u64> {
+    match result {
+        Ok(named_output) => Some(named_output.run_id()),
+        Err(error) => error.run_id(),
+    }
+}
+
+impl NamedOutput {
+    /// Name an [Output] that was obtained some other way
+    ///
+    /// Useful for output from another library or a test double so it can be checked with
+    /// [NamedOutput::nonzero_captured] and friends.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::NamedOutput;
+    /// use std::os::unix::process::ExitStatusExt;
+    /// use std::process::{ExitStatus, Output};
+    ///
+    /// let output = Output {
+    ///     status: ExitStatus::from_raw(1 << 8),
+    ///     stdout: Vec::new(),
+    ///     stderr: b"no such task".to_vec(),
+    /// };
+    ///
+    /// let error = NamedOutput::new("rake deploy", output)
+    ///     .nonzero_captured()
+    ///     .unwrap_err();
+    /// assert!(error.to_string().contains("Command failed `rake deploy`"));
+    /// ```
+    #[must_use]
+    pub fn new(name: impl Into<String>, output: Output) -> Self {
+        Self::for_run(name, next_run_id(), output)
+    }
+
+    /// Name the [Output] of a run whose ID was taken before it was spawned
+    pub(crate) fn for_run(name: impl Into<String>, run_id: u64, output: Output) -> Self {
+        Self {
+            name: name.into(),
+            run_id,
+            output,
+            extras: Box::default(),
+        }
+    }
+
+    pub(crate) fn with_stats(mut self, stats: OutputStats) -> Self {
+        self.extras.stats = Some(stats);
+        self
+    }
+
+    /// Timing of the output, see [OutputStats]
+    ///
+    /// Only available for commands whose output was streamed, such as with
+    /// [CommandWithName::stream_output].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "echo downloading; sleep 0.3; echo done"])
+    ///     .stream_output(std::io::sink(), std::io::sink())
+    ///     .unwrap();
+    ///
+    /// let stats = output.stats().unwrap();
+    /// println!("{stats}");
+    /// assert!(stats.longest_silence() >= std::time::Duration::from_millis(250));
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> Option<OutputStats> {
+        self.extras.stats
+    }
+
+    /// How long was spent spawning, reading output and waiting for exit, see [PhaseTimings]
+    ///
+    /// Only available for commands whose output was streamed, such as with
+    /// [CommandWithName::stream_output].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "echo building; sleep 0.2"])
+    ///     .stream_output(std::io::sink(), std::io::sink())
+    ///     .unwrap();
+    ///
+    /// let phases = output.phases().unwrap();
+    /// println!("{phases}");
+    /// assert!(phases.read() >= std::time::Duration::from_millis(150));
+    /// ```
+    #[must_use]
+    pub fn phases(&self) -> Option<PhaseTimings> {
+        self.extras.stats.map(|stats| stats.phases())
+    }
+
+    /// Check status and convert into an error if nonzero (include output in error)
+    ///
+    /// Because the [NamedOutput] does not contain information about whether it was originally
+    /// streamed or not, use this associated function when the output has not been made
+    /// available to the user. This has the effect of showing it in the event of [CmdError].
+    ///
+    /// If the output was streamed to the user use [NamedOutput::nonzero_streamed]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status is not zero
+    pub fn nonzero_captured(self) -> Result<NamedOutput, CmdError> {
+        if self.status().success() {
+            Ok(self)
+        } else {
+            Err(CmdError::NonZeroExitNotStreamed(self))
+        }
+    }
+
+    /// Check status and convert into an error if nonzero (hide output in error)
+    ///
+    /// Because the [NamedOutput] does not contain information about whether it was originally
+    /// streamed or not, use this associated function when the output has was streamed to the user.
+    /// This has the effect of hiding the output in the event of [CmdError] to prevent including
+    /// duplicate information twice.
+    ///
+    /// If the output was not streamed to the user use [NamedOutput::nonzero_captured]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status is not zero
+    pub fn nonzero_streamed(self) -> Result<NamedOutput, CmdError> {
+        if self.status().success() {
+            Ok(self)
+        } else {
+            Err(CmdError::NonZeroExitAlreadyStreamed(self))
+        }
+    }
+
+    /// Check status and convert into an error if nonzero (hide stdout, include stderr in error)
+    ///
+    /// Use when only stdout was streamed to the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status is not zero
+    pub fn nonzero_stdout_streamed(self) -> Result<NamedOutput, CmdError> {
+        if self.status().success() {
+            Ok(self)
+        } else {
+            Err(CmdError::NonZeroExitStdoutStreamed(self))
+        }
+    }
+
+    /// Check status and convert into an error if nonzero (hide stderr, include stdout in error)
+    ///
+    /// Use when only stderr was streamed to the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status is not zero
+    pub fn nonzero_stderr_streamed(self) -> Result<NamedOutput, CmdError> {
+        if self.status().success() {
+            Ok(self)
+        } else {
+            Err(CmdError::NonZeroExitStderrStreamed(self))
+        }
+    }
+
+    /// Check status and convert into an error if nonzero (include stderr in error)
+    ///
+    /// Use when stdout was never captured, for example because it was sent to null.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status is not zero
+    pub fn nonzero_stdout_discarded(self) -> Result<NamedOutput, CmdError> {
+        if self.status().success() {
+            Ok(self)
+        } else {
+            Err(CmdError::NonZeroExitStdoutDiscarded(self))
+        }
+    }
+
+    /// Return the ExitStatus of the output
+    #[must_use]
+    pub fn status(&self) -> &ExitStatus {
+        &self.output.status
+    }
+
+    /// Returns true if the command exited successfully, see [ExitStatus::success]
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.output.status.success()
+    }
+
+    /// Return the exit code, `None` if the command was killed by a signal
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "exit 1"])
+    ///     .named_output()
+    ///     .unwrap_or_else(|error| error.into());
+    ///
+    /// assert!(!output.success());
+    /// assert_eq!(Some(1), output.code());
+    /// assert_eq!(None, output.signal());
+    /// ```
+    #[must_use]
+    pub fn code(&self) -> Option<i32> {
+        self.output.status.code()
+    }
+
+    /// Return the number of the signal that killed the command, if any
+    #[must_use]
+    pub fn signal(&self) -> Option<i32> {
+        self.output.status.signal()
+    }
+
+    /// Return raw stdout
+    #[must_use]
+    pub fn stdout(&self) -> &Vec<u8> {
+        &self.output.stdout
+    }
+
+    /// Return raw stderr
+    #[must_use]
+    pub fn stderr(&self) -> &Vec<u8> {
+        &self.output.stderr
+    }
+
+    /// Return stdout as a `&str` if it's valid UTF-8
+    ///
+    /// Unlike [NamedOutput::stdout_lossy] invalid bytes are an error rather than being
+    /// replaced. To get an error that includes the command name use [NamedOutput::try_stdout_utf8].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if stdout is not valid UTF-8
+    pub fn stdout_utf8(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.output.stdout)
+    }
+
+    /// Return stderr as a `&str` if it's valid UTF-8
+    ///
+    /// Unlike [NamedOutput::stderr_lossy] invalid bytes are an error rather than being
+    /// replaced. To get an error that includes the command name use [NamedOutput::try_stderr_utf8].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if stderr is not valid UTF-8
+    pub fn stderr_utf8(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.output.stderr)
+    }
+
+    /// Return stdout as a `&str` or a [CmdError] naming the command if it's not valid UTF-8
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let version = Command::new("echo")
+    ///     .arg("1.2.3")
+    ///     .named_output()
+    ///     .and_then(|output| output.try_stdout_utf8().map(|out| out.trim().to_string()))
+    ///     .unwrap();
+    ///
+    /// assert_eq!("1.2.3", version);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::InvalidUtf8` if stdout is not valid UTF-8
+    pub fn try_stdout_utf8(&self) -> Result<&str, CmdError> {
+        self.stdout_utf8()
+            .map_err(|error| CmdError::InvalidUtf8(self.clone(), Stream::Stdout, error))
+    }
+
+    /// Return stderr as a `&str` or a [CmdError] naming the command if it's not valid UTF-8
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "printf 'caf\\xe9' >&2"])
+    ///     .named_output()
+    ///     .unwrap();
+    ///
+    /// let error = output.try_stderr_utf8().unwrap_err();
+    /// assert!(error.to_string().starts_with("Invalid UTF-8 in stderr of command `bash -c"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::InvalidUtf8` if stderr is not valid UTF-8
+    pub fn try_stderr_utf8(&self) -> Result<&str, CmdError> {
+        self.stderr_utf8()
+            .map_err(|error| CmdError::InvalidUtf8(self.clone(), Stream::Stderr, error))
+    }
+
+    /// Parse trimmed stdout into a value via [std::str::FromStr]
+    ///
+    /// When an encoding was set with `NamedOutput::with_encoding` it's used to decode
+    /// stdout instead of UTF-8.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let count = Command::new("echo")
+    ///     .arg("42")
+    ///     .named_output()
+    ///     .and_then(|output| output.parse_stdout::<u32>())
+    ///     .unwrap();
+    /// assert_eq!(42, count);
+    ///
+    /// let error = Command::new("echo")
+    ///     .arg("forty-two")
+    ///     .named_output()
+    ///     .and_then(|output| output.parse_stdout::<u32>())
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     "Could not parse stdout of command `echo forty-two`. invalid digit found in string\nstdout: forty-two\n",
+    ///     error.to_string()
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::InvalidUtf8` if stdout is not valid UTF-8 and no encoding was set,
+    /// and `CmdError::ParseError` if the trimmed contents cannot be parsed.
+    pub fn parse_stdout<T>(&self) -> Result<T, CmdError>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let parse = |contents: &str| {
+            contents
+                .trim()
+                .parse()
+                .map_err(|error| CmdError::ParseError(self.clone(), Box::new(error)))
+        };
+
+        #[cfg(feature = "encoding_rs")]
+        if let Some(encoding) = self.extras.encoding {
+            return parse(&encoding.decode(&self.output.stdout).0);
+        }
+
+        parse(self.try_stdout_utf8()?)
+    }
+
+    /// Number of bytes captured from stdout
+    ///
+    /// Zero when stdout was not captured, such as with
+    /// [CommandWithName::discard_stdout_output].
+    #[must_use]
+    pub fn stdout_bytes(&self) -> usize {
+        self.output.stdout.len()
+    }
+
+    /// Number of bytes captured from stderr
+    #[must_use]
+    pub fn stderr_bytes(&self) -> usize {
+        self.output.stderr.len()
+    }
+
+    /// Return lossy stdout as a String
+    ///
+    /// When an encoding was set with `NamedOutput::with_encoding` it's used to decode
+    /// the bytes instead of UTF-8.
+    #[must_use]
+    pub fn stdout_lossy(&self) -> String {
+        self.decode_lossy(&self.output.stdout)
+    }
+
+    /// Return lossy stderr as a String
+    ///
+    /// When an encoding was set with `NamedOutput::with_encoding` it's used to decode
+    /// the bytes instead of UTF-8.
+    #[must_use]
+    pub fn stderr_lossy(&self) -> String {
+        self.decode_lossy(&self.output.stderr)
+    }
+
+    /// Return lossy stdout split into lines, without `\n` or `\r\n` line endings
+    ///
+    /// Decodes like [NamedOutput::stdout_lossy].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("printf")
+    ///     .arg("one\r\ntwo\nthree")
+    ///     .named_output()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(vec!["one", "two", "three"], output.stdout_lines());
+    /// assert_eq!(Some("three".into()), output.stdout_lines_iter().last());
+    /// ```
+    #[must_use]
+    pub fn stdout_lines(&self) -> Vec<String> {
+        self.stdout_lossy().lines().map(String::from).collect()
+    }
+
+    /// Return lossy stderr split into lines, without `\n` or `\r\n` line endings
+    ///
+    /// Decodes like [NamedOutput::stderr_lossy].
+    #[must_use]
+    pub fn stderr_lines(&self) -> Vec<String> {
+        self.stderr_lossy().lines().map(String::from).collect()
+    }
+
+    /// Iterate over lines of stdout without copying, see [NamedOutput::stdout_lines]
+    ///
+    /// Each line is decoded as UTF-8 on its own and only copied if it contains invalid bytes.
+    /// An encoding set with `NamedOutput::with_encoding` is not used, call
+    /// [NamedOutput::stdout_lines] instead.
+    pub fn stdout_lines_iter(&self) -> impl Iterator<Item = std::borrow::Cow<'_, str>> {
+        lines_lossy(&self.output.stdout)
+    }
+
+    /// Iterate over lines of stderr without copying, see [NamedOutput::stdout_lines_iter]
+    pub fn stderr_lines_iter(&self) -> impl Iterator<Item = std::borrow::Cow<'_, str>> {
+        lines_lossy(&self.output.stderr)
+    }
+
+    /// Decode output using the given encoding in `stdout_lossy`, `stderr_lossy`, `parse_stdout`
+    /// and error displays
+    ///
+    /// Many Windows tools emit text in the OEM code page or as UTF-16. A byte order mark
+    /// in the output takes precedence over the given encoding.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "printf 'caf\\xe9'"])
+    ///     .named_output()
+    ///     .map(|output| output.with_encoding(encoding_rs::WINDOWS_1252))
+    ///     .map_err(|error| error.with_encoding(encoding_rs::WINDOWS_1252))
+    ///     .unwrap();
+    ///
+    /// assert_eq!("café", output.stdout_lossy());
+    /// assert_eq!("café", output.parse_stdout::<String>().unwrap());
+    /// ```
+    #[must_use]
+    #[cfg(feature = "encoding_rs")]
+    pub fn with_encoding(mut self, encoding: &'static encoding_rs::Encoding) -> Self {
+        self.extras.encoding = Some(encoding);
+        self
+    }
+
+    /// Replace `\r\n` line endings with `\n` in the captured stdout and stderr
+    ///
+    /// Affects `stdout_lossy`, the line accessors and error displays. Output that was already
+    /// streamed is left as it was written. Useful for comparing output from tools that write
+    /// Windows line endings against golden files.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let error = Command::new("bash")
+    ///     .args(["-c", "printf 'one\\r\\ntwo\\r\\n'; printf 'oops\\r\\n' >&2; exit 1"])
+    ///     .named_output()
+    ///     .map(|output| output.normalize_line_endings())
+    ///     .map_err(|error| error.normalize_line_endings())
+    ///     .unwrap_err();
+    ///
+    /// let output = fun_run::NamedOutput::from(error);
+    /// assert_eq!("one\ntwo\n", output.stdout_lossy());
+    /// assert_eq!("oops\n", output.stderr_lossy());
+    /// ```
+    #[must_use]
+    pub fn normalize_line_endings(mut self) -> Self {
+        normalize_line_endings(&mut self.output.stdout);
+        normalize_line_endings(&mut self.output.stderr);
+        self
+    }
+
+    /// Keep only the final state of lines rewritten with `\r` in the captured stdout and stderr
+    ///
+    /// Progress bars from tools such as `pip` or `docker pull` redraw a line by writing `\r`
+    /// and the new text, leaving thousands of updates in the capture and in error messages.
+    /// This keeps the text after the last `\r` of each line, like a terminal would show it.
+    /// `\r\n` line endings are kept. Output that was already streamed is left as it was
+    /// written.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("printf")
+    ///     .arg("Downloading\n 10%%\r 50%%\r100%%\nDone\n")
+    ///     .named_output()
+    ///     .map(|output| output.collapse_carriage_returns())
+    ///     .map_err(|error| error.collapse_carriage_returns())
+    ///     .unwrap();
+    ///
+    /// assert_eq!("Downloading\n100%\nDone\n", output.stdout_lossy());
+    /// ```
+    #[must_use]
+    pub fn collapse_carriage_returns(mut self) -> Self {
+        collapse_carriage_returns(&mut self.output.stdout);
+        collapse_carriage_returns(&mut self.output.stderr);
+        self
+    }
+
+    /// Show the working directory and environment variables of the command in error displays
+    ///
+    /// Lines for the working directory and the `env_keys` that are set are added under the
+    /// command name, so the error alone is enough to reproduce the failure. Values set on the
+    /// command take precedence over the parent environment, which is assumed to be inherited
+    /// even after [Command::env_clear]. Only include keys that are safe to show, see
+    /// [FunRunner::failure_context] to do this for every run.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let mut command = Command::new("false");
+    /// command.current_dir("/tmp").env("RAILS_ENV", "production");
+    ///
+    /// let error = command
+    ///     .named_output()
+    ///     .map_err(|error| error.with_failure_context(&command, ["RAILS_ENV", "NOT_SET_ANYWHERE"]))
+    ///     .unwrap_err();
+    ///
+    /// assert!(error
+    ///     .to_string()
+    ///     .starts_with("Command failed `false`\ncwd: /tmp\nenv: RAILS_ENV=production\nexit status: 1"));
+    /// ```
+    #[must_use]
+    pub fn with_failure_context<I, K>(mut self, command: &Command, env_keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<OsStr>,
+    {
+        let cwd = match command.get_current_dir() {
+            Some(dir) if dir.is_absolute() => Some(dir.to_path_buf()),
+            Some(dir) => std::env::current_dir().ok().map(|cwd| cwd.join(dir)),
+            None => std::env::current_dir().ok(),
+        };
+        let env = env_keys
+            .into_iter()
+            .filter_map(|key| {
+                let key = key.as_ref();
+                command::env_value(command, key).map(|value| {
+                    format!(
+                        "{key}={value}",
+                        key = key.to_string_lossy(),
+                        value = Quoting::Posix.quote(&value)
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        self.extras.context.clear();
+        if let Some(cwd) = cwd {
+            self.extras.context.push(format!("cwd: {}", cwd.display()));
+        }
+        if !env.is_empty() {
+            self.extras.context.push(format!("env: {}", env.join(" ")));
+        }
+        self
+    }
+
+    /// Reword the error shown when the command exits with a non-zero status
+    ///
+    /// See [ErrorTemplate] for placeholders and [FunRunner::error_template] to do this for
+    /// every run.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, ErrorTemplate, NamedOutput};
+    /// use std::process::Command;
+    ///
+    /// let output = NamedOutput::from(Command::new("false").named_output().unwrap_err());
+    ///
+    /// let error = output
+    ///     .with_error_template(ErrorTemplate::new("Build step failed `{name}`"))
+    ///     .nonzero_captured()
+    ///     .unwrap_err();
+    /// assert_eq!("Build step failed `false`", error.to_string());
+    /// ```
+    #[must_use]
+    pub fn with_error_template(mut self, template: impl Into<ErrorTemplate>) -> Self {
+        self.extras.template = Some(template.into());
+        self
+    }
+
+    /// Fit the name into `width` characters in the non-zero exit error
+    ///
+    /// Keeps long commands from breaking the layout of a terminal, use
+    /// [TerminalSize::detect] for its width. Only the error display changes, [NamedOutput::name]
+    /// is left as is.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, NameFit, NamedOutput};
+    /// use std::process::Command;
+    ///
+    /// let output = NamedOutput::from(
+    ///     Command::new("bash")
+    ///         .args(["-c", "exit 1", "--", "spec/models/user_spec.rb"])
+    ///         .named_output()
+    ///         .unwrap_err(),
+    /// );
+    ///
+    /// let error = output
+    ///     .with_name_fit(NameFit::Truncate, 21)
+    ///     .nonzero_captured()
+    ///     .unwrap_err();
+    /// assert!(error.to_string().starts_with("Command failed `bash -c \"e…er_spec.rb`"));
+    /// assert_eq!("bash -c \"exit 1\" -- spec/models/user_spec.rb", error.name());
+    /// ```
+    #[must_use]
+    pub fn with_name_fit(mut self, fit: NameFit, width: usize) -> Self {
+        self.extras.name_fit = Some((fit, width));
+        self
+    }
+
+    /// Tell users how to see the full output when the command fails with it captured
+    ///
+    /// The hint is shown on its own line after the `NonZeroExitNotStreamed` and
+    /// `NonZeroExitStdoutDiscarded` errors, such as to point at the verbose flag of your CLI.
+    /// Errors for streamed output are unchanged since it was already shown. Custom templates
+    /// show it where they use `{hint}`, see [ErrorTemplate]. See [FunRunner::streaming_hint]
+    /// to do this for every run.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, NamedOutput};
+    /// use std::process::Command;
+    ///
+    /// let output = NamedOutput::from(Command::new("false").named_output().unwrap_err());
+    ///
+    /// let error = output
+    ///     .with_streaming_hint("re-run with --verbose to stream full output")
+    ///     .nonzero_captured()
+    ///     .unwrap_err();
+    /// assert!(error
+    ///     .to_string()
+    ///     .ends_with("stderr: <empty>\nhint: re-run with --verbose to stream full output"));
+    /// ```
+    #[must_use]
+    pub fn with_streaming_hint(mut self, hint: impl Into<String>) -> Self {
+        self.extras.streaming_hint = Some(hint.into());
+        self
+    }
+
+    /// The name as shown in errors, see [NamedOutput::with_name_fit]
+    pub(crate) fn fitted_name(&self) -> String {
+        match self.extras.name_fit {
+            Some((fit, width)) => fit.apply(&self.name, width),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Time from spawn to exit, when it was measured
+    pub(crate) fn duration(&self) -> Option<std::time::Duration> {
+        self.extras
+            .elapsed
+            .or_else(|| self.extras.stats.map(|stats| stats.duration()))
+    }
+
+    /// Record the time from spawn to exit
+    pub(crate) fn with_elapsed(mut self, elapsed: std::time::Duration) -> Self {
+        self.extras.elapsed = Some(elapsed);
+        self
+    }
+
+    fn decode_lossy(&self, contents: &[u8]) -> String {
+        #[cfg(feature = "encoding_rs")]
+        if let Some(encoding) = self.extras.encoding {
+            return encoding.decode(contents).0.into_owned();
+        }
+
+        String::from_utf8_lossy(contents).to_string()
+    }
+
+    /// Return name of the command that was run
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return name of the command that was run, taking ownership of it
+    #[must_use]
+    pub fn into_name(self) -> String {
+        self.name
+    }
+
+    /// Return an ID unique to this run within the process
+    ///
+    /// Use it to correlate logs, errors and the [FunRunner] audit log for the same run when
+    /// many commands share a name. The ID is assigned when the command is spawned, so it's
+    /// known while a [NamedChild] is still running, see [NamedChild::run_id]. It is not
+    /// compared when checking two outputs for equality.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let first = Command::new("true").named_output().unwrap();
+    /// let second = Command::new("true").named_output().unwrap();
+    ///
+    /// assert_ne!(first.run_id(), second.run_id());
+    /// ```
+    #[must_use]
+    pub fn run_id(&self) -> u64 {
+        self.run_id
+    }
+
+    /// Return lines that matched the pattern registered under `name` in [LineCaptures]
+    ///
+    /// Returns an empty slice if no lines matched or no such pattern was registered.
+    #[must_use]
+    pub fn captured_lines(&self, name: &str) -> &[String] {
+        self.extras
+            .captured_lines
+            .get(name)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Prompts matched and responses sent by [CommandWithName::interact], in order
+    #[must_use]
+    pub fn transcript(&self) -> &[Exchange] {
+        &self.extras.transcript
+    }
+
+    /// Bytes sent to the command's stdin, when recorded with [Interaction::record_input]
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, Interaction};
+    /// use regex::Regex;
+    /// use std::process::Command;
+    ///
+    /// let output = Command::new("bash")
+    ///     .args(["-c", "printf 'Name: ' && read name && echo \"hello $name\""])
+    ///     .interact(
+    ///         Interaction::new()
+    ///             .expect(Regex::new("Name: $").unwrap(), "Richard")
+    ///             .record_input(),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(Some(&b"Richard\n"[..]), output.stdin());
+    /// ```
+    #[must_use]
+    pub fn stdin(&self) -> Option<&[u8]> {
+        self.extras.stdin.as_deref()
+    }
+
+    /// Return reference of the original [Output]
+    #[must_use]
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+}
+
+/// A prompt that was matched and the response that was sent to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// The output that matched the prompt
+    pub prompt: String,
+    /// The line sent in response, without a trailing newline
+    pub response: String,
+}
+
+/// Details about the line that caused a command to be aborted
+///
+/// See [crate::CommandWithName::stream_output_abort_on]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputMatch {
+    /// The stream the line was written to
+    pub stream: Stream,
+    /// The pattern that matched
+    pub pattern: String,
+    /// The matching line, without a trailing newline
+    pub line: String,
+}
+
+/// One of the two output streams of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Stdout => f.write_str("stdout"),
+            Stream::Stderr => f.write_str("stderr"),
+        }
+    }
+}
+
+impl AsRef<Output> for NamedOutput {
+    fn as_ref(&self) -> &Output {
+        &self.output
+    }
+}
+
+impl<'a> From<&'a NamedOutput> for &'a Output {
+    fn from(value: &'a NamedOutput) -> Self {
+        &value.output
+    }
+}
+
+impl From<NamedOutput> for Output {
+    fn from(value: NamedOutput) -> Self {
+        value.output
+    }
+}
+
+/// Access the fields of the original [Output] directly
+///
+/// Example:
+///
+/// ```
+/// use fun_run::CommandWithName;
+/// use std::process::Command;
+///
+/// let output = Command::new("echo").arg("hello").named_output().unwrap();
+///
+/// assert!(output.status.success());
+/// assert_eq!(b"hello\n".to_vec(), output.stdout);
+/// ```
+impl std::ops::Deref for NamedOutput {
+    type Target = Output;
+
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
+
+impl std::ops::DerefMut for NamedOutput {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.output
+    }
+}
+
+/// Joins directories with `:`, skipping empty ones so the current directory is never added
+///
+/// Directories containing `:` are not escaped.
+fn join_dirs<I, P>(dirs: I) -> OsString
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<std::path::Path>,
+{
+    dirs.into_iter()
+        .map(|dir| dir.as_ref().as_os_str().to_os_string())
+        .filter(|dir| !dir.is_empty())
+        .collect::<Vec<_>>()
+        .join(OsStr::new(":"))
+}
+
+/// Replace `\r\n` with `\n` in place
+fn normalize_line_endings(contents: &mut Vec<u8>) {
+    let mut len = 0;
+    for index in 0..contents.len() {
+        if contents[index] == b'\r' && contents.get(index + 1) == Some(&b'\n') {
+            continue;
+        }
+        contents[len] = contents[index];
+        len += 1;
+    }
+    contents.truncate(len);
+}
+
+/// Keep the text after the last `\r` of each line
+///
+/// Trailing `\r`s are ignored so a final redraw without a newline is kept.
+fn collapse_carriage_returns(contents: &mut Vec<u8>) {
+    let mut collapsed = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        let (body, ending) = if let Some(body) = line.strip_suffix(b"\r\n") {
+            (body, &b"\r\n"[..])
+        } else if let Some(body) = line.strip_suffix(b"\n") {
+            (body, &b"\n"[..])
+        } else {
+            (line, &b""[..])
+        };
+        let last = body
+            .rsplit(|byte| *byte == b'\r')
+            .find(|segment| !segment.is_empty())
+            .unwrap_or_default();
+        collapsed.extend_from_slice(last);
+        collapsed.extend_from_slice(ending);
+    }
+    *contents = collapsed;
+}
+
+/// Splits bytes into lossy UTF-8 lines, dropping `\n` and `\r\n` line endings
+fn lines_lossy(contents: &[u8]) -> impl Iterator<Item = std::borrow::Cow<'_, str>> {
+    contents
+        .strip_suffix(b"\n")
+        .unwrap_or(contents)
+        .split(|byte| *byte == b'\n')
+        .filter(move |_| !contents.is_empty())
+        .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)))
+}
+
+/// Returns true if an argument needs quotes to be read back as a single word
+///
+/// Any byte outside of `A-Za-z0-9_-.,:/@` and newline needs quoting, see
+/// <https://github.com/jimmycuadra/rust-shellwords/blob/d23b853a850ceec358a4137d5e520b067ddb7abc/src/lib.rs#L23>
+fn needs_debug_quote(arg: &str) -> bool {
+    arg.bytes()
+        .any(|b| !(b.is_ascii_alphanumeric() || b"_-.,:/@\n".contains(&b)))
+}
+
+/// Converts a command and its arguments into a user readable string
+///
+/// Example
+///
+/// ```rust
+/// use std::process::Command;
+/// use fun_run;
+///
+/// let name = fun_run::display(Command::new("bundle").arg("install"));
+/// assert_eq!(String::from("bundle install"), name);
+/// ```
+#[must_use]
+pub fn display(command: &mut Command) -> String {
+    display_with(command, Quoting::Debug)
+}
+
+/// Displays the command with the keys of every environment variable set on it, values
+/// masked, see [CommandWithName::log_name]
+fn display_with_set_env_keys(command: &mut Command) -> String {
+    let mut name = command
+        .get_envs()
+        .filter(|(_, value)| value.is_some())
+        .map(|(key, _)| format!("{}=**** ", key.to_string_lossy()))
+        .collect::<String>();
+    name.push_str(&display(command));
+    name
+}
 
-    /// Return lossy stderr as a String
-    #[must_use]
-    pub fn stderr_lossy(&self) -> String {
-        String::from_utf8_lossy(&self.output.stderr).to_string()
+/// Displays the command with every environment variable set on it, values included, see
+/// [NamedCommand::with_env_values_in_log_name]
+fn display_with_set_env(command: &mut Command) -> String {
+    let env = command
+        .get_envs()
+        .filter_map(|(key, value)| value.map(|value| (key.to_owned(), value.to_owned())))
+        .collect::<Vec<_>>();
+    let keys = env.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+    display_with_env_keys(command, env, keys)
+}
+
+/// How arguments are quoted when displaying a command, see [display_with]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quoting {
+    /// Quote empty arguments and those containing special characters with Rust debug quotes,
+    /// used by [display]
+    #[default]
+    Debug,
+    /// No quoting at all, the program and arguments are joined with spaces verbatim
+    ///
+    /// For logging or telemetry systems that do their own escaping. The result may not be
+    /// readable as a single command when arguments contain spaces.
+    Raw,
+    /// Quote with POSIX single quotes so the command pastes correctly into `sh` or `bash`
+    ///
+    /// Inside single quotes nothing is special, so `$`, backslashes and double quotes are shown
+    /// as is. A single quote is written as `'\''`. The program is quoted as well.
+    ///
+    /// Arguments that are not valid UTF-8 are written with `$'...'` quoting and invalid
+    /// bytes escaped as `\xNN`, so the displayed command runs with the exact same bytes.
+    Posix,
+}
+
+impl Quoting {
+    fn quote(self, arg: &OsStr) -> String {
+        let mut quoted = String::new();
+        self.push_quoted(&mut quoted, arg);
+        quoted
     }
 
-    /// Return name of the command that was run
-    #[must_use]
-    pub fn name(&self) -> String {
-        self.name.clone()
+    fn push_quoted(self, out: &mut String, arg: &OsStr) {
+        match (self, arg.to_str()) {
+            (Quoting::Posix, None) => ansi_c_quote(out, arg.as_bytes()),
+            (_, Some(arg)) => self.push_quoted_str(out, arg),
+            (Quoting::Debug | Quoting::Raw, None) => {
+                self.push_quoted_str(out, &arg.to_string_lossy());
+            }
+        }
     }
 
-    /// Return reference of the original [Output]
-    #[must_use]
-    pub fn output(&self) -> &Output {
-        &self.output
+    fn push_quoted_str(self, out: &mut String, arg: &str) {
+        match self {
+            Quoting::Debug if arg.is_empty() || needs_debug_quote(arg) => {
+                let _ = std::fmt::Write::write_fmt(out, format_args!("{arg:?}"));
+            }
+            Quoting::Posix if arg.is_empty() || arg.chars().any(needs_posix_quote) => {
+                out.push('\'');
+                for c in arg.chars() {
+                    match c {
+                        '\'' => out.push_str(r"'\''"),
+                        c => out.push(c),
+                    }
+                }
+                out.push('\'');
+            }
+            Quoting::Debug | Quoting::Raw | Quoting::Posix => out.push_str(arg),
+        }
     }
 }
 
-impl AsRef<Output> for NamedOutput {
-    fn as_ref(&self) -> &Output {
-        &self.output
-    }
+fn needs_posix_quote(c: char) -> bool {
+    !(c.is_ascii_alphanumeric() || "_-.,:/@=+%".contains(c))
 }
 
-impl<'a> From<&'a NamedOutput> for &'a Output {
-    fn from(value: &'a NamedOutput) -> Self {
-        &value.output
+/// Quotes bytes as `$'...'`, escaping bytes that are not valid UTF-8 as `\xNN`
+fn ansi_c_quote(out: &mut String, bytes: &[u8]) {
+    out.push_str("$'");
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' | '\'' => out.extend(['\\', c]),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => {
+                    let _ =
+                        std::fmt::Write::write_fmt(out, format_args!("\\x{:02x}", u32::from(c)));
+                }
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = std::fmt::Write::write_fmt(out, format_args!("\\x{byte:02x}"));
+        }
     }
+    out.push('\'');
 }
 
-impl From<NamedOutput> for Output {
-    fn from(value: NamedOutput) -> Self {
-        value.output
+/// Like [display] with a choice of how arguments are quoted
+///
+/// Example
+///
+/// ```rust
+/// use std::process::Command;
+/// use fun_run::{self, Quoting};
+///
+/// let mut command = Command::new("git");
+/// command.args(["commit", "-m", "Fix \"quoted\" thing"]);
+///
+/// assert_eq!(
+///     r#"git commit -m "Fix \"quoted\" thing""#,
+///     fun_run::display_with(&mut command, Quoting::Debug)
+/// );
+/// assert_eq!(
+///     r#"git commit -m Fix "quoted" thing"#,
+///     fun_run::display_with(&mut command, Quoting::Raw)
+/// );
+///
+/// command.arg("it's $HOME");
+/// assert_eq!(
+///     r#"git commit -m 'Fix "quoted" thing' 'it'\''s $HOME'"#,
+///     fun_run::display_with(&mut command, Quoting::Posix)
+/// );
+///
+/// use std::ffi::OsStr;
+/// use std::os::unix::ffi::OsStrExt;
+///
+/// let mut command = Command::new("cat");
+/// command.arg(OsStr::from_bytes(b"caf\xe9 it's.txt"));
+/// assert_eq!(
+///     r#"cat $'caf\xe9 it\'s.txt'"#,
+///     fun_run::display_with(&mut command, Quoting::Posix)
+/// );
+/// ```
+#[must_use]
+pub fn display_with(command: &mut Command, quoting: Quoting) -> String {
+    render(command, quoting)
+}
+
+/// [display_with] for when only a shared reference is available
+fn render(command: &Command, quoting: Quoting) -> String {
+    let program = command.get_program();
+    let capacity = command
+        .get_args()
+        .fold(program.len(), |len, arg| len + arg.len() + 1);
+    let mut display = String::with_capacity(capacity);
+
+    match quoting {
+        Quoting::Posix => quoting.push_quoted(&mut display, program),
+        Quoting::Debug | Quoting::Raw => display.push_str(&program.to_string_lossy()),
     }
+    for arg in command.get_args() {
+        display.push(' ');
+        quoting.push_quoted(&mut display, arg);
+    }
+    display
 }
 
-// https://github.com/jimmycuadra/rust-shellwords/blob/d23b853a850ceec358a4137d5e520b067ddb7abc/src/lib.rs#L23
-static QUOTE_ARG_RE: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"([^A-Za-z0-9_\-.,:/@\n])").expect("clippy checked"));
+/// Masks every occurrence of the secret values in a displayed command with `****`
+///
+/// Secrets are matched as given and as they appear after [Quoting::Debug] or [Quoting::Posix]
+/// quoting. Empty secrets are ignored.
+///
+/// Example
+///
+/// ```rust
+/// let name = r#"curl -H "Authorization: token abc123" https://example.com"#;
+///
+/// assert_eq!(
+///     r#"curl -H "Authorization: token ****" https://example.com"#,
+///     fun_run::redact(name, ["abc123"])
+/// );
+/// ```
+#[must_use]
+pub fn redact<I, S>(name: &str, secrets: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut forms = secrets
+        .into_iter()
+        .map(|secret| secret.as_ref().to_string())
+        .filter(|secret| !secret.is_empty())
+        .flat_map(|secret| {
+            let debug = format!("{secret:?}");
+            let debug = debug[1..debug.len() - 1].to_string();
+            let posix = secret.replace('\'', r"'\''");
+            [secret, debug, posix]
+        })
+        .collect::<Vec<String>>();
+    forms.sort_by_key(|form| std::cmp::Reverse(form.len()));
+
+    forms.iter().fold(name.to_string(), |name, form| {
+        name.replace(form.as_str(), "****")
+    })
+}
 
-/// Converts a command and its arguments into a user readable string
+/// Copies a command so it can be run again, for retries or running in parallel
+///
+/// The program, arguments, environment changes and working directory are copied. Settings
+/// that `Command` does not expose are not: stdio, `env_clear`, and unix extensions such as
+/// `uid` or `pre_exec`.
 ///
 /// Example
 ///
@@ -333,31 +2553,36 @@ static QUOTE_ARG_RE: LazyLock<Regex> =
 /// use std::process::Command;
 /// use fun_run;
 ///
-/// let name = fun_run::display(Command::new("bundle").arg("install"));
-/// assert_eq!(String::from("bundle install"), name);
+/// let mut command = Command::new("bundle");
+/// command.arg("install").env("RAILS_ENV", "production").env_remove("DEBUG");
+///
+/// let copy = fun_run::clone_command(&command);
+/// assert_eq!(command.get_program(), copy.get_program());
+/// assert!(command.get_args().eq(copy.get_args()));
+/// assert!(command.get_envs().eq(copy.get_envs()));
 /// ```
 #[must_use]
-pub fn display(command: &mut Command) -> String {
-    vec![command.get_program().to_string_lossy().to_string()]
-        .into_iter()
-        .chain(
-            command
-                .get_args()
-                .map(std::ffi::OsStr::to_string_lossy)
-                .map(|arg| {
-                    if QUOTE_ARG_RE.is_match(&arg) {
-                        format!("{arg:?}")
-                    } else {
-                        format!("{arg}")
-                    }
-                }),
-        )
-        .collect::<Vec<String>>()
-        .join(" ")
+pub fn clone_command(command: &Command) -> Command {
+    let mut copy = Command::new(command.get_program());
+    copy.args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => copy.env(key, value),
+            None => copy.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        copy.current_dir(dir);
+    }
+    copy
 }
 
 /// Converts a command, arguments, and specified environment variables to user readable string
 ///
+/// Environment values are quoted the same way as arguments. Keys missing from `env` are shown
+/// as `KEY=<unset>`, see [display_with_env_keys_using] to choose the [Quoting] or omit them
+/// and [unset_env_keys] to find them.
+///
 /// Example
 ///
 /// ```rust
@@ -372,10 +2597,67 @@ pub fn display(command: &mut Command) -> String {
 /// command.arg("install").envs(&env);
 ///
 /// let name = fun_run::display_with_env_keys(&mut command, &env, ["RAILS_ENV"]);
-/// assert_eq!(String::from(r#"RAILS_ENV="production" bundle install"#), name);
+/// assert_eq!(String::from("RAILS_ENV=production bundle install"), name);
 /// ```
 #[must_use]
 pub fn display_with_env_keys<E, K, V, I, O>(cmd: &mut Command, env: E, keys: I) -> String
+where
+    E: IntoIterator<Item = (K, V)>,
+    K: Into<OsString>,
+    V: Into<OsString>,
+    I: IntoIterator<Item = O>,
+    O: Into<OsString>,
+{
+    display_with_env_keys_using(cmd, env, keys, Quoting::Debug, UnsetEnv::Show)
+}
+
+/// How [display_with_env_keys_using] shows keys missing from the env
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsetEnv {
+    /// Show the key as `KEY=<unset>`, distinct from an empty `KEY=""`
+    ///
+    /// With [Quoting::Posix] the command is prefixed with `env -u KEY` instead so it still
+    /// pastes into a shell.
+    #[default]
+    Show,
+    /// Leave the key out
+    Omit,
+}
+
+/// Like [display_with_env_keys] with a choice of quoting and how to show unset keys
+///
+/// Example
+///
+/// ```rust
+/// use std::process::Command;
+/// use fun_run::{self, Quoting, UnsetEnv};
+///
+/// let env = [("GREETING", "hello world"), ("EMPTY", "")];
+/// let keys = ["GREETING", "EMPTY", "MISSING"];
+/// let mut command = Command::new("echo");
+/// command.arg("$GREETING").envs(env);
+///
+/// assert_eq!(
+///     r#"GREETING="hello world" EMPTY="" MISSING=<unset> echo "$GREETING""#,
+///     fun_run::display_with_env_keys_using(&mut command, env, keys, Quoting::Debug, UnsetEnv::Show)
+/// );
+/// assert_eq!(
+///     "GREETING='hello world' EMPTY='' echo '$GREETING'",
+///     fun_run::display_with_env_keys_using(&mut command, env, keys, Quoting::Posix, UnsetEnv::Omit)
+/// );
+/// assert_eq!(
+///     "env -u MISSING GREETING='hello world' EMPTY='' echo '$GREETING'",
+///     fun_run::display_with_env_keys_using(&mut command, env, keys, Quoting::Posix, UnsetEnv::Show)
+/// );
+/// ```
+#[must_use]
+pub fn display_with_env_keys_using<E, K, V, I, O>(
+    cmd: &mut Command,
+    env: E,
+    keys: I,
+    quoting: Quoting,
+    unset: UnsetEnv,
+) -> String
 where
     E: IntoIterator<Item = (K, V)>,
     K: Into<OsString>,
@@ -388,20 +2670,69 @@ where
         .map(|(k, v)| (k.into(), v.into()))
         .collect::<std::collections::HashMap<OsString, OsString>>();
 
-    keys.into_iter()
-        .map(|key| {
+    // `KEY=<unset>` would be a redirection in a shell
+    let mut env_unset = Vec::new();
+    let assignments = keys
+        .into_iter()
+        .filter_map(|key| {
             let key = key.into();
-            format!(
-                "{}={:?}",
-                key.to_string_lossy(),
-                env.get(&key).cloned().unwrap_or_else(|| OsString::from(""))
-            )
+            match (env.get(&key), unset) {
+                (Some(value), _) => Some(format!(
+                    "{}={}",
+                    key.to_string_lossy(),
+                    quoting.quote(value)
+                )),
+                (None, UnsetEnv::Show) if quoting == Quoting::Posix => {
+                    env_unset.push(format!("-u {}", quoting.quote(&key)));
+                    None
+                }
+                (None, UnsetEnv::Show) => Some(format!("{}=<unset>", key.to_string_lossy())),
+                (None, UnsetEnv::Omit) => None,
+            }
         })
-        .chain([display(cmd)])
+        .collect::<Vec<String>>();
+
+    let prefix = (!env_unset.is_empty()).then(|| format!("env {}", env_unset.join(" ")));
+    prefix
+        .into_iter()
+        .chain(assignments)
+        .chain([display_with(cmd, quoting)])
         .collect::<Vec<String>>()
         .join(" ")
 }
 
+/// Returns the keys that are missing from `env`, in order
+///
+/// Example
+///
+/// ```rust
+/// let env = [("RAILS_ENV", "production")];
+///
+/// assert_eq!(
+///     vec![std::ffi::OsString::from("DATABASE_URL")],
+///     fun_run::unset_env_keys(env, ["RAILS_ENV", "DATABASE_URL"])
+/// );
+/// ```
+#[must_use]
+pub fn unset_env_keys<E, K, V, I, O>(env: E, keys: I) -> Vec<OsString>
+where
+    E: IntoIterator<Item = (K, V)>,
+    K: Into<OsString>,
+    V: Into<OsString>,
+    I: IntoIterator<Item = O>,
+    O: Into<OsString>,
+{
+    let env = env
+        .into_iter()
+        .map(|(k, _)| k.into())
+        .collect::<std::collections::HashSet<OsString>>();
+
+    keys.into_iter()
+        .map(Into::into)
+        .filter(|key| !env.contains(key))
+        .collect()
+}
+
 /// Who says (`Command`) errors can't be fun?
 ///
 /// Fun run errors include all the info a user needs to debug, like
@@ -413,84 +2744,568 @@ where
 ///
 /// Enjoy if you want, skip if you don't. Fun run errors are not mandatory.
 ///
-/// Error output formatting is unstable
+/// Error output formatting is unstable. New variants may be added in minor releases, so
+/// matches need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 #[allow(clippy::module_name_repetitions)]
 pub enum CmdError {
-    SystemError(String, std::io::Error),
+    SystemError(String, std::io::Error, Option<u64>),
 
     NonZeroExitNotStreamed(NamedOutput),
 
     NonZeroExitAlreadyStreamed(NamedOutput),
+
+    NonZeroExitStdoutStreamed(NamedOutput),
+
+    NonZeroExitStderrStreamed(NamedOutput),
+
+    NonZeroExitStdoutDiscarded(NamedOutput),
+
+    InvalidUtf8(NamedOutput, Stream, std::str::Utf8Error),
+
+    ParseError(NamedOutput, Box<dyn std::error::Error + Send + Sync>),
+
+    OutputMatched(NamedOutput, Box<OutputMatch>),
+
+    NotReady(NamedOutput, String),
+
+    Interrupted(NamedOutput, i32),
+
+    InteractionFailed(NamedOutput, String),
+
+    CircuitOpen(String, usize, Duration),
+
+    Throttled(String, String),
+
+    Declined(String),
+
+    Rejected(String, String),
+
+    TimedOut(NamedOutput, Duration),
+
+    SeccompKilled(NamedOutput),
 }
 
 impl Display for CmdError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_display(f, NameQuote::Backtick)
+    }
+}
+
+struct QuotedError<'a>(&'a CmdError, NameQuote);
+
+impl Display for QuotedError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.write_display(f, self.1)
+    }
+}
+
+impl CmdError {
+    fn write_display(&self, f: &mut std::fmt::Formatter<'_>, quote: NameQuote) -> std::fmt::Result {
         match self {
-            CmdError::SystemError(name, error) => {
-                write!(f, "Could not run command `{name}`. {error}")
+            CmdError::SystemError(name, error, _) => match self.validation_error() {
+                Some(validation_error) => write!(f, "{validation_error}"),
+                None => write!(
+                    f,
+                    "Could not run command {name}. {error}",
+                    name = quote.quoted(name)
+                ),
+            },
+            CmdError::NonZeroExitNotStreamed(named_output) => write_nonzero(
+                f,
+                named_output,
+                &display_out_or_empty(&named_output.stdout_lossy()),
+                &display_out_or_empty(&named_output.stderr_lossy()),
+                quote,
+                named_output.extras.streaming_hint.as_deref(),
+            ),
+            CmdError::NonZeroExitAlreadyStreamed(named_output) => {
+                write_nonzero(f, named_output, SEE_ABOVE, SEE_ABOVE, quote, None)
             }
-            CmdError::NonZeroExitNotStreamed(named_output) => {
-                let stdout = display_out_or_empty(&named_output.output.stdout);
-                let stderr = display_out_or_empty(&named_output.output.stderr);
+            CmdError::NonZeroExitStdoutStreamed(named_output) => write_nonzero(
+                f,
+                named_output,
+                SEE_ABOVE,
+                &display_out_or_empty(&named_output.stderr_lossy()),
+                quote,
+                None,
+            ),
+            CmdError::NonZeroExitStderrStreamed(named_output) => write_nonzero(
+                f,
+                named_output,
+                &display_out_or_empty(&named_output.stdout_lossy()),
+                SEE_ABOVE,
+                quote,
+                None,
+            ),
+            CmdError::NonZeroExitStdoutDiscarded(named_output) => write_nonzero(
+                f,
+                named_output,
+                DISCARDED,
+                &display_out_or_empty(&named_output.stderr_lossy()),
+                quote,
+                named_output.extras.streaming_hint.as_deref(),
+            ),
+            CmdError::InvalidUtf8(named_output, stream, error) => {
+                write!(
+                    f,
+                    "Invalid UTF-8 in {stream} of command {name}. {error}",
+                    name = quote.quoted(named_output.name())
+                )
+            }
+            CmdError::ParseError(named_output, error) => {
+                writeln!(
+                    f,
+                    "Could not parse stdout of command {name}. {error}",
+                    name = quote.quoted(named_output.name())
+                )?;
+                write!(
+                    f,
+                    "stdout: {stdout}",
+                    stdout = display_parse_error_stdout(named_output, error.as_ref())
+                )
+            }
+            CmdError::OutputMatched(named_output, matched) => {
+                writeln!(
+                    f,
+                    "Command aborted {name}",
+                    name = quote.quoted(named_output.name())
+                )?;
+                writeln!(
+                    f,
+                    "{stream} matched `{pattern}`: {line}",
+                    stream = matched.stream,
+                    pattern = matched.pattern,
+                    line = matched.line
+                )?;
+                writeln!(f, "stdout: {SEE_ABOVE}")?;
+                write!(f, "stderr: {SEE_ABOVE}")
+            }
+            CmdError::NotReady(named_output, reason) => {
+                writeln!(
+                    f,
+                    "Command not ready {name}. {reason}",
+                    name = quote.quoted(named_output.name())
+                )?;
+                write_captured(f, named_output)
+            }
+            CmdError::InteractionFailed(named_output, reason) => {
+                writeln!(
+                    f,
+                    "Command interaction failed {name}. {reason}",
+                    name = quote.quoted(named_output.name())
+                )?;
+                write_captured(f, named_output)
+            }
+            CmdError::TimedOut(named_output, timeout) => {
+                writeln!(
+                    f,
+                    "Command timed out {name} after {timeout:?}",
+                    name = quote.quoted(named_output.name())
+                )?;
+                write_captured(f, named_output)
+            }
+            CmdError::SeccompKilled(named_output) => {
+                writeln!(
+                    f,
+                    "Command killed by seccomp filter {name}. It made a system call the filter does not allow",
+                    name = quote.quoted(named_output.name())
+                )?;
+                write_captured(f, named_output)
+            }
+            CmdError::CircuitOpen(name, failures, remaining) => {
+                write!(
+                    f,
+                    "Not running command {name}. It failed {failures} {times} in a row, try again in {remaining:.0?}",
+                    name = quote.quoted(name),
+                    times = if *failures == 1 { "time" } else { "times" }
+                )
+            }
+            CmdError::Throttled(name, reason) => {
+                write!(
+                    f,
+                    "Not running command {name}. Limit reached, {reason}",
+                    name = quote.quoted(name)
+                )
+            }
+            CmdError::Declined(name) => {
+                write!(
+                    f,
+                    "Not running command {name}. Declined by user",
+                    name = quote.quoted(name)
+                )
+            }
+            CmdError::Rejected(name, reason) => {
+                write!(
+                    f,
+                    "Not running command {name}. Rejected, {reason}",
+                    name = quote.quoted(name)
+                )
+            }
+            CmdError::Interrupted(named_output, signal) => {
+                writeln!(
+                    f,
+                    "Command interrupted {name} by {signal}",
+                    name = quote.quoted(named_output.name()),
+                    signal = signals::signal_name(*signal)
+                )?;
+                writeln!(
+                    f,
+                    "exit status: {status}",
+                    status = named_output.output.status.code().unwrap_or(1)
+                )?;
+                writeln!(f, "stdout: {SEE_ABOVE}")?;
+                write!(f, "stderr: {SEE_ABOVE}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CmdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CmdError::SystemError(_, io_err, _) => Some(io_err),
+            CmdError::InvalidUtf8(_, _, utf8_err) => Some(utf8_err),
+            CmdError::ParseError(_, parse_err) => Some(parse_err.as_ref()),
+            CmdError::NonZeroExitNotStreamed(_)
+            | CmdError::NonZeroExitAlreadyStreamed(_)
+            | CmdError::NonZeroExitStdoutStreamed(_)
+            | CmdError::NonZeroExitStderrStreamed(_)
+            | CmdError::NonZeroExitStdoutDiscarded(_)
+            | CmdError::OutputMatched(_, _)
+            | CmdError::NotReady(_, _)
+            | CmdError::Interrupted(_, _)
+            | CmdError::InteractionFailed(_, _)
+            | CmdError::CircuitOpen(_, _, _)
+            | CmdError::Throttled(_, _)
+            | CmdError::Declined(_)
+            | CmdError::Rejected(_, _)
+            | CmdError::TimedOut(_, _)
+            | CmdError::SeccompKilled(_) => None,
+        }
+    }
+}
+
+impl CmdError {
+    /// Returns a display representation of the command that failed
+    ///
+    /// Example:
+    ///
+    /// ```no_run
+    /// use fun_run::CommandWithName;
+    /// use std::process::Command;
+    ///
+    /// let result = Command::new("cat")
+    ///     .arg("mouse.txt")
+    ///     .named_output();
+    ///
+    /// match result {
+    ///     Ok(_) => unimplemented!(),
+    ///     Err(error) => assert_eq!(error.name(), "cat mouse.txt")
+    /// }
+    /// ```
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            CmdError::SystemError(name, ..)
+            | CmdError::CircuitOpen(name, _, _)
+            | CmdError::Throttled(name, _)
+            | CmdError::Declined(name)
+            | CmdError::Rejected(name, _) => name,
+            CmdError::NonZeroExitNotStreamed(out)
+            | CmdError::NonZeroExitAlreadyStreamed(out)
+            | CmdError::NonZeroExitStdoutStreamed(out)
+            | CmdError::NonZeroExitStderrStreamed(out)
+            | CmdError::NonZeroExitStdoutDiscarded(out)
+            | CmdError::InvalidUtf8(out, _, _)
+            | CmdError::ParseError(out, _)
+            | CmdError::OutputMatched(out, _)
+            | CmdError::NotReady(out, _)
+            | CmdError::Interrupted(out, _)
+            | CmdError::InteractionFailed(out, _)
+            | CmdError::TimedOut(out, _)
+            | CmdError::SeccompKilled(out) => &out.name,
+        }
+    }
+
+    /// Returns a display representation of the command that failed, taking ownership of it
+    #[must_use]
+    pub fn into_name(self) -> String {
+        match self {
+            CmdError::SystemError(name, ..)
+            | CmdError::CircuitOpen(name, _, _)
+            | CmdError::Throttled(name, _)
+            | CmdError::Declined(name)
+            | CmdError::Rejected(name, _) => name,
+            CmdError::NonZeroExitNotStreamed(out)
+            | CmdError::NonZeroExitAlreadyStreamed(out)
+            | CmdError::NonZeroExitStdoutStreamed(out)
+            | CmdError::NonZeroExitStderrStreamed(out)
+            | CmdError::NonZeroExitStdoutDiscarded(out)
+            | CmdError::InvalidUtf8(out, _, _)
+            | CmdError::ParseError(out, _)
+            | CmdError::OutputMatched(out, _)
+            | CmdError::NotReady(out, _)
+            | CmdError::Interrupted(out, _)
+            | CmdError::InteractionFailed(out, _)
+            | CmdError::TimedOut(out, _)
+            | CmdError::SeccompKilled(out) => out.name,
+        }
+    }
+
+    /// The [NamedOutput::run_id] of the failed run, `None` if nothing was run
+    ///
+    /// A command that could not be spawned has no output but keeps the ID it was assigned,
+    /// which is also recorded in the [FunRunner] history and audit log, and in
+    /// [StepReport::run_id].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, NamedOutput};
+    /// use std::process::Command;
+    ///
+    /// let error = Command::new("does_not_exist_fun_run")
+    ///     .named_output()
+    ///     .unwrap_err();
+    /// let run_id = error.run_id().unwrap();
+    ///
+    /// let output = NamedOutput::new("fake", Command::new("true").output().unwrap());
+    /// assert!(output.run_id() > run_id);
+    /// ```
+    #[must_use]
+    pub fn run_id(&self) -> Option<u64> {
+        match self {
+            CmdError::SystemError(_, _, run_id) => *run_id,
+            _ => self.named_output().map(NamedOutput::run_id),
+        }
+    }
 
-                writeln!(f, "Command failed `{name}`", name = named_output.name())?;
-                writeln!(
-                    f,
-                    "exit status: {status}",
-                    status = named_output.output.status.code().unwrap_or(1)
-                )?;
-                writeln!(f, "stdout: {stdout}",)?;
-                write!(f, "stderr: {stderr}",)
-            }
-            CmdError::NonZeroExitAlreadyStreamed(named_output) => {
-                writeln!(f, "Command failed `{name}`", name = named_output.name())?;
-                writeln!(
-                    f,
-                    "exit status: {status}",
-                    status = named_output.output.status.code().unwrap_or(1)
-                )?;
-                writeln!(f, "stdout: <see above>")?;
-                write!(f, "stderr: <see above>")
-            }
+    /// The [ValidationError] that stopped the command from running
+    ///
+    /// Returned for errors converted from [ValidationError] and when spawning fails because the
+    /// program, an argument or an environment variable contains a NUL byte.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, ValidationError};
+    /// use std::process::Command;
+    ///
+    /// let mut command = Command::new("echo");
+    /// command.args(["hello", "wor\0ld"]);
+    ///
+    /// let error = command.named_output().unwrap_err();
+    /// assert!(matches!(
+    ///     error.validation_error(),
+    ///     Some(ValidationError::NulInArgument(_, 2))
+    /// ));
+    /// ```
+    #[must_use]
+    pub fn validation_error(&self) -> Option<&ValidationError> {
+        match self {
+            CmdError::SystemError(_, error, _) => error.get_ref()?.downcast_ref(),
+            _ => None,
         }
     }
-}
 
-impl std::error::Error for CmdError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    /// The output of the run, `None` if the command never ran
+    pub(crate) fn named_output(&self) -> Option<&NamedOutput> {
         match self {
-            CmdError::SystemError(_, io_err) => Some(io_err),
-            CmdError::NonZeroExitNotStreamed(_) | CmdError::NonZeroExitAlreadyStreamed(_) => None,
+            CmdError::SystemError(..)
+            | CmdError::CircuitOpen(_, _, _)
+            | CmdError::Throttled(_, _)
+            | CmdError::Declined(_)
+            | CmdError::Rejected(_, _) => None,
+            CmdError::NonZeroExitNotStreamed(out)
+            | CmdError::NonZeroExitAlreadyStreamed(out)
+            | CmdError::NonZeroExitStdoutStreamed(out)
+            | CmdError::NonZeroExitStderrStreamed(out)
+            | CmdError::NonZeroExitStdoutDiscarded(out)
+            | CmdError::InvalidUtf8(out, _, _)
+            | CmdError::ParseError(out, _)
+            | CmdError::OutputMatched(out, _)
+            | CmdError::NotReady(out, _)
+            | CmdError::Interrupted(out, _)
+            | CmdError::InteractionFailed(out, _)
+            | CmdError::TimedOut(out, _)
+            | CmdError::SeccompKilled(out) => Some(out),
         }
     }
-}
 
-impl CmdError {
-    /// Returns a display representation of the command that failed
+    /// Decode output contained in the error using the given encoding
+    ///
+    /// See `NamedOutput::with_encoding`
+    #[must_use]
+    #[cfg(feature = "encoding_rs")]
+    pub fn with_encoding(self, encoding: &'static encoding_rs::Encoding) -> Self {
+        self.map_named_output(|named_output| named_output.with_encoding(encoding))
+    }
+
+    /// Replace `\r\n` line endings with `\n` in output contained in the error
+    ///
+    /// See `NamedOutput::normalize_line_endings`
+    #[must_use]
+    pub fn normalize_line_endings(self) -> Self {
+        self.map_named_output(NamedOutput::normalize_line_endings)
+    }
+
+    /// Keep only the final state of lines rewritten with `\r` in output contained in the error
+    ///
+    /// See `NamedOutput::collapse_carriage_returns`
+    #[must_use]
+    pub fn collapse_carriage_returns(self) -> Self {
+        self.map_named_output(NamedOutput::collapse_carriage_returns)
+    }
+
+    /// Convert into an `std::io::Error` for APIs that return `io::Result`
+    ///
+    /// The message is the first line of the display, which names the command. The original
+    /// error is kept as the `source()` so nothing is lost. Spawn errors keep their kind,
+    /// timeouts and interruptions map to [std::io::ErrorKind::TimedOut] and
+    /// [std::io::ErrorKind::Interrupted], everything else is [std::io::ErrorKind::Other].
     ///
     /// Example:
     ///
-    /// ```no_run
-    /// use fun_run::CommandWithName;
+    /// ```
+    /// use fun_run::{CmdError, CommandWithName};
+    /// use std::error::Error;
     /// use std::process::Command;
     ///
-    /// let result = Command::new("cat")
-    ///     .arg("mouse.txt")
-    ///     .named_output();
-    ///
-    /// match result {
-    ///     Ok(_) => unimplemented!(),
-    ///     Err(error) => assert_eq!(error.name().to_string(), "cat mouse.txt")
+    /// fn build() -> std::io::Result<()> {
+    ///     Command::new("bash")
+    ///         .args(["-c", "echo 'oh no' >&2; exit 2"])
+    ///         .named_output()
+    ///         .map_err(CmdError::into_io_error)?;
+    ///     Ok(())
     /// }
+    ///
+    /// let error = build().unwrap_err();
+    /// assert_eq!(
+    ///     "Command failed `bash -c \"echo 'oh no' >&2; exit 2\"`",
+    ///     error.to_string()
+    /// );
+    ///
+    /// let original = error.source().unwrap().downcast_ref::<CmdError>().unwrap();
+    /// assert_eq!(Some(2), original.status().code());
+    /// ```
+    #[must_use]
+    pub fn into_io_error(self) -> std::io::Error {
+        let kind = match &self {
+            CmdError::SystemError(_, error, _) => error.kind(),
+            CmdError::TimedOut(_, _) => std::io::ErrorKind::TimedOut,
+            CmdError::Interrupted(_, _) => std::io::ErrorKind::Interrupted,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, IoCmdError(self))
+    }
+
+    /// Display the error with the command name in a different style than backticks
+    ///
+    /// Custom templates only change where they use `{quoted_name}`, see [ErrorTemplate].
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, NameQuote};
+    /// use std::process::Command;
+    ///
+    /// let error = Command::new("false").named_output().unwrap_err();
+    ///
+    /// assert!(error
+    ///     .display_with(NameQuote::None)
+    ///     .to_string()
+    ///     .starts_with("Command failed false\nexit status: 1"));
+    /// assert!(error
+    ///     .display_with(NameQuote::CodeBlock)
+    ///     .to_string()
+    ///     .starts_with("Command failed \n```\nfalse\n```\n\nexit status: 1"));
     /// ```
     #[must_use]
-    pub fn name(&self) -> std::borrow::Cow<'_, str> {
+    pub fn display_with(&self, quote: NameQuote) -> impl Display + '_ {
+        QuotedError(self, quote)
+    }
+
+    /// Reword the display of a non-zero exit
+    ///
+    /// See `NamedOutput::with_error_template`
+    #[must_use]
+    pub fn with_error_template(self, template: impl Into<ErrorTemplate>) -> Self {
+        self.map_named_output(|named_output| named_output.with_error_template(template))
+    }
+
+    /// Show a hint after captured output in the display of a non-zero exit
+    ///
+    /// See `NamedOutput::with_streaming_hint`
+    #[must_use]
+    pub fn with_streaming_hint(self, hint: impl Into<String>) -> Self {
+        self.map_named_output(|named_output| named_output.with_streaming_hint(hint))
+    }
+
+    /// Fit the name into `width` characters in the display of a non-zero exit
+    ///
+    /// See `NamedOutput::with_name_fit`
+    #[must_use]
+    pub fn with_name_fit(self, fit: NameFit, width: usize) -> Self {
+        self.map_named_output(|named_output| named_output.with_name_fit(fit, width))
+    }
+
+    /// Show the working directory and environment variables of the command in the display
+    ///
+    /// See `NamedOutput::with_failure_context`
+    #[must_use]
+    pub fn with_failure_context<I, K>(self, command: &Command, env_keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<OsStr>,
+    {
+        self.map_named_output(|named_output| named_output.with_failure_context(command, env_keys))
+    }
+
+    /// Change the output contained in the error, keeping the kind of error
+    fn map_named_output(self, f: impl FnOnce(NamedOutput) -> NamedOutput) -> Self {
         match self {
-            CmdError::SystemError(name, _) => name.into(),
-            CmdError::NonZeroExitNotStreamed(out) | CmdError::NonZeroExitAlreadyStreamed(out) => {
-                out.name.as_str().into()
+            CmdError::SystemError(..)
+            | CmdError::CircuitOpen(_, _, _)
+            | CmdError::Throttled(_, _)
+            | CmdError::Declined(_)
+            | CmdError::Rejected(_, _) => self,
+            CmdError::NonZeroExitNotStreamed(named_output) => {
+                CmdError::NonZeroExitNotStreamed(f(named_output))
+            }
+            CmdError::NonZeroExitAlreadyStreamed(named_output) => {
+                CmdError::NonZeroExitAlreadyStreamed(f(named_output))
+            }
+            CmdError::NonZeroExitStdoutStreamed(named_output) => {
+                CmdError::NonZeroExitStdoutStreamed(f(named_output))
+            }
+            CmdError::NonZeroExitStderrStreamed(named_output) => {
+                CmdError::NonZeroExitStderrStreamed(f(named_output))
+            }
+            CmdError::NonZeroExitStdoutDiscarded(named_output) => {
+                CmdError::NonZeroExitStdoutDiscarded(f(named_output))
+            }
+            CmdError::InvalidUtf8(named_output, stream, error) => {
+                CmdError::InvalidUtf8(f(named_output), stream, error)
+            }
+            CmdError::ParseError(named_output, error) => {
+                CmdError::ParseError(f(named_output), error)
+            }
+            CmdError::OutputMatched(named_output, matched) => {
+                CmdError::OutputMatched(f(named_output), matched)
+            }
+            CmdError::NotReady(named_output, reason) => CmdError::NotReady(f(named_output), reason),
+            CmdError::Interrupted(named_output, signal) => {
+                CmdError::Interrupted(f(named_output), signal)
             }
+            CmdError::InteractionFailed(named_output, reason) => {
+                CmdError::InteractionFailed(f(named_output), reason)
+            }
+            CmdError::TimedOut(named_output, timeout) => {
+                CmdError::TimedOut(f(named_output), timeout)
+            }
+            CmdError::SeccompKilled(named_output) => CmdError::SeccompKilled(f(named_output)),
         }
     }
 
@@ -499,34 +3314,196 @@ impl CmdError {
     /// If the command failed and no error can be produced a default non-zero value will be returned
     pub fn status(&self) -> ExitStatus {
         match self {
-            CmdError::SystemError(_, error) => {
+            CmdError::SystemError(_, error, _) => {
                 ExitStatus::from_raw(error.raw_os_error().unwrap_or(-1))
             }
-            CmdError::NonZeroExitNotStreamed(named_output) => named_output.status().to_owned(),
-            CmdError::NonZeroExitAlreadyStreamed(named_output) => named_output.status().to_owned(),
+            CmdError::CircuitOpen(_, _, _)
+            | CmdError::Throttled(_, _)
+            | CmdError::Declined(_)
+            | CmdError::Rejected(_, _) => ExitStatus::from_raw(1 << 8),
+            CmdError::NonZeroExitNotStreamed(named_output)
+            | CmdError::NonZeroExitAlreadyStreamed(named_output)
+            | CmdError::NonZeroExitStdoutStreamed(named_output)
+            | CmdError::NonZeroExitStderrStreamed(named_output)
+            | CmdError::NonZeroExitStdoutDiscarded(named_output)
+            | CmdError::InvalidUtf8(named_output, _, _)
+            | CmdError::ParseError(named_output, _)
+            | CmdError::OutputMatched(named_output, _)
+            | CmdError::NotReady(named_output, _)
+            | CmdError::Interrupted(named_output, _)
+            | CmdError::InteractionFailed(named_output, _)
+            | CmdError::TimedOut(named_output, _)
+            | CmdError::SeccompKilled(named_output) => named_output.status().to_owned(),
+        }
+    }
+
+    /// Exit code to end this process with, so callers see why the wrapped command failed
+    ///
+    /// Uses the command's own exit code. A command killed by a signal maps to `128 + signal`
+    /// like a shell. When the command could not be run the shell conventions of 127 for a
+    /// missing program and 126 for one that cannot be executed are used. A failure that is not
+    /// reflected in the exit code, such as output that could not be parsed, returns 1.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::CommandWithName;
+    /// use std::process::{Command, ExitCode};
+    ///
+    /// let error = Command::new("bash").args(["-c", "exit 3"]).named_output().unwrap_err();
+    /// assert_eq!(3, error.exit_code());
+    ///
+    /// let error = Command::new("bash").args(["-c", "kill -9 $$"]).named_output().unwrap_err();
+    /// assert_eq!(137, error.exit_code());
+    ///
+    /// // End `main() -> ExitCode` with the code of the failed command
+    /// let code: ExitCode = error.into();
+    /// ```
+    #[must_use]
+    pub fn exit_code(&self) -> i32 {
+        let code = match self {
+            CmdError::SystemError(_, error, _) => match error.kind() {
+                std::io::ErrorKind::NotFound => 127,
+                std::io::ErrorKind::PermissionDenied => 126,
+                _ => 1,
+            },
+            _ => {
+                let status = self.status();
+                status
+                    .code()
+                    .or_else(|| status.signal().map(|signal| 128 + signal))
+                    .unwrap_or(1)
+            }
+        };
+        if code == 0 {
+            1
+        } else {
+            code
         }
     }
 }
 
+impl From<CmdError> for std::process::ExitCode {
+    /// See [CmdError::exit_code], codes that do not fit in a `u8` become 1
+    fn from(value: CmdError) -> Self {
+        u8::try_from(value.exit_code()).unwrap_or(1).into()
+    }
+}
+
 impl From<CmdError> for NamedOutput {
     fn from(value: CmdError) -> Self {
         match value {
-            CmdError::SystemError(name, error) => NamedOutput {
+            CmdError::SystemError(name, error, run_id) => NamedOutput::for_run(
                 name,
-                output: Output {
+                run_id.unwrap_or_else(next_run_id),
+                Output {
                     status: ExitStatus::from_raw(error.raw_os_error().unwrap_or(-1)),
                     stdout: Vec::new(),
                     stderr: error.to_string().into_bytes(),
                 },
-            },
+            ),
             CmdError::NonZeroExitNotStreamed(named)
-            | CmdError::NonZeroExitAlreadyStreamed(named) => named,
+            | CmdError::NonZeroExitAlreadyStreamed(named)
+            | CmdError::NonZeroExitStdoutStreamed(named)
+            | CmdError::NonZeroExitStderrStreamed(named)
+            | CmdError::NonZeroExitStdoutDiscarded(named)
+            | CmdError::InvalidUtf8(named, _, _)
+            | CmdError::ParseError(named, _)
+            | CmdError::OutputMatched(named, _)
+            | CmdError::NotReady(named, _)
+            | CmdError::Interrupted(named, _)
+            | CmdError::InteractionFailed(named, _)
+            | CmdError::TimedOut(named, _)
+            | CmdError::SeccompKilled(named) => named,
+            error @ (CmdError::CircuitOpen(_, _, _)
+            | CmdError::Throttled(_, _)
+            | CmdError::Declined(_)
+            | CmdError::Rejected(_, _)) => NamedOutput::new(
+                error.name().to_string(),
+                Output {
+                    status: error.status(),
+                    stdout: Vec::new(),
+                    stderr: error.to_string().into_bytes(),
+                },
+            ),
+        }
+    }
+}
+
+const SEE_ABOVE: &str = "<see above>";
+const DISCARDED: &str = "<discarded>";
+
+fn write_nonzero(
+    f: &mut std::fmt::Formatter<'_>,
+    named_output: &NamedOutput,
+    stdout: &str,
+    stderr: &str,
+    quote: NameQuote,
+    hint: Option<&str>,
+) -> std::fmt::Result {
+    let template = named_output
+        .extras
+        .template
+        .as_ref()
+        .map_or(ErrorTemplate::DEFAULT, ErrorTemplate::as_str);
+    template::render(f, template, named_output, stdout, stderr, quote, hint)
+}
+
+#[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+fn display_parse_error_stdout(
+    named_output: &NamedOutput,
+    error: &(dyn std::error::Error + Send + Sync + 'static),
+) -> String {
+    #[cfg(feature = "serde")]
+    if let Some(json_error) = error.downcast_ref::<serde_json::Error>() {
+        return json::snippet(&named_output.stdout_lossy(), json_error);
+    }
+
+    display_out_or_empty(&named_output.stdout_lossy())
+}
+
+/// Writes the exit status and full captured output of a command that did not finish normally
+fn write_captured(f: &mut std::fmt::Formatter<'_>, named_output: &NamedOutput) -> std::fmt::Result {
+    write_context(f, named_output)?;
+    writeln!(
+        f,
+        "exit status: {status}",
+        status = named_output.output.status.code().unwrap_or(1)
+    )?;
+    write_stdin(f, named_output)?;
+    writeln!(
+        f,
+        "stdout: {stdout}",
+        stdout = display_out_or_empty(&named_output.stdout_lossy())
+    )?;
+    write!(
+        f,
+        "stderr: {stderr}",
+        stderr = display_out_or_empty(&named_output.stderr_lossy())
+    )
+}
+
+/// Writes the recorded input as a `stdin:` line, see [NamedOutput::stdin]
+fn write_stdin(f: &mut std::fmt::Formatter<'_>, named_output: &NamedOutput) -> std::fmt::Result {
+    match &named_output.extras.stdin {
+        Some(stdin) => {
+            let stdin = String::from_utf8_lossy(stdin);
+            let stdin = stdin.strip_suffix('\n').unwrap_or(&stdin);
+            writeln!(f, "stdin: {}", display_out_or_empty(stdin))
         }
+        None => Ok(()),
+    }
+}
+
+/// Writes the lines added by [NamedOutput::with_failure_context]
+fn write_context(f: &mut std::fmt::Formatter<'_>, named_output: &NamedOutput) -> std::fmt::Result {
+    for line in &named_output.extras.context {
+        writeln!(f, "{line}")?;
     }
+    Ok(())
 }
 
-fn display_out_or_empty(contents: &[u8]) -> String {
-    let contents = String::from_utf8_lossy(contents);
+fn display_out_or_empty(contents: &str) -> String {
     if contents.trim().is_empty() {
         "<empty>".to_string()
     } else {
@@ -534,10 +3511,53 @@ fn display_out_or_empty(contents: &[u8]) -> String {
     }
 }
 
+/// Runs the command, writing stdout and stderr to the writers as they arrive and capturing them
+///
+/// The building block behind [CommandWithName::stream_output], for orchestration that needs
+/// the same stream pumping without a [NamedOutput]. Stdout and stderr are set to piped and
+/// read on their own threads at the same time so neither can fill up and block the command.
+/// Stdin is left as set on the command. Returns once the command exits and both streams are
+/// closed. A non-zero exit is not an error, check [Output::status].
+///
+/// Example:
+///
+/// ```
+/// use std::process::Command;
+///
+/// let mut log = Vec::new();
+/// let output = fun_run::output_and_write_streams(
+///     Command::new("bash").args(["-c", "echo building && echo 'oh no' >&2 && exit 3"]),
+///     &mut log,
+///     std::io::sink(),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(b"building\n", log.as_slice());
+/// assert_eq!(b"building\n", output.stdout.as_slice());
+/// assert_eq!(b"oh no\n", output.stderr.as_slice());
+/// assert_eq!(Some(3), output.status.code());
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the command cannot be spawned, or if reading its output or writing to
+/// either writer fails. The command is still waited on when writing fails.
+pub fn output_and_write_streams<OW, EW>(
+    command: &mut Command,
+    stdout_write: OW,
+    stderr_write: EW,
+) -> std::io::Result<Output>
+where
+    OW: Write + Send,
+    EW: Write + Send,
+{
+    command::output_and_write_streams(command, stdout_write, stderr_write).map(|(output, _)| output)
+}
+
 /// Converts a `std::io::Error` into a `CmdError` which includes the formatted command name
 #[must_use]
 pub fn on_system_error(name: String, error: std::io::Error) -> CmdError {
-    CmdError::SystemError(name, error)
+    CmdError::SystemError(name, error, None)
 }
 
 /// Converts an `Output` into an error when status is non-zero
@@ -555,15 +3575,7 @@ pub fn on_system_error(name: String, error: std::io::Error) -> CmdError {
 ///
 /// Returns Err when the `Output` status is non-zero
 pub fn nonzero_streamed(name: String, output: impl Into<Output>) -> Result<NamedOutput, CmdError> {
-    let output = output.into();
-    if output.status.success() {
-        Ok(NamedOutput { name, output })
-    } else {
-        Err(CmdError::NonZeroExitAlreadyStreamed(NamedOutput {
-            name,
-            output,
-        }))
-    }
+    NamedOutput::new(name, output.into()).nonzero_streamed()
 }
 
 /// Converts an `Output` into an error when status is non-zero
@@ -577,15 +3589,52 @@ pub fn nonzero_streamed(name: String, output: impl Into<Output>) -> Result<Named
 ///
 /// Returns Err when the `Output` status is non-zero
 pub fn nonzero_captured(name: String, output: impl Into<Output>) -> Result<NamedOutput, CmdError> {
-    let output = output.into();
-    if output.status.success() {
-        Ok(NamedOutput { name, output })
-    } else {
-        Err(CmdError::NonZeroExitNotStreamed(NamedOutput {
-            name,
-            output,
-        }))
-    }
+    NamedOutput::new(name, output.into()).nonzero_captured()
+}
+
+/// Converts an `Output` into an error when status is non-zero
+///
+/// Use when only stdout was streamed to the user, captured stderr will be
+/// included in the error display.
+///
+/// # Errors
+///
+/// Returns Err when the `Output` status is non-zero
+pub fn nonzero_stdout_streamed(
+    name: String,
+    output: impl Into<Output>,
+) -> Result<NamedOutput, CmdError> {
+    NamedOutput::new(name, output.into()).nonzero_stdout_streamed()
+}
+
+/// Converts an `Output` into an error when status is non-zero
+///
+/// Use when only stderr was streamed to the user, captured stdout will be
+/// included in the error display.
+///
+/// # Errors
+///
+/// Returns Err when the `Output` status is non-zero
+pub fn nonzero_stderr_streamed(
+    name: String,
+    output: impl Into<Output>,
+) -> Result<NamedOutput, CmdError> {
+    NamedOutput::new(name, output.into()).nonzero_stderr_streamed()
+}
+
+/// Converts an `Output` into an error when status is non-zero
+///
+/// Use when stdout was never captured, the error display will note that
+/// stdout was discarded rather than reporting it as empty.
+///
+/// # Errors
+///
+/// Returns Err when the `Output` status is non-zero
+pub fn nonzero_stdout_discarded(
+    name: String,
+    output: impl Into<Output>,
+) -> Result<NamedOutput, CmdError> {
+    NamedOutput::new(name, output.into()).nonzero_stdout_discarded()
 }
 
 /// Adds diagnostic information to a `CmdError` using `which_problem` if it is a `CmdError::SystemError`
@@ -620,23 +3669,141 @@ pub fn nonzero_captured(name: String, output: impl Into<Output>) -> Result<Named
 /// cmd.named_output().map_err(|error| {
 ///     fun_run::map_which_problem(error, cmd.mut_cmd(), std::env::var_os("PATH"))
 /// }).unwrap();
-/// ````
+/// ```
 #[cfg(feature = "which_problem")]
 pub fn map_which_problem(
     error: CmdError,
     cmd: &mut Command,
     path_env: Option<OsString>,
 ) -> CmdError {
-    match error {
-        CmdError::SystemError(name, error) => {
-            CmdError::SystemError(name, annotate_which_problem(error, cmd, path_env))
+    map_which_problem_with(error, cmd, path_env, &WhichProblemOptions::default())
+}
+
+/// Controls how much [map_which_problem_with] reveals about the system
+///
+/// The defaults match [map_which_problem]. To use the diagnostics in production logs without
+/// leaking the filesystem layout, limit the suggestions, redact the home directory and cap
+/// the number of lines.
+///
+/// This feature is experimental and may change in the future.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg(feature = "which_problem")]
+pub struct WhichProblemOptions {
+    guess_limit: usize,
+    redact_home: bool,
+    max_lines: Option<usize>,
+}
+
+#[cfg(feature = "which_problem")]
+impl Default for WhichProblemOptions {
+    fn default() -> Self {
+        Self {
+            guess_limit: Which::default().guess_limit,
+            redact_home: false,
+            max_lines: None,
+        }
+    }
+}
+
+#[cfg(feature = "which_problem")]
+impl WhichProblemOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of "did you mean" suggestions for similarly named programs
+    #[must_use]
+    pub fn guess_limit(mut self, guess_limit: usize) -> Self {
+        self.guess_limit = guess_limit;
+        self
+    }
+
+    /// Replace the `HOME` directory with `~` in the diagnostics
+    #[must_use]
+    pub fn redact_home(mut self) -> Self {
+        self.redact_home = true;
+        self
+    }
+
+    /// Show at most this many lines of diagnostics, such as directory listings and
+    /// permissions, noting how many were left out
+    #[must_use]
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    fn apply(&self, details: String) -> String {
+        let details = match std::env::var("HOME") {
+            Ok(home) if self.redact_home && !home.is_empty() => details.replace(&home, "~"),
+            _ => details,
+        };
+        match self.max_lines {
+            Some(max) if details.lines().count() > max => {
+                let hidden = details.lines().count() - max;
+                let mut shown = details.lines().take(max).collect::<Vec<_>>().join("\n");
+                shown.push_str(&format!("\n... {hidden} more line(s) hidden"));
+                shown
+            }
+            _ => details,
         }
-        CmdError::NonZeroExitNotStreamed(_) | CmdError::NonZeroExitAlreadyStreamed(_) => error,
+    }
+}
+
+/// Like [map_which_problem] with control over how much is revealed, see [WhichProblemOptions]
+///
+/// Example:
+///
+/// ```no_run
+/// use fun_run::{self, CommandWithName, WhichProblemOptions};
+/// use std::process::Command;
+///
+/// let options = WhichProblemOptions::new().guess_limit(1).redact_home().max_lines(20);
+/// let mut cmd = Command::new("bundle");
+/// cmd.arg("install");
+/// cmd.named_output().map_err(|error| {
+///     fun_run::map_which_problem_with(error, cmd.mut_cmd(), std::env::var_os("PATH"), &options)
+/// }).unwrap();
+/// ```
+#[cfg(feature = "which_problem")]
+pub fn map_which_problem_with(
+    error: CmdError,
+    cmd: &mut Command,
+    path_env: Option<OsString>,
+    options: &WhichProblemOptions,
+) -> CmdError {
+    match error {
+        CmdError::SystemError(name, error, run_id) => CmdError::SystemError(
+            name,
+            annotate_which_problem(error, cmd, path_env, options),
+            run_id,
+        ),
+        CmdError::NonZeroExitNotStreamed(_)
+        | CmdError::NonZeroExitAlreadyStreamed(_)
+        | CmdError::NonZeroExitStdoutStreamed(_)
+        | CmdError::NonZeroExitStderrStreamed(_)
+        | CmdError::NonZeroExitStdoutDiscarded(_)
+        | CmdError::InvalidUtf8(_, _, _)
+        | CmdError::ParseError(_, _)
+        | CmdError::OutputMatched(_, _)
+        | CmdError::NotReady(_, _)
+        | CmdError::Interrupted(_, _)
+        | CmdError::InteractionFailed(_, _)
+        | CmdError::CircuitOpen(_, _, _)
+        | CmdError::Throttled(_, _)
+        | CmdError::Declined(_)
+        | CmdError::Rejected(_, _)
+        | CmdError::TimedOut(_, _)
+        | CmdError::SeccompKilled(_) => error,
     }
 }
 
 /// Adds diagnostic information to an `std::io::Error` using `which_problem`
 ///
+/// Replaces the "Did you mean" hint added when the program is not found, the diagnostics
+/// include their own guesses.
+///
 /// This feature is experimental
 #[must_use]
 #[cfg(feature = "which_problem")]
@@ -644,6 +3811,7 @@ fn annotate_which_problem(
     error: std::io::Error,
     cmd: &mut Command,
     path_env: Option<OsString>,
+    options: &WhichProblemOptions,
 ) -> std::io::Error {
     let program = cmd.get_program().to_os_string();
     let current_working_dir = cmd.get_current_dir().map(std::path::Path::to_path_buf);
@@ -651,16 +3819,20 @@ fn annotate_which_problem(
         cwd: current_working_dir,
         program,
         path_env,
+        guess_limit: options.guess_limit,
         ..Which::default()
     }
     .diagnose();
 
     let annotation = match problem {
-        Ok(details) => format!("\nSystem diagnostic information:\n\n{details}"),
+        Ok(details) => format!(
+            "\nSystem diagnostic information:\n\n{}",
+            options.apply(details.to_string())
+        ),
         Err(error) => format!("\nInternal error while gathering dianostic information:\n\n{error}"),
     };
 
-    annotate_io_error(error, annotation)
+    annotate_io_error(crate::command::without_hint(error), annotation)
 }
 
 /// Returns an IO error that displays the given annotation starting on
@@ -673,14 +3845,33 @@ fn annotate_io_error(source: std::io::Error, annotation: String) -> std::io::Err
     IoErrorAnnotation::new(source, annotation).into_io_error()
 }
 
+/// A [CmdError] inside an `std::io::Error`, see [CmdError::into_io_error]
+#[derive(Debug)]
+struct IoCmdError(CmdError);
+
+impl std::fmt::Display for IoCmdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let display = self.0.to_string();
+        let summary = display
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default();
+        f.write_str(summary)
+    }
+}
+
+impl std::error::Error for IoCmdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
 #[derive(Debug)]
-#[cfg(feature = "which_problem")]
 pub(crate) struct IoErrorAnnotation {
     source: std::io::Error,
     annotation: String,
 }
 
-#[cfg(feature = "which_problem")]
 impl IoErrorAnnotation {
     pub(crate) fn new(source: std::io::Error, annotation: String) -> Self {
         Self { source, annotation }
@@ -691,7 +3882,6 @@ impl IoErrorAnnotation {
     }
 }
 
-#[cfg(feature = "which_problem")]
 impl std::fmt::Display for IoErrorAnnotation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{}", self.source)?;
@@ -700,7 +3890,6 @@ impl std::fmt::Display for IoErrorAnnotation {
     }
 }
 
-#[cfg(feature = "which_problem")]
 impl std::error::Error for IoErrorAnnotation {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         self.source()
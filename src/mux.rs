@@ -0,0 +1,147 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Longest partial line a line buffered writer holds before writing it out anyway
+///
+/// Keeps memory bounded when a command writes a lot of output without a newline, such as a
+/// progress bar redrawn with `\r`.
+pub(crate) const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Share one writer between stdout and stderr without interleaving partial lines
+///
+/// When a command writes to both stdout and stderr and both are streamed to the same
+/// destination (such as a terminal) a partial line from one stream can end up in the
+/// middle of a line from the other. Each [MultiplexedWriter] handed out by
+/// [LineMultiplexer::writer] buffers bytes until it sees a newline and then forwards
+/// only complete lines to the shared writer, so every line arrives intact. A line longer than
+/// 64 KiB is forwarded in pieces so output without newlines can't grow the buffer forever.
+///
+/// This only affects what is written to the shared writer, the captured `stdout` and
+/// `stderr` of the resulting [crate::NamedOutput] are unchanged.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, LineMultiplexer};
+/// use std::process::Command;
+///
+/// let mux = LineMultiplexer::new(std::io::stdout());
+///
+/// let output = Command::new("bash")
+///     .args(["-c", "echo -n 'hello ' && echo -n 'oops' >&2 && echo 'world'"])
+///     .stream_output(mux.writer(), mux.writer())
+///     .unwrap();
+///
+/// assert_eq!("hello world\n", output.stdout_lossy());
+/// assert_eq!("oops", output.stderr_lossy());
+/// ```
+#[derive(Debug)]
+pub struct LineMultiplexer<W> {
+    shared: Arc<Mutex<W>>,
+}
+
+impl<W: Write> LineMultiplexer<W> {
+    /// Wrap the writer that all lines will be forwarded to
+    pub fn new(writer: W) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    /// Returns a new writer that forwards complete lines to the shared writer
+    ///
+    /// Call once per stream. Any trailing partial line is forwarded when the returned
+    /// writer is dropped.
+    #[must_use]
+    pub fn writer(&self) -> MultiplexedWriter<W> {
+        MultiplexedWriter {
+            shared: Arc::clone(&self.shared),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// A line buffered writer created with [LineMultiplexer::writer]
+#[derive(Debug)]
+pub struct MultiplexedWriter<W: Write> {
+    shared: Arc<Mutex<W>>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> MultiplexedWriter<W> {
+    fn forward(&mut self, end: usize) -> io::Result<()> {
+        let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.write_all(&self.buffer[..end])?;
+        shared.flush()?;
+        self.buffer.drain(..end);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for MultiplexedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if let Some(index) = self.buffer.iter().rposition(|&byte| byte == b'\n') {
+            self.forward(index + 1)?;
+        }
+        if self.buffer.len() >= MAX_LINE_LENGTH {
+            self.forward(self.buffer.len())?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Flushes the shared writer, partial lines are held until they're complete
+    fn flush(&mut self) -> io::Result<()> {
+        self.shared
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush()
+    }
+}
+
+impl<W: Write> Drop for MultiplexedWriter<W> {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            let _ = self.forward(self.buffer.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_str_eq;
+
+    #[test]
+    fn test_partial_lines_are_not_interleaved() {
+        let mux = LineMultiplexer::new(Vec::new());
+        let mut stdout = mux.writer();
+        let mut stderr = mux.writer();
+
+        stdout.write_all(b"hello ").unwrap();
+        stderr.write_all(b"oh ").unwrap();
+        stdout.write_all(b"world\nagain").unwrap();
+        stderr.write_all(b"no\n").unwrap();
+        drop(stdout);
+        drop(stderr);
+
+        let shared = mux.shared.lock().unwrap();
+        assert_str_eq!(
+            "hello world\noh no\nagain",
+            String::from_utf8_lossy(&shared)
+        );
+    }
+
+    #[test]
+    fn test_long_lines_are_forwarded_in_pieces() {
+        let mux = LineMultiplexer::new(Vec::new());
+        let mut writer = mux.writer();
+
+        writer.write_all(&[b'.'; MAX_LINE_LENGTH - 1]).unwrap();
+        assert!(mux.shared.lock().unwrap().is_empty());
+
+        writer.write_all(b"..").unwrap();
+        assert_eq!(MAX_LINE_LENGTH + 1, mux.shared.lock().unwrap().len());
+        assert!(writer.buffer.is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Once, OnceLock};
+
+/// Most children tracked at once, children spawned while every slot is taken are not cleaned up
+const MAX_TRACKED: usize = 256;
+/// Signals that terminate tracked children before they reach the previous handler
+const TERMINATING: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+/// Pids of tracked children, negated for children that lead their own process group, zero
+/// for an empty slot. Atomics so the signal handler can read them.
+static TRACKED: [AtomicI32; MAX_TRACKED] = [const { AtomicI32::new(0) }; MAX_TRACKED];
+/// Threads running a captured command through [std::process::Command::output], which never
+/// exposes the child, zero for an empty slot. Their children are found through `/proc`.
+static CAPTURING: [AtomicI32; MAX_TRACKED] = [const { AtomicI32::new(0) }; MAX_TRACKED];
+/// Handlers replaced by [terminate_children_on_exit], called after terminating children
+static PREVIOUS: OnceLock<Vec<(libc::c_int, libc::sigaction)>> = OnceLock::new();
+
+/// Terminate commands that are still running when this process exits, panics or is
+/// interrupted, so they don't outlive it as orphans
+///
+/// Opt-in and process wide. Once called, every child spawned by fun_run is tracked and sent
+/// `SIGTERM` on normal exit, on a panic in the main thread and on `SIGHUP`, `SIGINT` or
+/// `SIGTERM`. Handlers for those signals that were installed beforehand still run afterwards.
+/// Commands that lead their own process group, such as those run through
+/// [crate::FunRunner::terminate_children_on_exit] or
+/// [crate::CommandWithName::stream_output_forwarding_signals], have their whole group
+/// terminated, including anything they spawned. A process killed with `SIGKILL` gets no
+/// chance to clean up.
+///
+/// Captured commands such as [crate::CommandWithName::named_output] keep any stdio set on
+/// the command. They are found through `/proc` on Linux and not tracked on other platforms.
+///
+/// Example:
+///
+/// ```no_run
+/// use fun_run::CommandWithName;
+/// use std::process::Command;
+///
+/// fun_run::terminate_children_on_exit();
+///
+/// let server = Command::new("bash")
+///     .args(["-c", "echo 'Listening' && exec sleep 100"])
+///     .spawn_until(
+///         regex::Regex::new("^Listening").unwrap(),
+///         std::time::Duration::from_secs(10),
+///         std::io::stdout(),
+///         std::io::stderr(),
+///     )
+///     .unwrap();
+///
+/// // The server is terminated when main returns, even without waiting on it
+/// std::mem::forget(server);
+/// ```
+pub fn terminate_children_on_exit() {
+    INSTALL.call_once(|| {
+        ENABLED.store(true, Ordering::SeqCst);
+
+        // SAFETY: The function only touches atomics and sends signals
+        unsafe {
+            libc::atexit(terminate_at_exit);
+        }
+
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous_hook(info);
+            if std::thread::current().name() == Some("main") {
+                terminate_all();
+            }
+        }));
+
+        // Previous handlers are stored before replacing them, so a signal arriving right after
+        // a handler is installed can already find the one it replaced
+        let previous = PREVIOUS.get_or_init(|| {
+            TERMINATING
+                .into_iter()
+                .filter_map(|signal| {
+                    // SAFETY: A null action only reads the current handler into `replaced`
+                    unsafe {
+                        let mut replaced: libc::sigaction = std::mem::zeroed();
+                        (libc::sigaction(signal, std::ptr::null(), &raw mut replaced) == 0)
+                            .then_some((signal, replaced))
+                    }
+                })
+                .collect()
+        });
+        for (signal, _) in previous {
+            // SAFETY: The handler only touches atomics, sends signals and calls the previous
+            // handler, which was already allowed to run in a signal handler
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = terminate_on_signal
+                    as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                    as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+                libc::sigemptyset(&raw mut action.sa_mask);
+                libc::sigaction(*signal, &raw const action, std::ptr::null_mut());
+            }
+        }
+    });
+}
+
+/// Returns true once [terminate_children_on_exit] was called
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Remember a spawned child so it can be terminated later
+///
+/// Slots of children that were already waited on are reused.
+#[allow(clippy::cast_possible_wrap)]
+pub(crate) fn track(child: &Child) {
+    if !enabled() {
+        return;
+    }
+    let pid = child.id() as i32;
+    // SAFETY: `getpgid` has no memory safety requirements
+    let entry = if unsafe { libc::getpgid(pid) } == pid {
+        -pid
+    } else {
+        pid
+    };
+    for slot in &TRACKED {
+        let current = slot.load(Ordering::SeqCst);
+        if (current == 0 || !is_unwaited_child(current.abs()))
+            && slot
+                .compare_exchange(current, entry, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Marks the current thread as running a captured command until the guard is dropped
+///
+/// Returns `None` when tracking is off, every slot is taken or children of a thread can't be
+/// listed on this platform.
+pub(crate) fn track_thread() -> Option<CapturingThread> {
+    if !enabled() {
+        return None;
+    }
+    let tid = current_thread_id()?;
+    CAPTURING.iter().find_map(|slot| {
+        slot.compare_exchange(0, tid, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+            .then_some(CapturingThread { slot })
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn current_thread_id() -> Option<libc::pid_t> {
+    // SAFETY: `gettid` has no memory safety requirements
+    Some(unsafe { libc::gettid() })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_thread_id() -> Option<libc::pid_t> {
+    None
+}
+
+/// Clears the slot taken by [track_thread] when the captured command finished
+pub(crate) struct CapturingThread {
+    slot: &'static AtomicI32,
+}
+
+impl Drop for CapturingThread {
+    fn drop(&mut self) {
+        self.slot.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Send `SIGTERM` to every tracked child that was not waited on yet
+pub(crate) fn terminate_all() {
+    for slot in &TRACKED {
+        let entry = slot.swap(0, Ordering::SeqCst);
+        if entry != 0 && is_unwaited_child(entry.abs()) {
+            // SAFETY: `kill` is async-signal-safe. The pid still belongs to our child, so it
+            // cannot have been reused by an unrelated process.
+            unsafe {
+                libc::kill(entry, libc::SIGTERM);
+            }
+        }
+    }
+    #[cfg(target_os = "linux")]
+    for slot in &CAPTURING {
+        let tid = slot.load(Ordering::SeqCst);
+        if tid != 0 {
+            terminate_children_of_thread(tid);
+        }
+    }
+}
+
+/// Send `SIGTERM` to the children spawned by a thread, or their group if they lead one
+///
+/// Reads `/proc/self/task/<tid>/children` with a stack buffer, so it is safe to call from a
+/// signal handler.
+#[cfg(target_os = "linux")]
+fn terminate_children_of_thread(tid: libc::pid_t) {
+    let mut path = [0_u8; 64];
+    let mut len = 0;
+    for part in [b"/proc/self/task/".as_slice(), &digits(tid), b"/children"] {
+        for &byte in part.iter().take_while(|&&byte| byte != 0) {
+            path[len] = byte;
+            len += 1;
+        }
+    }
+
+    let mut contents = [0_u8; 4096];
+    // SAFETY: `open`, `read` and `close` are async-signal-safe, the path is NUL terminated and
+    // `read` writes at most the length of `contents`
+    let read = unsafe {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_RDONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return;
+        }
+        let read = libc::read(fd, contents.as_mut_ptr().cast(), contents.len());
+        libc::close(fd);
+        read
+    };
+    let Ok(read) = usize::try_from(read) else {
+        return;
+    };
+
+    for pid in contents[..read].split(u8::is_ascii_whitespace) {
+        if !pid.iter().all(u8::is_ascii_digit) {
+            continue;
+        }
+        let pid = pid.iter().fold(0, |pid: libc::pid_t, digit| {
+            pid.wrapping_mul(10)
+                .wrapping_add(libc::pid_t::from(digit - b'0'))
+        });
+        if pid > 0 && is_unwaited_child(pid) {
+            // SAFETY: `getpgid` and `kill` are plain system calls. The pid still belongs to
+            // our child, so it cannot have been reused by an unrelated process.
+            unsafe {
+                let target = if libc::getpgid(pid) == pid { -pid } else { pid };
+                libc::kill(target, libc::SIGTERM);
+            }
+        }
+    }
+}
+
+/// The decimal digits of a positive number followed by NUL bytes, without allocating
+#[cfg(target_os = "linux")]
+fn digits(mut number: libc::pid_t) -> [u8; 12] {
+    let mut digits = [0_u8; 12];
+    let mut len = 0;
+    while number > 0 || len == 0 {
+        digits[len] = b'0' + u8::try_from(number % 10).unwrap_or_default();
+        number /= 10;
+        len += 1;
+    }
+    digits[..len].reverse();
+    digits
+}
+
+/// Returns true if `pid` is a child of this process that is running or exited without being
+/// waited on
+///
+/// Once a child is waited on its pid can be reused, checking first keeps an unrelated process
+/// from being terminated.
+#[allow(clippy::cast_sign_loss)]
+fn is_unwaited_child(pid: libc::pid_t) -> bool {
+    // SAFETY: `WNOWAIT` leaves the child to be waited on by its owner, `waitid` only writes
+    // to `info`
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &raw mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        ) == 0
+    }
+}
+
+extern "C" fn terminate_at_exit() {
+    terminate_all();
+}
+
+extern "C" fn terminate_on_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    terminate_all();
+
+    let previous = PREVIOUS.get().and_then(|previous| {
+        previous
+            .iter()
+            .find(|(previous_signal, _)| *previous_signal == signal)
+            .map(|(_, action)| *action)
+    });
+    match previous {
+        Some(action) if action.sa_sigaction == libc::SIG_IGN => {}
+        Some(action) if action.sa_sigaction != libc::SIG_DFL => {
+            // SAFETY: The previous handler was installed for this signal with these flags, so
+            // it has the matching signature
+            unsafe {
+                if action.sa_flags & libc::SA_SIGINFO == 0 {
+                    let handler: extern "C" fn(libc::c_int) =
+                        std::mem::transmute(action.sa_sigaction);
+                    handler(signal);
+                } else {
+                    let handler: extern "C" fn(
+                        libc::c_int,
+                        *mut libc::siginfo_t,
+                        *mut libc::c_void,
+                    ) = std::mem::transmute(action.sa_sigaction);
+                    handler(signal, info, context);
+                }
+            }
+        }
+        _ => {
+            // SAFETY: `signal` and `raise` are async-signal-safe. The signal is blocked until
+            // this handler returns, then the default action ends the process.
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::NamedOutput;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{ExitStatus, Output};
+
+/// First line of a saved output, bumped when the format changes
+const HEADER: &str = "fun_run NamedOutput 1";
+
+impl NamedOutput {
+    /// Write the name, exit status, stdout and stderr to a file
+    ///
+    /// Load it with [NamedOutput::load], for example to stash the result of a build step as an
+    /// artifact and show the same error message in a later process. The file is a small
+    /// container format: a header line followed by each field's name and length on a line of
+    /// its own, then the raw bytes. Output is stored as is so non UTF-8 output survives.
+    /// Captured lines, the transcript and the run id are not saved.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, NamedOutput};
+    /// use std::process::Command;
+    ///
+    /// let path = std::env::temp_dir().join("fun_run_save_doctest.out");
+    /// let error = Command::new("bash")
+    ///     .args(["-c", "echo 'Gem::MissingSpecError' >&2; exit 1"])
+    ///     .named_output()
+    ///     .unwrap_err();
+    /// NamedOutput::from(error).save(&path).unwrap();
+    ///
+    /// // Later, maybe in another process
+    /// let error = NamedOutput::load(&path)
+    ///     .unwrap()
+    ///     .nonzero_captured()
+    ///     .unwrap_err();
+    /// assert!(error.to_string().contains("Gem::MissingSpecError"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    /// Read an output written by [NamedOutput::save]
+    ///
+    /// The loaded output gets a new [NamedOutput::run_id].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, or an `InvalidData` error if it was not
+    /// written by [NamedOutput::save].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<NamedOutput> {
+        Self::read_from(BufReader::new(std::fs::File::open(path)?))
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{HEADER}")?;
+        write_field(writer, "name", self.name.as_bytes())?;
+        write_field(
+            writer,
+            "status",
+            self.output.status.into_raw().to_string().as_bytes(),
+        )?;
+        write_field(writer, "stdout", &self.output.stdout)?;
+        write_field(writer, "stderr", &self.output.stderr)
+    }
+
+    fn read_from(mut reader: impl BufRead) -> io::Result<NamedOutput> {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        if header.trim_end() != HEADER {
+            return Err(invalid_data(format!(
+                "Expected `{HEADER}` but found {header:?}"
+            )));
+        }
+
+        let name = String::from_utf8(read_field(&mut reader, "name")?)
+            .map_err(|error| invalid_data(format!("Name is not UTF-8. {error}")))?;
+        let status = String::from_utf8(read_field(&mut reader, "status")?)
+            .ok()
+            .and_then(|status| status.parse().ok())
+            .map(ExitStatus::from_raw)
+            .ok_or_else(|| invalid_data("Status is not a number".to_string()))?;
+        let stdout = read_field(&mut reader, "stdout")?;
+        let stderr = read_field(&mut reader, "stderr")?;
+
+        Ok(NamedOutput::new(
+            name,
+            Output {
+                status,
+                stdout,
+                stderr,
+            },
+        ))
+    }
+}
+
+fn write_field(writer: &mut impl Write, key: &str, value: &[u8]) -> io::Result<()> {
+    writeln!(writer, "{key} {}", value.len())?;
+    writer.write_all(value)?;
+    writer.write_all(b"\n")
+}
+
+fn read_field(mut reader: impl BufRead, key: &str) -> io::Result<Vec<u8>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let len = line
+        .trim_end()
+        .strip_prefix(key)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or_else(|| invalid_data(format!("Expected `{key} <length>` but found {line:?}")))?;
+
+    let mut value = Vec::new();
+    reader.take(len as u64 + 1).read_to_end(&mut value)?;
+    if value.len() != len + 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Expected {len} bytes of {key}"),
+        ));
+    }
+    if value.pop() != Some(b'\n') {
+        return Err(invalid_data(format!("Expected a newline after {key}")));
+    }
+    Ok(value)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_keeps_raw_bytes() {
+        let output = NamedOutput::new(
+            "make 'all'\n",
+            Output {
+                status: ExitStatus::from_raw(libc::SIGKILL),
+                stdout: b"caf\xe9\n\nstdout 3\n".to_vec(),
+                stderr: Vec::new(),
+            },
+        );
+
+        let mut bytes = Vec::new();
+        output.write_to(&mut bytes).unwrap();
+        let loaded = NamedOutput::read_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(output, loaded);
+        assert_eq!(Some(libc::SIGKILL), loaded.signal());
+        assert_ne!(output.run_id(), loaded.run_id());
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let error = NamedOutput::read_from(&b"hello\n"[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        let error =
+            NamedOutput::read_from(format!("{HEADER}\nname 100\nshort\n").as_bytes()).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    }
+}
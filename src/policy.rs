@@ -0,0 +1,238 @@
+use crate::{CmdError, NamedOutput};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, Output};
+use std::thread;
+use std::time::Duration;
+
+/// What to do with a finished run, see [ExitPolicy::decide]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitDecision {
+    /// The run succeeded
+    Success,
+    /// The run failed in a way that may succeed if run again
+    Retry,
+    /// The run failed and running it again will not help
+    Fail,
+}
+
+/// Declares which exit statuses count as success, which are worth retrying and which fail
+///
+/// Consumed by [crate::CommandWithName::named_output_with_policy]. Exit codes that are neither
+/// a success nor retryable fail. A command killed by a signal fails by default since the
+/// signal may come from the user pressing Ctrl+C or a timeout. Use
+/// [ExitPolicy::retry_on_signal] to retry runs killed by something like the OOM killer.
+///
+/// Failures can also be retried based on stderr with [ExitPolicy::retry_on_stderr], so
+/// network flakes are retried while a compile error fails fast.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{ExitDecision, ExitPolicy};
+/// use std::os::unix::process::ExitStatusExt;
+/// use std::process::{ExitStatus, Output};
+///
+/// let policy = ExitPolicy::success_on([0, 1]).retry_on([75, 111]).fail_on_signal();
+/// let output = |raw| Output {
+///     status: ExitStatus::from_raw(raw),
+///     stdout: Vec::new(),
+///     stderr: Vec::new(),
+/// };
+///
+/// assert_eq!(ExitDecision::Success, policy.decide(&output(1 << 8)));
+/// assert_eq!(ExitDecision::Retry, policy.decide(&output(75 << 8)));
+/// assert_eq!(ExitDecision::Fail, policy.decide(&output(2 << 8)));
+/// assert_eq!(ExitDecision::Fail, policy.decide(&output(9)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExitPolicy {
+    success: Vec<i32>,
+    retry: Vec<i32>,
+    #[cfg(feature = "regex")]
+    retry_stderr: Vec<Regex>,
+    retry_on_signal: bool,
+    max_retries: usize,
+    retry_delay: Duration,
+}
+
+impl Default for ExitPolicy {
+    /// Succeed on 0 and fail on anything else
+    fn default() -> Self {
+        Self::success_on([0])
+    }
+}
+
+impl ExitPolicy {
+    /// Exit codes that count as success
+    #[must_use]
+    pub fn success_on(codes: impl IntoIterator<Item = i32>) -> Self {
+        Self {
+            success: codes.into_iter().collect(),
+            retry: Vec::new(),
+            #[cfg(feature = "regex")]
+            retry_stderr: Vec::new(),
+            retry_on_signal: false,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Exit codes that are worth running the command again for
+    #[must_use]
+    pub fn retry_on(mut self, codes: impl IntoIterator<Item = i32>) -> Self {
+        self.retry.extend(codes);
+        self
+    }
+
+    /// Retry a failed run when its stderr matches `pattern`, regardless of the exit code
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{ExitDecision, ExitPolicy};
+    /// use regex::Regex;
+    /// use std::os::unix::process::ExitStatusExt;
+    /// use std::process::{ExitStatus, Output};
+    ///
+    /// let policy = ExitPolicy::default()
+    ///     .retry_on_stderr(Regex::new("connection reset|timed out").unwrap());
+    /// let output = |stderr: &str| Output {
+    ///     status: ExitStatus::from_raw(1 << 8),
+    ///     stdout: Vec::new(),
+    ///     stderr: stderr.as_bytes().to_vec(),
+    /// };
+    ///
+    /// assert_eq!(ExitDecision::Retry, policy.decide(&output("fetch: connection reset by peer")));
+    /// assert_eq!(ExitDecision::Fail, policy.decide(&output("error[E0425]: cannot find value")));
+    /// ```
+    #[must_use]
+    #[cfg(feature = "regex")]
+    pub fn retry_on_stderr(mut self, pattern: Regex) -> Self {
+        self.retry_stderr.push(pattern);
+        self
+    }
+
+    /// Fail immediately when the command is killed by a signal, the default
+    #[must_use]
+    pub fn fail_on_signal(mut self) -> Self {
+        self.retry_on_signal = false;
+        self
+    }
+
+    /// Retry when the command is killed by a signal other than SIGINT or SIGTERM
+    ///
+    /// SIGINT and SIGTERM ask the command to stop, such as when the user presses Ctrl+C, so they
+    /// always fail.
+    #[must_use]
+    pub fn retry_on_signal(mut self) -> Self {
+        self.retry_on_signal = true;
+        self
+    }
+
+    /// Maximum number of times to run the command again, defaults to 3
+    #[must_use]
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait before running the command again, defaults to 1 second
+    #[must_use]
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Decide what to do with the output of a finished run
+    #[must_use]
+    pub fn decide(&self, output: &Output) -> ExitDecision {
+        match output.status.code() {
+            Some(code) if self.success.contains(&code) => ExitDecision::Success,
+            Some(code) if self.retry.contains(&code) => ExitDecision::Retry,
+            _ if self.stderr_is_retryable(&output.stderr) => ExitDecision::Retry,
+            None if self.retry_on_signal
+                && output
+                    .status
+                    .signal()
+                    .is_some_and(|signal| ![libc::SIGINT, libc::SIGTERM].contains(&signal)) =>
+            {
+                ExitDecision::Retry
+            }
+            _ => ExitDecision::Fail,
+        }
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[allow(clippy::unused_self)]
+    fn stderr_is_retryable(&self, _stderr: &[u8]) -> bool {
+        false
+    }
+
+    #[cfg(feature = "regex")]
+    fn stderr_is_retryable(&self, stderr: &[u8]) -> bool {
+        if self.retry_stderr.is_empty() {
+            return false;
+        }
+        let stderr = String::from_utf8_lossy(stderr);
+        self.retry_stderr
+            .iter()
+            .any(|pattern| pattern.is_match(&stderr))
+    }
+
+    /// Runs the command, running it again while the policy says to retry
+    pub(crate) fn run(&self, name: &str, command: &mut Command) -> Result<NamedOutput, CmdError> {
+        let mut retries = 0;
+        loop {
+            let run_id = crate::next_run_id();
+            let named_output = crate::command::output(command)
+                .map_err(|error| CmdError::SystemError(name.to_string(), error, Some(run_id)))
+                .map(|output| NamedOutput::for_run(name, run_id, output))?;
+
+            match self.decide(named_output.output()) {
+                ExitDecision::Success => return Ok(named_output),
+                ExitDecision::Retry if retries < self.max_retries => {
+                    retries += 1;
+                    thread::sleep(self.retry_delay);
+                }
+                ExitDecision::Retry | ExitDecision::Fail => {
+                    return Err(CmdError::NonZeroExitNotStreamed(named_output))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::ExitStatus;
+
+    fn output(raw: i32) -> Output {
+        Output {
+            status: ExitStatus::from_raw(raw),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = ExitPolicy::default();
+
+        assert_eq!(ExitDecision::Success, policy.decide(&output(0)));
+        assert_eq!(ExitDecision::Fail, policy.decide(&output(1 << 8)));
+        assert_eq!(ExitDecision::Fail, policy.decide(&output(libc::SIGINT)));
+        assert_eq!(ExitDecision::Fail, policy.decide(&output(libc::SIGKILL)));
+    }
+
+    #[test]
+    fn test_retry_on_signal_skips_requests_to_stop() {
+        let policy = ExitPolicy::default().retry_on_signal();
+
+        assert_eq!(ExitDecision::Retry, policy.decide(&output(libc::SIGKILL)));
+        assert_eq!(ExitDecision::Fail, policy.decide(&output(libc::SIGINT)));
+        assert_eq!(ExitDecision::Fail, policy.decide(&output(libc::SIGTERM)));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::Stream;
+use std::io::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Bytes read from a command's stdout and stderr so far
+///
+/// Passed to the callback of [crate::CommandWithName::stream_output_with_progress].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteProgress {
+    pub stdout: usize,
+    pub stderr: usize,
+}
+
+impl ByteProgress {
+    /// Bytes read from both streams
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.stdout + self.stderr
+    }
+}
+
+/// Counts bytes from both streams and reports them to a callback
+pub(crate) struct ProgressTracker<F> {
+    state: Mutex<(ByteProgress, F)>,
+}
+
+impl<F: FnMut(ByteProgress) + Send> ProgressTracker<F> {
+    pub(crate) fn new(on_progress: F) -> Self {
+        Self {
+            state: Mutex::new((ByteProgress::default(), on_progress)),
+        }
+    }
+
+    /// Wraps the writer for one stream so every write is counted
+    pub(crate) fn writer<W: Write>(&self, stream: Stream, inner: W) -> ProgressWrite<'_, W, F> {
+        ProgressWrite {
+            inner,
+            stream,
+            tracker: self,
+        }
+    }
+
+    fn record(&self, stream: Stream, bytes: usize) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (progress, on_progress) = &mut *state;
+        match stream {
+            Stream::Stdout => progress.stdout += bytes,
+            Stream::Stderr => progress.stderr += bytes,
+        }
+        on_progress(*progress);
+    }
+}
+
+/// A writer created by [ProgressTracker::writer]
+pub(crate) struct ProgressWrite<'a, W, F> {
+    inner: W,
+    stream: Stream,
+    tracker: &'a ProgressTracker<F>,
+}
+
+impl<W: Write, F: FnMut(ByteProgress) + Send> Write for ProgressWrite<'_, W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.tracker.record(self.stream, written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// When a streamed command's output arrived, see [crate::NamedOutput::stats]
+///
+/// Helps tell a hung command from a slow one: a long silence means nothing was printed for
+/// that long, while steady output with a low rate points at a slow network or disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputStats {
+    bytes: usize,
+    duration: Duration,
+    longest_silence: Duration,
+    phases: PhaseTimings,
+}
+
+impl OutputStats {
+    /// Bytes of stdout and stderr combined
+    #[must_use]
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Time from spawning the command until it exited
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The longest time without any output, including before the first and after the last output
+    #[must_use]
+    pub fn longest_silence(&self) -> Duration {
+        self.longest_silence
+    }
+
+    /// Where the time went, see [PhaseTimings]
+    #[must_use]
+    pub fn phases(&self) -> PhaseTimings {
+        self.phases
+    }
+
+    /// Average bytes of output per second over the whole run
+    #[must_use]
+    pub fn bytes_per_sec(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::fmt::Display for OutputStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes in {:.2}s ({:.0} bytes/sec), longest silence {:.2}s",
+            self.bytes,
+            self.duration.as_secs_f64(),
+            self.bytes_per_sec(),
+            self.longest_silence.as_secs_f64()
+        )
+    }
+}
+
+/// How long a streamed command spent in each phase of running, see
+/// [crate::NamedOutput::phases]
+///
+/// Tells "the process was slow" apart from "we were slow to drain its output": reading ends
+/// when both streams are closed, which is usually when the process exits, so a long
+/// [PhaseTimings::wait] after reading points at a process that closed its output but kept
+/// running, while a slow writer shows up in [PhaseTimings::read].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PhaseTimings {
+    spawn: Duration,
+    read: Duration,
+    wait: Duration,
+}
+
+impl PhaseTimings {
+    /// Time to start the process
+    #[must_use]
+    pub fn spawn(&self) -> Duration {
+        self.spawn
+    }
+
+    /// Time from the process starting until stdout and stderr were read to the end
+    #[must_use]
+    pub fn read(&self) -> Duration {
+        self.read
+    }
+
+    /// Time from reading all output until the process exited
+    #[must_use]
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    /// Sum of the phases
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.spawn + self.read + self.wait
+    }
+}
+
+impl std::fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "spawn {:.3}s, read {:.3}s, wait {:.3}s",
+            self.spawn.as_secs_f64(),
+            self.read.as_secs_f64(),
+            self.wait.as_secs_f64()
+        )
+    }
+}
+
+/// Records when each phase of a streamed run ends, for [PhaseTimings]
+pub(crate) struct PhaseTimer {
+    start: Instant,
+    spawned: Option<Instant>,
+    read: Option<Instant>,
+}
+
+impl PhaseTimer {
+    pub(crate) fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            spawned: None,
+            read: None,
+        }
+    }
+
+    /// The process started
+    pub(crate) fn spawned(&mut self) {
+        self.spawned = Some(Instant::now());
+    }
+
+    /// Both output streams were read to the end
+    pub(crate) fn read(&mut self) {
+        self.read = Some(Instant::now());
+    }
+
+    /// The process exited
+    pub(crate) fn finish(self) -> PhaseTimings {
+        let exited = Instant::now();
+        let spawned = self.spawned.unwrap_or(exited);
+        let read = self.read.unwrap_or(exited);
+        PhaseTimings {
+            spawn: spawned - self.start,
+            read: read.saturating_duration_since(spawned),
+            wait: exited.saturating_duration_since(read),
+        }
+    }
+}
+
+/// Tracks the gaps between output for [OutputStats]
+pub(crate) struct SilenceTimer {
+    start: Instant,
+    last: Instant,
+    longest: Duration,
+}
+
+impl SilenceTimer {
+    pub(crate) fn start() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            longest: Duration::ZERO,
+        }
+    }
+
+    /// Output arrived
+    pub(crate) fn record(&mut self) {
+        let now = Instant::now();
+        self.longest = self.longest.max(now - self.last);
+        self.last = now;
+    }
+
+    /// The command exited
+    pub(crate) fn finish(mut self, bytes: usize, phases: PhaseTimings) -> OutputStats {
+        self.record();
+        OutputStats {
+            bytes,
+            duration: self.last - self.start,
+            longest_silence: self.longest,
+            phases,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::process::Command;
+
+    #[test]
+    fn test_progress_counts_both_streams() {
+        let mut updates = Vec::new();
+        let output = Command::new("bash")
+            .args(["-c", "printf hello; printf oops >&2"])
+            .stream_output_with_progress(std::io::sink(), std::io::sink(), |progress| {
+                updates.push(progress);
+            })
+            .unwrap();
+
+        assert_eq!(
+            Some(&ByteProgress {
+                stdout: 5,
+                stderr: 4
+            }),
+            updates.last()
+        );
+        assert_eq!(5, output.stdout_bytes());
+        assert_eq!(4, output.stderr_bytes());
+    }
+
+    #[test]
+    fn test_stats_find_longest_silence() {
+        let output = Command::new("bash")
+            .args(["-c", "echo one; sleep 0.4; echo two; sleep 0.1"])
+            .stream_output(std::io::sink(), std::io::sink())
+            .unwrap();
+
+        let stats = output.stats().unwrap();
+        assert_eq!(8, stats.bytes());
+        assert!(stats.longest_silence() >= Duration::from_millis(350));
+        assert!(stats.longest_silence() <= stats.duration());
+        assert!(stats.bytes_per_sec() > 0.0);
+
+        let output = Command::new("true").named_output().unwrap();
+        assert_eq!(None, output.stats());
+    }
+
+    #[test]
+    fn test_phases_separate_reading_from_waiting() {
+        // The script keeps running after closing stdout and stderr
+        let output = Command::new("bash")
+            .args(["-c", "echo start; sleep 0.2; exec >&- 2>&-; sleep 0.3"])
+            .stream_output(std::io::sink(), std::io::sink())
+            .unwrap();
+
+        let phases = output.phases().unwrap();
+        assert!(phases.read() >= Duration::from_millis(150), "{phases}");
+        assert!(phases.wait() >= Duration::from_millis(250), "{phases}");
+        assert!(phases.total() <= output.stats().unwrap().duration() + Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,461 @@
+use crate::Stream;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Records everything a command reads and writes, with the time it happened
+///
+/// Wrap the writers passed to streaming methods such as
+/// [crate::CommandWithName::stream_output] with [Recorder::writer], or hand the recorder to
+/// [crate::Interaction::recorder] to also record what is sent to stdin. Clones share the same
+/// recording, take it with [Recorder::recording] once the command finishes.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Recorder, Stream};
+/// use std::process::Command;
+///
+/// let recorder = Recorder::new();
+/// let _ = Command::new("bash")
+///     .args(["-c", "echo 'compiling' && echo 'error: oops' >&2 && exit 1"])
+///     .stream_output(
+///         recorder.writer(Stream::Stdout, std::io::sink()),
+///         recorder.writer(Stream::Stderr, std::io::sink()),
+///     );
+///
+/// let recording = recorder.recording();
+/// let mut stdout = Vec::new();
+/// let mut stderr = Vec::new();
+/// recording.replay(&mut stdout, &mut stderr).unwrap();
+///
+/// assert_eq!("compiling\n", String::from_utf8_lossy(&stdout));
+/// assert_eq!("error: oops\n", String::from_utf8_lossy(&stderr));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    start: Instant,
+    log: Arc<Mutex<Log>>,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    events: Vec<RecordedEvent>,
+    /// Bytes recorded so far on each channel, the offset of the next event
+    totals: [usize; 3],
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            log: Arc::default(),
+        }
+    }
+}
+
+impl Recorder {
+    /// Start a recording, event times are measured from now
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a writer that forwards all bytes to `inner` while recording them as `stream`
+    #[must_use]
+    pub fn writer<W: Write>(&self, stream: Stream, inner: W) -> RecordingWriter<W> {
+        RecordingWriter {
+            inner,
+            channel: stream.into(),
+            recorder: self.clone(),
+        }
+    }
+
+    /// Record bytes sent to the command's stdin
+    pub fn input(&self, data: &[u8]) {
+        self.record(Channel::Stdin, data);
+    }
+
+    /// Returns the events recorded so far
+    #[must_use]
+    pub fn recording(&self) -> Recording {
+        Recording {
+            events: self
+                .log
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .events
+                .clone(),
+        }
+    }
+
+    pub(crate) fn record(&self, channel: Channel, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let time = self.start.elapsed();
+        let mut log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        let offset = log.totals[channel as usize];
+        log.totals[channel as usize] += data.len();
+        log.events.push(RecordedEvent {
+            time,
+            channel,
+            offset,
+            data: data.to_vec(),
+        });
+    }
+}
+
+/// A writer created with [Recorder::writer]
+#[derive(Debug)]
+pub struct RecordingWriter<W: Write> {
+    inner: W,
+    channel: Channel,
+    recorder: Recorder,
+}
+
+impl<W: Write> Write for RecordingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.recorder.record(self.channel, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Which stream of the command an event belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl From<Stream> for Channel {
+    fn from(stream: Stream) -> Self {
+        match stream {
+            Stream::Stdout => Channel::Stdout,
+            Stream::Stderr => Channel::Stderr,
+        }
+    }
+}
+
+impl Channel {
+    #[cfg(feature = "serde")]
+    fn code(self) -> &'static str {
+        match self {
+            Channel::Stdin => "i",
+            Channel::Stdout => "o",
+            Channel::Stderr => "e",
+        }
+    }
+}
+
+/// Bytes read or written by the command at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// Since the [Recorder] was created
+    pub time: Duration,
+    pub channel: Channel,
+    /// Number of bytes on the same channel before this event
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+/// The events of a [Recorder] in the order they happened
+///
+/// With the `serde` feature save it with `Recording::write_to` and load it with
+/// `Recording::read_from` so the run can be replayed somewhere else, such as by a support
+/// engineer looking at a failed build.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    /// Every recorded event, in order
+    #[must_use]
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Write the recorded stdout and stderr to the writers at once, input is skipped
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn replay(&self, stdout_write: impl Write, stderr_write: impl Write) -> io::Result<()> {
+        self.replay_paced(stdout_write, stderr_write, |_| {})
+    }
+
+    /// Like [Recording::replay] but waits between events so output arrives as fast as it
+    /// did when recorded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn replay_in_real_time(
+        &self,
+        stdout_write: impl Write,
+        stderr_write: impl Write,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        self.replay_paced(stdout_write, stderr_write, |time| {
+            std::thread::sleep(time.saturating_sub(start.elapsed()));
+        })
+    }
+
+    fn replay_paced(
+        &self,
+        mut stdout_write: impl Write,
+        mut stderr_write: impl Write,
+        mut wait: impl FnMut(Duration),
+    ) -> io::Result<()> {
+        for event in &self.events {
+            let writer: &mut dyn Write = match event.channel {
+                Channel::Stdin => continue,
+                Channel::Stdout => &mut stdout_write,
+                Channel::Stderr => &mut stderr_write,
+            };
+            wait(event.time);
+            writer.write_all(&event.data)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Save as an asciinema-like file
+    ///
+    /// The first line is a header, then one JSON array per event: seconds since the start,
+    /// `"i"`, `"o"` or `"e"` for stdin, stdout or stderr, the data as a string and the byte
+    /// offset of the data on its channel. Data that is not UTF-8 is saved lossily, characters
+    /// split between events are kept whole and a character cut off at the end is saved as
+    /// a replacement character.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::{CommandWithName, Recorder, Recording, Stream};
+    /// use std::process::Command;
+    ///
+    /// let recorder = Recorder::new();
+    /// Command::new("echo")
+    ///     .arg("hello")
+    ///     .stream_output(
+    ///         recorder.writer(Stream::Stdout, std::io::sink()),
+    ///         recorder.writer(Stream::Stderr, std::io::sink()),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// let mut file = Vec::new();
+    /// recorder.recording().write_to(&mut file).unwrap();
+    /// assert!(String::from_utf8_lossy(&file).lines().nth(1).unwrap().ends_with(r#","o","hello\n",0]"#));
+    ///
+    /// let recording = Recording::read_from(file.as_slice()).unwrap();
+    /// assert_eq!(recorder.recording(), recording);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    #[cfg(feature = "serde")]
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let size = crate::TerminalSize::detect().unwrap_or(crate::TerminalSize::new(80, 24));
+        let header = serde_json::json!({
+            "version": 2,
+            "width": size.columns,
+            "height": size.rows,
+        });
+        writeln!(writer, "{header}")?;
+
+        // Bytes of a character that continues in the next event of the same channel
+        let mut partial: [Vec<u8>; 3] = Default::default();
+        // Time and end offset of the last event of each channel
+        let mut last = [(Duration::ZERO, 0); 3];
+        for event in &self.events {
+            last[event.channel as usize] = (event.time, event.offset + event.data.len());
+            let partial = &mut partial[event.channel as usize];
+            let offset = event.offset - partial.len();
+            partial.extend_from_slice(&event.data);
+            let complete = match std::str::from_utf8(partial) {
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                _ => partial.len(),
+            };
+            let data = String::from_utf8_lossy(&partial[..complete]).to_string();
+            partial.drain(..complete);
+            if data.is_empty() {
+                continue;
+            }
+            let line =
+                serde_json::json!([event.time.as_secs_f64(), event.channel.code(), data, offset,]);
+            writeln!(writer, "{line}")?;
+        }
+
+        // A character that never finished, such as when the command was killed mid-write
+        let mut leftovers = [Channel::Stdin, Channel::Stdout, Channel::Stderr]
+            .into_iter()
+            .filter(|channel| !partial[*channel as usize].is_empty())
+            .collect::<Vec<_>>();
+        leftovers.sort_by_key(|channel| last[*channel as usize].0);
+        for channel in leftovers {
+            let partial = &partial[channel as usize];
+            let (time, end) = last[channel as usize];
+            let data = String::from_utf8_lossy(partial);
+            let line = serde_json::json!([
+                time.as_secs_f64(),
+                channel.code(),
+                data,
+                end - partial.len()
+            ]);
+            writeln!(writer, "{line}")?;
+        }
+        writer.flush()
+    }
+
+    /// Load a file saved with [Recording::write_to]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or a line is not a recorded event.
+    #[cfg(feature = "serde")]
+    pub fn read_from(reader: impl io::Read) -> io::Result<Self> {
+        use std::io::BufRead;
+
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Not a recorded event: {line}"),
+            )
+        };
+        let mut events = Vec::new();
+        for line in io::BufReader::new(reader).lines().skip(1) {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value =
+                serde_json::from_str::<serde_json::Value>(&line).map_err(|_| invalid(&line))?;
+            let event = match value.as_array().map(Vec::as_slice) {
+                Some([time, code, data, offset]) => {
+                    let channel = match code.as_str() {
+                        Some("i") => Channel::Stdin,
+                        Some("o") => Channel::Stdout,
+                        Some("e") => Channel::Stderr,
+                        _ => return Err(invalid(&line)),
+                    };
+                    RecordedEvent {
+                        time: time
+                            .as_f64()
+                            .and_then(|time| Duration::try_from_secs_f64(time).ok())
+                            .ok_or_else(|| invalid(&line))?,
+                        channel,
+                        offset: offset
+                            .as_u64()
+                            .and_then(|offset| usize::try_from(offset).ok())
+                            .ok_or_else(|| invalid(&line))?,
+                        data: data
+                            .as_str()
+                            .ok_or_else(|| invalid(&line))?
+                            .as_bytes()
+                            .to_vec(),
+                    }
+                }
+                _ => return Err(invalid(&line)),
+            };
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_offsets_count_bytes_per_channel() {
+        let recorder = Recorder::new();
+        let mut stdout = recorder.writer(Stream::Stdout, Vec::new());
+        let mut stderr = recorder.writer(Stream::Stderr, Vec::new());
+
+        stdout.write_all(b"one ").unwrap();
+        stderr.write_all(b"oops").unwrap();
+        recorder.input(b"y\n");
+        stdout.write_all(b"two").unwrap();
+
+        let offsets = recorder
+            .recording()
+            .events()
+            .iter()
+            .map(|event| (event.channel, event.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Channel::Stdout, 0),
+                (Channel::Stderr, 0),
+                (Channel::Stdin, 0),
+                (Channel::Stdout, 4)
+            ],
+            offsets
+        );
+        assert_eq!(b"one two", stdout.inner.as_slice());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_characters_split_between_events_are_saved_whole() {
+        let recorder = Recorder::new();
+        let snowman = "☃".as_bytes();
+        recorder.record(Channel::Stdout, &snowman[..1]);
+        recorder.record(Channel::Stderr, b"!");
+        recorder.record(Channel::Stdout, &snowman[1..]);
+
+        let mut file = Vec::new();
+        recorder.recording().write_to(&mut file).unwrap();
+        let recording = Recording::read_from(file.as_slice()).unwrap();
+
+        let events = recording
+            .events()
+            .iter()
+            .map(|event| (event.channel, event.offset, event.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Channel::Stderr, 0, b"!".to_vec()),
+                (Channel::Stdout, 0, snowman.to_vec())
+            ],
+            events
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_incomplete_character_at_the_end_is_saved_lossily() {
+        let recorder = Recorder::new();
+        let snowman = "☃".as_bytes();
+        recorder.record(Channel::Stdout, b"ok ");
+        recorder.record(Channel::Stdout, &snowman[..2]);
+        recorder.record(Channel::Stderr, b"!");
+
+        let mut file = Vec::new();
+        recorder.recording().write_to(&mut file).unwrap();
+        let recording = Recording::read_from(file.as_slice()).unwrap();
+
+        let events = recording
+            .events()
+            .iter()
+            .map(|event| (event.channel, event.offset, event.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Channel::Stdout, 0, b"ok ".to_vec()),
+                (Channel::Stderr, 0, b"!".to_vec()),
+                (Channel::Stdout, 3, "\u{FFFD}".as_bytes().to_vec())
+            ],
+            events
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    BackendIo, CmdError, CommandSpec, CommandWithName, ExecutionBackend, IoErrorAnnotation,
+    NamedCommand, NamedOutput,
+};
+use std::ffi::CString;
+use std::os::unix::process::CommandExt;
+
+/// Runs commands in new Linux namespaces, isolating them without a container runtime
+///
+/// Choose what to isolate: [Sandbox::mount] so mounts don't leak out, [Sandbox::no_network]
+/// for a network with only an unconfigured loopback device and [Sandbox::pid] so processes
+/// the command starts get their own pids and can't signal anything outside. They still see
+/// every process through `/proc` unless the command also has [Sandbox::mount] and mounts a
+/// fresh `/proc`. The pid namespace applies to the children of the command, the command
+/// itself keeps its pid.
+///
+/// Creating namespaces takes `CAP_SYS_ADMIN`. Add [Sandbox::user] to create a user
+/// namespace first, which unprivileged users can do where the kernel allows it. The command
+/// then runs as root inside the namespace, mapped to the current user outside. As an
+/// [ExecutionBackend] a spawn that fails to create namespaces says why in the error. The
+/// name lists what is isolated, such as `(sandboxed: network) make test`, and is unchanged
+/// when nothing is.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{BackendIo, CommandSpec, CommandWithName, ExecutionBackend, Sandbox};
+/// use std::process::Command;
+///
+/// let sandbox = Sandbox::new().user().no_network();
+/// let mut command = Command::new("cat");
+/// command.arg("/proc/net/dev");
+/// let mut spec = CommandSpec::new(command);
+///
+/// match sandbox.run(&mut spec, BackendIo::Capture) {
+///     Ok(output) => {
+///         assert_eq!("(sandboxed: user, network) cat /proc/net/dev", output.name());
+///         assert!(!output.stdout_lossy().contains("eth0"));
+///     }
+///     Err(error) => assert!(error.to_string().contains("namespace")),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sandbox {
+    user: bool,
+    mount: bool,
+    network: bool,
+    pid: bool,
+}
+
+impl Sandbox {
+    /// Nothing isolated until namespaces are added
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a user namespace first, so no privileges are needed for the others
+    #[must_use]
+    pub fn user(mut self) -> Self {
+        self.user = true;
+        self
+    }
+
+    /// A mount namespace, mounts made by the command are not seen outside
+    #[must_use]
+    pub fn mount(mut self) -> Self {
+        self.mount = true;
+        self
+    }
+
+    /// A network namespace, the command can't reach the network
+    #[must_use]
+    pub fn no_network(mut self) -> Self {
+        self.network = true;
+        self
+    }
+
+    /// A pid namespace for processes the command starts
+    #[must_use]
+    pub fn pid(mut self) -> Self {
+        self.pid = true;
+        self
+    }
+
+    /// The namespaces to create, for names and errors
+    fn namespaces(self) -> Vec<&'static str> {
+        [
+            (self.user, "user"),
+            (self.mount, "mount"),
+            (self.network, "network"),
+            (self.pid, "pid"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+    }
+
+    /// Run `command` in the namespaces, naming it after `command`
+    ///
+    /// Adds a `pre_exec` hook to `command` itself, see [ExecutionBackend] for calling it more
+    /// than once.
+    pub fn exec<'a, C: CommandWithName>(&self, command: &'a mut C) -> NamedCommand<'a> {
+        let namespaces = self.namespaces();
+        if namespaces.is_empty() {
+            let name = command.name();
+            return command.named(name);
+        }
+        let name = format!("(sandboxed: {}) {}", namespaces.join(", "), command.name());
+        let sandboxed = command.mut_cmd();
+
+        let mut flags = 0;
+        for (enabled, flag) in [
+            (self.user, libc::CLONE_NEWUSER),
+            (self.mount, libc::CLONE_NEWNS),
+            (self.network, libc::CLONE_NEWNET),
+            (self.pid, libc::CLONE_NEWPID),
+        ] {
+            if enabled {
+                flags |= flag;
+            }
+        }
+        let mount = self.mount;
+        // Allocate before forking, the child may only call async-signal-safe functions
+        // SAFETY: `getuid` and `getgid` have no memory safety requirements
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let user_maps = self.user.then(|| {
+            [
+                (c"/proc/self/setgroups", b"deny".to_vec()),
+                (c"/proc/self/uid_map", format!("0 {uid} 1").into_bytes()),
+                (c"/proc/self/gid_map", format!("0 {gid} 1").into_bytes()),
+            ]
+        });
+        let root = CString::from(c"/");
+
+        // SAFETY: `unshare`, `mount`, `open`, `write` and `close` are async-signal-safe and
+        // everything they use was allocated before forking
+        unsafe {
+            sandboxed.pre_exec(move || {
+                if libc::unshare(flags) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(maps) = &user_maps {
+                    for (path, contents) in maps {
+                        write_file(path, contents)?;
+                    }
+                }
+                // Mounts are often shared with the parent namespace, stop them propagating
+                if mount
+                    && libc::mount(
+                        std::ptr::null(),
+                        root.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_REC | libc::MS_PRIVATE,
+                        std::ptr::null(),
+                    ) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command.named(name)
+    }
+
+    /// Explain spawn errors caused by missing privileges or kernel support
+    fn explain(self, error: CmdError) -> CmdError {
+        let namespaces = self.namespaces().join(", ");
+        match error {
+            CmdError::SystemError(name, error, run_id) => {
+                let annotation = match error.raw_os_error() {
+                    Some(libc::EPERM) if self.user => format!(
+                        "Creating {namespaces} namespaces was not permitted, unprivileged user namespaces may be disabled by the kernel or a security policy"
+                    ),
+                    Some(libc::EPERM) => format!(
+                        "Creating {namespaces} namespaces requires the `CAP_SYS_ADMIN` capability, or a user namespace with `Sandbox::user`"
+                    ),
+                    Some(libc::EINVAL | libc::ENOSPC | libc::EUSERS) => format!(
+                        "Creating {namespaces} namespaces is not supported or the limit was reached"
+                    ),
+                    _ => return CmdError::SystemError(name, error, run_id),
+                };
+                CmdError::SystemError(
+                    name,
+                    IoErrorAnnotation::new(error, annotation).into_io_error(),
+                    run_id,
+                )
+            }
+            error => error,
+        }
+    }
+}
+
+impl ExecutionBackend for Sandbox {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(&mut self.exec(spec))
+            .map_err(|error| self.explain(error))
+    }
+}
+
+/// Write a whole file using only async-signal-safe calls
+fn write_file(path: &std::ffi::CStr, contents: &[u8]) -> std::io::Result<()> {
+    // SAFETY: The path is a valid C string and the buffer is valid for its length
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_errors_name_the_missing_capability() {
+        let error = Sandbox::new().pid().explain(CmdError::SystemError(
+            "ps".to_string(),
+            std::io::Error::from_raw_os_error(libc::EPERM),
+            None,
+        ));
+        assert!(error
+            .to_string()
+            .contains("Creating pid namespaces requires the `CAP_SYS_ADMIN` capability"));
+
+        let mut command = std::process::Command::new("ps");
+        assert_eq!(
+            "(sandboxed: mount, network) ps",
+            Sandbox::new()
+                .no_network()
+                .mount()
+                .exec(&mut command)
+                .name()
+        );
+
+        let mut command = std::process::Command::new("ps");
+        command.env_clear();
+        assert_eq!("ps", Sandbox::new().exec(&mut command).name());
+        assert_eq!(
+            "(sandboxed: user) ps",
+            Sandbox::new().user().exec(&mut command).name()
+        );
+    }
+
+    #[test]
+    fn test_secrets_stay_masked() {
+        let mut command = std::process::Command::new("deploy");
+        command.arg("hunter2");
+        let mut named = command.named_redacting(["hunter2"]);
+        assert_eq!(
+            vec!["deploy", "****"],
+            Sandbox::new().user().exec(&mut named).log_argv()
+        );
+        assert_eq!(
+            vec!["deploy", "****"],
+            Sandbox::new().exec(&mut named).log_argv()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    BackendIo, CmdError, CommandSpec, CommandWithName, ExecutionBackend, NamedCommand, NamedOutput,
+};
+use seccompiler::BpfProgram;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::sync::Arc;
+
+/// Runs commands with a seccomp filter restricting the system calls they can make
+///
+/// The filter is compiled ahead of time with [seccompiler] and applied to the child just
+/// before the command starts, along with `no_new_privs`. It stays in place for everything
+/// the command spawns. An empty filter applies no restrictions.
+///
+/// The filter is already active when the child calls `execve` to start the program, so a
+/// filter that only allows listed system calls has to allow `execve` and whatever the program
+/// needs to load, or every command fails before it starts.
+///
+/// As an [ExecutionBackend] a command killed for making a disallowed system call, which shows
+/// up as `SIGSYS`, returns `CmdError::SeccompKilled` instead of an ordinary non-zero exit.
+/// Filters that answer with an errno instead of killing let the command handle the error and
+/// fail as usual. The name is unchanged, the filter is part of how the command runs rather
+/// than what it runs.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{BackendIo, CmdError, CommandSpec, ExecutionBackend, Seccomp};
+/// use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+/// use std::process::Command;
+///
+/// let filter = SeccompFilter::new(
+///     [(libc::SYS_uname, vec![])].into_iter().collect(),
+///     SeccompAction::Allow,
+///     SeccompAction::KillProcess,
+///     std::env::consts::ARCH.try_into().unwrap(),
+/// )
+/// .unwrap();
+/// let seccomp = Seccomp::new(BpfProgram::try_from(filter).unwrap());
+///
+/// let error = seccomp
+///     .run(&mut CommandSpec::new(Command::new("uname")), BackendIo::Capture)
+///     .unwrap_err();
+/// assert!(matches!(error, CmdError::SeccompKilled(_)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Seccomp {
+    filter: Arc<BpfProgram>,
+}
+
+impl Seccomp {
+    /// Apply this compiled filter to commands
+    #[must_use]
+    pub fn new(filter: BpfProgram) -> Self {
+        Self {
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Apply the filter to `command`, keeping its name
+    ///
+    /// Adds a `pre_exec` hook to `command` itself, see [ExecutionBackend] for calling it more
+    /// than once.
+    pub fn exec<'a, C: CommandWithName>(&self, command: &'a mut C) -> NamedCommand<'a> {
+        let name = command.name();
+        let filtered = command.mut_cmd();
+        let filter = Arc::clone(&self.filter);
+        // SAFETY: Applying the filter only calls `prctl` and `seccomp` on memory allocated
+        // before forking
+        unsafe {
+            filtered.pre_exec(move || {
+                if filter.is_empty() {
+                    return Ok(());
+                }
+                seccompiler::apply_filter(&filter).map_err(|_| std::io::Error::last_os_error())
+            });
+        }
+        command.named(name)
+    }
+}
+
+impl ExecutionBackend for Seccomp {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(&mut self.exec(spec)).map_err(|error| {
+            if error.status().signal() == Some(libc::SIGSYS) && error.named_output().is_some() {
+                CmdError::SeccompKilled(NamedOutput::from(error))
+            } else {
+                error
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use seccompiler::{SeccompAction, SeccompFilter};
+    use std::process::Command;
+
+    fn filter(action: SeccompAction) -> Seccomp {
+        let filter = SeccompFilter::new(
+            [(libc::SYS_uname, vec![])].into_iter().collect(),
+            SeccompAction::Allow,
+            action,
+            std::env::consts::ARCH.try_into().unwrap(),
+        )
+        .unwrap();
+        Seccomp::new(BpfProgram::try_from(filter).unwrap())
+    }
+
+    #[test]
+    fn test_seccomp_kills_are_told_apart_from_failures() {
+        let error = filter(SeccompAction::KillProcess)
+            .run(
+                &mut CommandSpec::new(Command::new("uname")),
+                BackendIo::Capture,
+            )
+            .unwrap_err();
+        assert!(matches!(error, CmdError::SeccompKilled(_)), "{error}");
+        assert!(error
+            .to_string()
+            .starts_with("Command killed by seccomp filter `uname`"));
+
+        let error = filter(SeccompAction::Errno(libc::EPERM as u32))
+            .run(
+                &mut CommandSpec::new(Command::new("uname")),
+                BackendIo::Capture,
+            )
+            .unwrap_err();
+        assert!(
+            matches!(error, CmdError::NonZeroExitNotStreamed(_)),
+            "{error}"
+        );
+
+        let mut command = Command::new("uname");
+        command.env_clear();
+        let output = Seccomp::new(BpfProgram::new())
+            .exec(&mut command)
+            .named_output();
+        assert!(output.is_ok());
+    }
+
+    #[test]
+    fn test_secrets_stay_masked() {
+        let mut command = Command::new("deploy");
+        command.arg("hunter2");
+        let mut named = command.named_redacting(["hunter2"]);
+        assert_eq!(
+            vec!["deploy", "****"],
+            Seccomp::new(BpfProgram::new()).exec(&mut named).log_argv()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::CommandSpec;
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+/// Runs a script with `sh -c`, named after the script
+///
+/// For pipes, `&&` and other shell syntax that a [Command] can't express on its own. The
+/// script is passed as a single argument so it reaches the shell unchanged, and the name
+/// shows it quoted as one argument so it reads the same as what ran. The crate is unix only,
+/// there is no PowerShell or `cmd` counterpart. See [Shell] for other shells and for loading
+/// the user's shell setup.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::CommandWithName;
+///
+/// let output = fun_run::shell("echo building && echo installing")
+///     .named_output()
+///     .unwrap();
+///
+/// assert_eq!("sh -c \"echo building && echo installing\"", output.name());
+/// assert_eq!("building\ninstalling\n", output.stdout_lossy());
+/// ```
+#[must_use]
+pub fn shell(script: impl AsRef<OsStr>) -> CommandSpec {
+    Shell::new().script(script)
+}
+
+/// Builds a shell invocation for a script, see [shell()]
+///
+/// Tools run on a developer's machine often need the same setup as their terminal, such as a
+/// `PATH` changed by rbenv or nvm. A login shell (`-l`) reads profile files like
+/// `~/.profile` or `~/.bash_profile`, an interactive shell (`-i`) reads rc files like
+/// `~/.bashrc` or `~/.zshrc`. The shell and its flags are part of the name, such as
+/// `bash -lic "bundle install"`.
+///
+/// Interactive shells may warn about job control when there is no terminal, and some rc
+/// files print to stdout or skip their setup when they are not interactive.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Shell};
+///
+/// let output = Shell::new()
+///     .program("bash")
+///     .login()
+///     .script("echo $0")
+///     .named_output()
+///     .unwrap();
+///
+/// assert_eq!("bash -lc \"echo $0\"", output.name());
+/// assert!(output.stdout_lossy().ends_with("bash\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shell {
+    program: OsString,
+    login: bool,
+    interactive: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self {
+            program: OsString::from("sh"),
+            login: false,
+            interactive: false,
+        }
+    }
+}
+
+impl Shell {
+    /// `sh` without login or interactive setup
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The user's shell from `SHELL`, falling back to `sh`
+    #[must_use]
+    pub fn user() -> Self {
+        let shell = Self::new();
+        match std::env::var_os("SHELL") {
+            Some(program) if !program.is_empty() => shell.program(program),
+            _ => shell,
+        }
+    }
+
+    /// Run this shell instead of `sh`, such as `bash` or `/bin/zsh`
+    #[must_use]
+    pub fn program(mut self, program: impl Into<OsString>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Start a login shell with `-l`, reading profile files
+    #[must_use]
+    pub fn login(mut self) -> Self {
+        self.login = true;
+        self
+    }
+
+    /// Start an interactive shell with `-i`, reading rc files
+    #[must_use]
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// A command that runs the script with this shell
+    #[must_use]
+    pub fn script(&self, script: impl AsRef<OsStr>) -> CommandSpec {
+        let mut flags = String::from("-");
+        if self.login {
+            flags.push('l');
+        }
+        if self.interactive {
+            flags.push('i');
+        }
+        flags.push('c');
+
+        let mut command = Command::new(&self.program);
+        command.arg(flags).arg(script);
+        CommandSpec::new(command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+
+    #[test]
+    fn test_script_is_one_argument() {
+        let mut spec = shell("printf '%s\\n' \"$0\" && exit 3");
+        assert_eq!(
+            vec!["-c", "printf '%s\\n' \"$0\" && exit 3"],
+            spec.command().get_args().collect::<Vec<_>>()
+        );
+
+        let error = spec.named_output().unwrap_err();
+        assert_eq!(Some(3), error.status().code());
+        assert!(error
+            .to_string()
+            .starts_with("Command failed `sh -c \"printf '%s\\\\n' \\\"$0\\\" && exit 3\"`"));
+    }
+
+    #[test]
+    fn test_flags_are_in_the_name() {
+        let mut spec = Shell::new()
+            .program("/bin/zsh")
+            .login()
+            .interactive()
+            .script("bundle install");
+        assert_eq!("/bin/zsh -lic \"bundle install\"", spec.name());
+    }
+}
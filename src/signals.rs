@@ -0,0 +1,224 @@
+use std::io;
+use std::process::Child;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Signals relayed to the child, see [crate::CommandWithName::stream_output_forwarding_signals]
+const FORWARDED: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// Handlers are process wide, so only one run forwards signals at a time
+static FORWARDING: Mutex<()> = Mutex::new(());
+/// Pid of the running child, zero when there is none
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+/// Last signal received while forwarding, zero when there is none
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    RECEIVED.store(signal, Ordering::SeqCst);
+    let pid = FORWARD_TO.load(Ordering::SeqCst);
+    if pid > 0 && !from_terminal(info) {
+        // SAFETY: `kill` is async-signal-safe
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// Whether the kernel sent the signal, such as for Ctrl+C typed at the terminal
+///
+/// Those reach every process in the foreground process group, which includes the child, so
+/// relaying them would deliver the signal twice.
+fn from_terminal(info: *const libc::siginfo_t) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    // SAFETY: The kernel passes a valid `siginfo_t` to handlers installed with `SA_SIGINFO`
+    return !info.is_null() && unsafe { (*info).si_code } == libc::SI_KERNEL;
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = info;
+        false
+    }
+}
+
+/// Relays SIGINT and SIGTERM to a child until dropped
+///
+/// The previous handlers are restored on drop.
+pub(crate) struct SignalForwarding {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl SignalForwarding {
+    pub(crate) fn install() -> io::Result<Self> {
+        let lock = FORWARDING.lock().unwrap_or_else(PoisonError::into_inner);
+        FORWARD_TO.store(0, Ordering::SeqCst);
+        RECEIVED.store(0, Ordering::SeqCst);
+
+        let mut forwarding = Self {
+            previous: Vec::new(),
+            _lock: lock,
+        };
+        for signal in FORWARDED {
+            // SAFETY: The handler only touches atomics and calls `kill`, both are async-signal-safe
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward
+                    as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                    as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+                libc::sigemptyset(&raw mut action.sa_mask);
+
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &raw const action, &raw mut previous) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                forwarding.previous.push((signal, previous));
+            }
+        }
+        Ok(forwarding)
+    }
+
+    /// Start relaying signals to the child
+    ///
+    /// A signal that arrived before the child was spawned is relayed immediately.
+    #[allow(clippy::cast_possible_wrap)]
+    pub(crate) fn forward_to(&self, child: &Child) {
+        let pid = child.id() as i32;
+        FORWARD_TO.store(pid, Ordering::SeqCst);
+        if let Some(signal) = self.received() {
+            // SAFETY: The child has not been waited on yet so the pid cannot have been reused
+            unsafe {
+                libc::kill(pid, signal);
+            }
+        }
+    }
+
+    /// The signal received while forwarding, if any
+    pub(crate) fn received(&self) -> Option<libc::c_int> {
+        Some(RECEIVED.load(Ordering::SeqCst)).filter(|signal| *signal != 0)
+    }
+}
+
+impl Drop for SignalForwarding {
+    fn drop(&mut self) {
+        FORWARD_TO.store(0, Ordering::SeqCst);
+        for (signal, previous) in &self.previous {
+            // SAFETY: Restores the action that was in place before `install`
+            unsafe {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// A unix signal that can be sent to a running command, see [crate::NamedChild::signal]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// `SIGHUP`, commonly used to ask servers to reload their configuration
+    Hup,
+    /// `SIGINT`, what Ctrl+C sends
+    Int,
+    /// `SIGQUIT`
+    Quit,
+    /// `SIGKILL`, cannot be caught
+    Kill,
+    /// `SIGTERM`, a request to shut down
+    Term,
+    /// `SIGUSR1`
+    Usr1,
+    /// `SIGUSR2`
+    Usr2,
+}
+
+impl Signal {
+    /// The raw signal number
+    #[must_use]
+    pub fn as_raw(self) -> i32 {
+        match self {
+            Signal::Hup => libc::SIGHUP,
+            Signal::Int => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Term => libc::SIGTERM,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+        }
+    }
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&signal_name(self.as_raw()))
+    }
+}
+
+/// Send `signal` to the process `pid`
+#[allow(clippy::cast_possible_wrap)]
+pub(crate) fn send(pid: u32, signal: Signal) -> io::Result<()> {
+    // SAFETY: `kill` has no memory safety requirements
+    if unsafe { libc::kill(pid as libc::pid_t, signal.as_raw()) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Human readable name for a signal number, i.e. `SIGINT`
+pub(crate) fn signal_name(signal: libc::c_int) -> String {
+    match signal {
+        libc::SIGINT => "SIGINT".to_string(),
+        libc::SIGTERM => "SIGTERM".to_string(),
+        libc::SIGHUP => "SIGHUP".to_string(),
+        libc::SIGQUIT => "SIGQUIT".to_string(),
+        libc::SIGKILL => "SIGKILL".to_string(),
+        libc::SIGUSR1 => "SIGUSR1".to_string(),
+        libc::SIGUSR2 => "SIGUSR2".to_string(),
+        other => format!("signal {other}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_signal_is_relayed_to_child() {
+        let forwarding = SignalForwarding::install().unwrap();
+        let mut child = Command::new("sleep").arg("100").spawn().unwrap();
+        forwarding.forward_to(&child);
+
+        // SAFETY: The forwarding handler is installed so the test process is not terminated
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+
+        assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+        assert_eq!(Some(libc::SIGTERM), forwarding.received());
+    }
+
+    #[test]
+    fn test_child_stays_in_the_foreground_process_group() {
+        let output = Command::new("bash")
+            .args(["-c", "cut -d' ' -f5 /proc/$$/stat"])
+            .stream_output_forwarding_signals(std::io::sink(), std::io::sink())
+            .unwrap();
+
+        // SAFETY: `getpgrp` always succeeds
+        let pgid = unsafe { libc::getpgrp() };
+        assert_eq!(pgid.to_string(), output.stdout_lossy().trim());
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!("SIGINT", signal_name(libc::SIGINT));
+        assert_eq!("signal 64", signal_name(64));
+        assert_eq!("SIGHUP", Signal::Hup.to_string());
+    }
+}
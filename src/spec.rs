@@ -0,0 +1,383 @@
+use crate::{CmdError, CommandWithName, NamedCommand};
+use std::ffi::OsString;
+use std::io;
+use std::process::Command;
+use std::str::FromStr;
+
+/// An owned command that can be cloned and run again with changes
+///
+/// Wraps a [Command], copying it with [crate::clone_command] when cloned. Use
+/// [CommandSpec::rerun_with] to derive a new spec with overrides applied, then run it with
+/// any [CommandWithName] method.
+///
+/// Unless a custom name is given the name is derived from the command, so it reflects any
+/// overrides. Environment variables changed by an override are included in the name.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandSpec, CommandWithName};
+/// use std::process::Command;
+///
+/// let mut command = Command::new("echo");
+/// command.arg("installing");
+/// let mut spec = CommandSpec::new(command);
+///
+/// if spec.named_output().is_ok() {
+///     let output = spec
+///         .rerun_with(|cmd| {
+///             cmd.arg("--verbose").env("DEBUG", "1");
+///         })
+///         .named_output()
+///         .unwrap();
+///
+///     assert_eq!("DEBUG=1 echo installing --verbose", output.name());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CommandSpec {
+    name: Option<String>,
+    log_name: Option<String>,
+    env_keys: Vec<OsString>,
+    command: Command,
+}
+
+impl CommandSpec {
+    #[must_use]
+    pub fn new(command: Command) -> Self {
+        Self {
+            name: None,
+            log_name: None,
+            env_keys: Vec::new(),
+            command,
+        }
+    }
+
+    /// Use a custom name instead of deriving one from the command
+    #[must_use]
+    pub fn with_name(mut self, name: impl AsRef<str>) -> Self {
+        self.name = Some(name.as_ref().to_string());
+        self
+    }
+
+    /// Use a custom log name instead of deriving one from the command, see
+    /// [CommandWithName::log_name]
+    #[must_use]
+    pub fn with_log_name(mut self, log_name: impl AsRef<str>) -> Self {
+        self.log_name = Some(log_name.as_ref().to_string());
+        self
+    }
+
+    /// The wrapped command
+    #[must_use]
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Copy this spec and apply `overrides` to the copy's command
+    #[must_use]
+    pub fn rerun_with(&self, overrides: impl FnOnce(&mut Command)) -> Self {
+        let mut spec = self.clone();
+        overrides(&mut spec.command);
+
+        for (key, value) in spec.command.get_envs() {
+            let before = self
+                .command
+                .get_envs()
+                .find_map(|(before, value)| (before == key).then_some(value));
+            if before != Some(value) && !spec.env_keys.iter().any(|known| known == key) {
+                spec.env_keys.push(key.to_os_string());
+            }
+        }
+        spec
+    }
+}
+
+impl Clone for CommandSpec {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            log_name: self.log_name.clone(),
+            env_keys: self.env_keys.clone(),
+            command: crate::clone_command(&self.command),
+        }
+    }
+}
+
+impl From<Command> for CommandSpec {
+    fn from(command: Command) -> Self {
+        Self::new(command)
+    }
+}
+
+/// Parses a shell style string such as `RAILS_ENV=production bundle exec rake`
+///
+/// Words are split on whitespace, single and double quotes group words and a backslash
+/// escapes the next character. Leading `KEY=value` words are set as env vars on the command
+/// and shown in its name. No other shell features such as variable expansion, globs or
+/// pipes are supported.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandSpec, CommandWithName};
+///
+/// let mut spec: CommandSpec = "RAILS_ENV=production bundle exec rake 'db:migrate'"
+///     .parse()
+///     .unwrap();
+///
+/// assert_eq!("RAILS_ENV=production bundle exec rake db:migrate", spec.name());
+/// ```
+///
+/// # Errors
+///
+/// Returns a [ParseCommandError] if a quote is not closed or there is no program.
+impl FromStr for CommandSpec {
+    type Err = ParseCommandError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut words = split_words(input)
+            .ok_or_else(|| ParseCommandError::UnterminatedQuote(input.to_string()))?
+            .into_iter()
+            .peekable();
+
+        let mut envs = Vec::new();
+        while let Some((key, value)) = words
+            .next_if(|word| word.assignment.is_some())
+            .map(Word::into_assignment)
+        {
+            envs.push((key, value));
+        }
+        let program = words
+            .next()
+            .ok_or_else(|| ParseCommandError::MissingProgram(input.to_string()))?;
+
+        let mut command = Command::new(program.text);
+        command.args(words.map(|word| word.text));
+        command.envs(envs.iter().map(|(key, value)| (key, value)));
+
+        Ok(Self {
+            name: None,
+            log_name: None,
+            env_keys: envs
+                .into_iter()
+                .map(|(key, _)| OsString::from(key))
+                .collect(),
+            command,
+        })
+    }
+}
+
+/// Why a string could not be parsed into a [CommandSpec]
+///
+/// Each variant holds the string that was parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCommandError {
+    /// A single or double quote was not closed
+    UnterminatedQuote(String),
+    /// Only env var assignments, or nothing at all, was given
+    MissingProgram(String),
+}
+
+impl std::fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseCommandError::UnterminatedQuote(input) => {
+                write!(f, "Could not parse command `{input}`. Unterminated quote")
+            }
+            ParseCommandError::MissingProgram(input) => {
+                write!(f, "Could not parse command `{input}`. No program given")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseCommandError {}
+
+/// Becomes a [CmdError::SystemError] so parsing can be chained with running the command
+impl From<ParseCommandError> for CmdError {
+    fn from(error: ParseCommandError) -> Self {
+        let (ParseCommandError::UnterminatedQuote(input)
+        | ParseCommandError::MissingProgram(input)) = &error;
+        CmdError::SystemError(
+            input.clone(),
+            io::Error::new(io::ErrorKind::InvalidInput, error),
+            None,
+        )
+    }
+}
+
+struct Word {
+    text: String,
+    /// Position of the `=` when the word starts with an unquoted `KEY=`
+    assignment: Option<usize>,
+}
+
+impl Word {
+    fn into_assignment(self) -> (String, String) {
+        let at = self.assignment.unwrap_or(self.text.len());
+        let (key, value) = self.text.split_at(at);
+        (key.to_string(), value.trim_start_matches('=').to_string())
+    }
+}
+
+/// Splits on whitespace honoring quotes and backslashes, `None` if a quote is not closed
+fn split_words(input: &str) -> Option<Vec<Word>> {
+    let mut words = Vec::new();
+    let mut current: Option<Word> = None;
+    let mut plain = true;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            words.extend(current.take());
+            plain = true;
+            continue;
+        }
+        let word = current.get_or_insert_with(|| Word {
+            text: String::new(),
+            assignment: None,
+        });
+        match c {
+            '\\' => {
+                plain = false;
+                word.text.extend(chars.next());
+            }
+            '\'' => {
+                plain = false;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.text.push(c),
+                    }
+                }
+            }
+            '"' => {
+                plain = false;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.text.push(c),
+                            c => word.text.extend(['\\', c]),
+                        },
+                        c => word.text.push(c),
+                    }
+                }
+            }
+            '=' if plain && word.assignment.is_none() && is_env_key(&word.text) => {
+                word.assignment = Some(word.text.len());
+                word.text.push(c);
+            }
+            c => {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    plain = false;
+                }
+                word.text.push(c);
+            }
+        }
+    }
+    words.extend(current);
+    Some(words)
+}
+
+fn is_env_key(key: &str) -> bool {
+    key.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl CommandWithName for CommandSpec {
+    fn name(&mut self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => {
+                let env = self
+                    .command
+                    .get_envs()
+                    .filter_map(|(key, value)| {
+                        value.map(|value| (key.to_owned(), value.to_owned()))
+                    })
+                    .collect::<Vec<_>>();
+                crate::display_with_env_keys(&mut self.command, env, &self.env_keys)
+            }
+        }
+    }
+
+    /// Keeps the custom log name
+    fn named(&mut self, s: impl AsRef<str>) -> NamedCommand<'_> {
+        let log_name = self.log_name.clone();
+        let named = self.command.named(s);
+        match log_name {
+            Some(log_name) => named.with_log_name(log_name),
+            None => named,
+        }
+    }
+
+    fn log_name(&mut self) -> String {
+        match &self.log_name {
+            Some(log_name) => log_name.clone(),
+            None => crate::display_with_set_env_keys(&mut self.command),
+        }
+    }
+
+    fn mut_cmd(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rerun_keeps_original_and_custom_name() {
+        let mut command = Command::new("rake");
+        command.env("RAILS_ENV", "test");
+        let mut spec = CommandSpec::new(command);
+
+        let mut rerun = spec.rerun_with(|cmd| {
+            cmd.arg("--trace")
+                .env("RAILS_ENV", "test")
+                .env("VERBOSE", "1");
+        });
+        assert_eq!("rake", spec.name());
+        assert_eq!("VERBOSE=1 rake --trace", rerun.name());
+
+        let named = rerun.clone().with_name("Run tests");
+        assert_eq!("Run tests", named.rerun_with(|_| {}).name());
+    }
+
+    #[test]
+    fn test_parse_leading_env_assignments() {
+        let mut spec = CommandSpec::from_str(
+            r#"RAILS_ENV=production DEBUG= bundle exec "rake task" --opt=value 'a b' c\ d"#,
+        )
+        .unwrap();
+
+        let args = spec.mut_cmd().get_args().collect::<Vec<_>>();
+        assert_eq!(vec!["exec", "rake task", "--opt=value", "a b", "c d"], args);
+        assert_eq!(
+            r#"RAILS_ENV=production DEBUG="" bundle exec "rake task" "--opt=value" "a b" "c d""#,
+            spec.name()
+        );
+
+        let mut spec = CommandSpec::from_str(r#""KEY=value" echo"#).unwrap();
+        assert_eq!("KEY=value", spec.mut_cmd().get_program());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "Could not parse command `echo 'oops`. Unterminated quote",
+            CommandSpec::from_str("echo 'oops").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Could not parse command `A=1`. No program given",
+            CommandSpec::from_str("A=1").unwrap_err().to_string()
+        );
+        assert_eq!(
+            ParseCommandError::MissingProgram(String::new()),
+            CommandSpec::from_str("").unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    BackendIo, CmdError, CommandSpec, CommandWithName, ExecutionBackend, NamedOutput, Quoting,
+};
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+/// Runs commands on a remote host with the `ssh` binary
+///
+/// Build the command as if it ran locally, then translate it with [Ssh::exec]. The program
+/// and arguments are quoted for the remote shell, the working directory becomes a `cd` and
+/// environment variables set on the command are assigned in front of it. Output comes back
+/// over ssh, so the result streams, captures and fails like any other command. The command
+/// keeps its name, prefixed with the host, so errors read
+/// ``Command failed `(on deploy@web-1) bundle install` ``. ssh exits with 255 when it can't
+/// connect. As an [ExecutionBackend] it runs a [CommandSpec] this way.
+///
+/// Authentication and host keys come from the local ssh setup, add options such as
+/// `BatchMode=yes` with [Ssh::option] so a missing key fails instead of prompting.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Ssh};
+/// use std::process::Command;
+///
+/// let mut command = Command::new("bundle");
+/// command.arg("install").current_dir("/srv/app").env("RAILS_ENV", "production");
+///
+/// let mut remote = Ssh::new("web-1").user("deploy").port(2222).exec(&mut command).unwrap();
+/// assert_eq!("(on deploy@web-1) bundle install", remote.name());
+/// assert_eq!(
+///     vec![
+///         "-p",
+///         "2222",
+///         "--",
+///         "deploy@web-1",
+///         "cd /srv/app && RAILS_ENV=production bundle install"
+///     ],
+///     remote.command().get_args().collect::<Vec<_>>()
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Ssh {
+    host: String,
+    user: Option<String>,
+    port: Option<u16>,
+    options: Vec<OsString>,
+}
+
+impl Ssh {
+    /// Connect to `host`, a hostname, address or alias from the ssh config
+    #[must_use]
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            user: None,
+            port: None,
+            options: Vec::new(),
+        }
+    }
+
+    /// Log in as this user instead of the ssh default
+    #[must_use]
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Connect to this port instead of the ssh default
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Pass an option to ssh with `-o`, such as `BatchMode=yes`
+    #[must_use]
+    pub fn option(mut self, option: impl Into<OsString>) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// The destination given to ssh, `user@host` or `host`
+    #[must_use]
+    pub fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{user}@{host}", host = self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// A command that runs `command` on the host, named after `command`
+    ///
+    /// The destination follows `--` so a host starting with `-` can't be read as an option.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::Rejected` if an environment variable set on the command is not a
+    /// valid shell variable name, it could not be assigned in the remote shell.
+    pub fn exec<C: CommandWithName>(&self, command: &mut C) -> Result<CommandSpec, CmdError> {
+        let destination = self.destination();
+        let name = format!("(on {destination}) {}", command.name());
+        let inner = command.mut_cmd();
+        if let Some((key, _)) = inner.get_envs().find(|(key, _)| !is_shell_name(key)) {
+            return Err(CmdError::Rejected(
+                name,
+                format!(
+                    "environment variable `{}` is not a valid shell variable name",
+                    key.to_string_lossy()
+                ),
+            ));
+        }
+
+        let mut remote = String::new();
+        if let Some(dir) = inner.get_current_dir() {
+            remote.push_str("cd ");
+            remote.push_str(&Quoting::Posix.quote(dir.as_os_str()));
+            remote.push_str(" && ");
+        }
+        for (key, value) in inner.get_envs() {
+            if let Some(value) = value {
+                remote.push_str(&key.to_string_lossy());
+                remote.push('=');
+                remote.push_str(&Quoting::Posix.quote(value));
+                remote.push(' ');
+            }
+        }
+        remote.push_str(&crate::display_with(inner, Quoting::Posix));
+
+        let mut ssh = Command::new("ssh");
+        if let Some(port) = self.port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+        for option in &self.options {
+            ssh.arg("-o").arg(option);
+        }
+        ssh.arg("--").arg(destination).arg(remote);
+
+        Ok(CommandSpec::new(ssh).with_name(name))
+    }
+}
+
+impl ExecutionBackend for Ssh {
+    fn run(&self, spec: &mut CommandSpec, io: BackendIo<'_>) -> Result<NamedOutput, CmdError> {
+        io.run(&mut self.exec(spec)?)
+    }
+}
+
+/// Returns true if `key` can be assigned in a POSIX shell, letters, digits and underscores
+/// not starting with a digit
+fn is_shell_name(key: &OsStr) -> bool {
+    let key = key.as_encoded_bytes();
+    key.first()
+        .is_some_and(|first| first.is_ascii_alphabetic() || *first == b'_')
+        && key
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remote_command_is_quoted_for_the_shell() {
+        let mut command = Command::new("echo");
+        command
+            .args(["it's", "$HOME"])
+            .current_dir("/srv/my app")
+            .env("GREETING", "hello world");
+
+        let mut remote = Ssh::new("web-1")
+            .option("BatchMode=yes")
+            .exec(&mut command)
+            .unwrap();
+        assert_eq!("(on web-1) echo \"it's\" \"$HOME\"", remote.name());
+        assert_eq!(
+            vec![
+                "-o",
+                "BatchMode=yes",
+                "--",
+                "web-1",
+                "cd '/srv/my app' && GREETING='hello world' echo 'it'\\''s' '$HOME'"
+            ],
+            remote.command().get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_destination_and_env_keys_are_not_injected() {
+        let remote = Ssh::new("-oProxyCommand=touch /tmp/pwned")
+            .exec(&mut Command::new("true"))
+            .unwrap();
+        assert_eq!(
+            vec!["--", "-oProxyCommand=touch /tmp/pwned", "true"],
+            remote.command().get_args().collect::<Vec<_>>()
+        );
+
+        let mut command = Command::new("true");
+        command.env("X=1; touch /tmp/pwned; Y", "2");
+        let error = Ssh::new("web-1").exec(&mut command).unwrap_err();
+        assert!(matches!(error, CmdError::Rejected(_, _)));
+        assert_eq!(
+            "Not running command `(on web-1) true`. Rejected, environment variable `X=1; touch /tmp/pwned; Y` is not a valid shell variable name",
+            error.to_string()
+        );
+
+        assert!(is_shell_name(OsStr::new("_RAILS_ENV2")));
+        assert!(!is_shell_name(OsStr::new("2FAST")));
+        assert!(!is_shell_name(OsStr::new("")));
+    }
+
+    #[test]
+    fn test_remote_command_runs_in_a_shell() {
+        let dir = std::env::temp_dir().join(format!("fun_run ssh test {}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "echo \"$GREETING\" \"$1\" && pwd", "sh", "it's $HOME"])
+            .current_dir(&dir)
+            .env("GREETING", "hello world");
+
+        let remote = Ssh::new("web-1").exec(&mut command).unwrap();
+        let script = remote.command().get_args().last().unwrap();
+        let output = crate::shell(script).named_output();
+        std::fs::remove_dir(&dir).unwrap();
+        let output = output.unwrap();
+        assert_eq!(
+            format!("hello world it's $HOME\n{}\n", dir.display()),
+            output.stdout_lossy()
+        );
+    }
+}
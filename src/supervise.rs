@@ -0,0 +1,334 @@
+use crate::child::Readiness;
+use crate::{CmdError, NamedChild, NamedOutput};
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often a supervised command is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Keep a named command running in the background, restarting it when it crashes
+///
+/// A crash is any run that fails, such as a non-zero exit or being killed by a signal. Runs that
+/// exit successfully are not restarted. Between restarts the supervisor waits, starting at the
+/// initial backoff and doubling up to the maximum backoff. Once the restart limit is reached
+/// the command is left stopped.
+///
+/// Output from every run is forwarded to the given writers and each finished run is kept
+/// so it can be inspected via [Supervised::runs].
+///
+/// Example:
+///
+/// ```
+/// use fun_run::Supervisor;
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let mut command = Command::new("bash");
+/// command.args(["-c", "echo 'crashing' && exit 1"]);
+///
+/// let supervised = Supervisor::new(command)
+///     .max_restarts(2)
+///     .backoff(Duration::from_millis(10), Duration::from_millis(20))
+///     .start(std::io::stdout(), std::io::stderr())
+///     .unwrap();
+///
+/// let runs = supervised.join();
+/// assert_eq!(3, runs.len());
+/// assert_eq!("crashing\n", runs[2].stdout_lossy());
+/// ```
+#[derive(Debug)]
+pub struct Supervisor {
+    name: String,
+    command: Command,
+    max_restarts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Supervisor {
+    /// Supervise the given command, named via [crate::display]
+    #[must_use]
+    pub fn new(mut command: Command) -> Self {
+        Self {
+            name: crate::display(&mut command),
+            command,
+            max_restarts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Rename the supervised command
+    #[must_use]
+    pub fn named(mut self, name: impl AsRef<str>) -> Self {
+        self.name = name.as_ref().to_string();
+        self
+    }
+
+    /// Maximum number of times the command is restarted after crashing, defaults to 5
+    #[must_use]
+    pub fn max_restarts(mut self, max_restarts: usize) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Time to wait before the first restart and the most to wait between any two restarts
+    ///
+    /// Defaults to 100ms and 30s.
+    #[must_use]
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Start the command and supervise it on a background thread
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::SystemError` if the first run of the command cannot be started,
+    /// later failures to start count as crashes.
+    pub fn start<OW, EW>(
+        mut self,
+        stdout_write: OW,
+        stderr_write: EW,
+    ) -> Result<Supervised, CmdError>
+    where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        let stdout_write = Arc::new(Mutex::new(stdout_write));
+        let stderr_write = Arc::new(Mutex::new(stderr_write));
+        let child = self.spawn(&stdout_write, &stderr_write)?;
+
+        let state = Arc::new(State {
+            status: Mutex::new(Status {
+                restarts: 0,
+                runs: Vec::new(),
+                stopping: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || self.supervise(child, &state, &stdout_write, &stderr_write))
+        };
+
+        Ok(Supervised { state, thread })
+    }
+
+    fn spawn<OW, EW>(
+        &mut self,
+        stdout_write: &Arc<Mutex<OW>>,
+        stderr_write: &Arc<Mutex<EW>>,
+    ) -> Result<NamedChild, CmdError>
+    where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        NamedChild::spawn(
+            self.name.clone(),
+            &mut self.command,
+            Arc::new(Readiness::new()),
+            SharedWriter(Arc::clone(stdout_write)),
+            SharedWriter(Arc::clone(stderr_write)),
+        )
+    }
+
+    fn supervise<OW, EW>(
+        mut self,
+        mut child: NamedChild,
+        state: &State,
+        stdout_write: &Arc<Mutex<OW>>,
+        stderr_write: &Arc<Mutex<EW>>,
+    ) where
+        OW: Write + Send + 'static,
+        EW: Write + Send + 'static,
+    {
+        let mut backoff = self.initial_backoff;
+        loop {
+            let stopping = loop {
+                if state.is_stopping() {
+                    let _ = child.kill();
+                    break true;
+                }
+                match child.try_wait_status() {
+                    Ok(None) => thread::sleep(POLL_INTERVAL),
+                    Ok(Some(_)) | Err(_) => break false,
+                }
+            };
+
+            let result = child.wait();
+            let crashed = result.is_err();
+            let restarts = state.record(result.unwrap_or_else(NamedOutput::from));
+            if stopping || !crashed || restarts >= self.max_restarts || state.sleep(backoff) {
+                return;
+            }
+            backoff = (backoff * 2).min(self.max_backoff);
+
+            child = loop {
+                state.restarted();
+                match self.spawn(stdout_write, stderr_write) {
+                    Ok(child) => break child,
+                    Err(error) => {
+                        let restarts = state.record(NamedOutput::from(error));
+                        if restarts >= self.max_restarts || state.sleep(backoff) {
+                            return;
+                        }
+                        backoff = (backoff * 2).min(self.max_backoff);
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// A command kept running by a [Supervisor]
+#[derive(Debug)]
+pub struct Supervised {
+    state: Arc<State>,
+    thread: JoinHandle<()>,
+}
+
+impl Supervised {
+    /// Number of times the command has been restarted
+    #[must_use]
+    pub fn restarts(&self) -> usize {
+        self.state.lock().restarts
+    }
+
+    /// Output of every run that has finished so far, oldest first
+    #[must_use]
+    pub fn runs(&self) -> Vec<NamedOutput> {
+        self.state.lock().runs.clone()
+    }
+
+    /// Returns true while the command is running or waiting to be restarted
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// Kill the command, stop restarting it and return the output of every run
+    #[must_use]
+    pub fn stop(self) -> Vec<NamedOutput> {
+        self.state.lock().stopping = true;
+        self.state.changed.notify_all();
+        self.join()
+    }
+
+    /// Wait until the command exits successfully or runs out of restarts, and return the output of every run
+    #[must_use]
+    pub fn join(self) -> Vec<NamedOutput> {
+        if let Err(error) = self.thread.join() {
+            std::panic::resume_unwind(error)
+        }
+        std::mem::take(&mut self.state.lock().runs)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    status: Mutex<Status>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct Status {
+    restarts: usize,
+    runs: Vec<NamedOutput>,
+    stopping: bool,
+}
+
+impl State {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.lock().stopping
+    }
+
+    /// Stores a finished run and returns the number of restarts so far
+    fn record(&self, run: NamedOutput) -> usize {
+        let mut status = self.lock();
+        status.runs.push(run);
+        status.restarts
+    }
+
+    fn restarted(&self) {
+        self.lock().restarts += 1;
+    }
+
+    /// Waits for the backoff to elapse, returns true if stopped in the meantime
+    fn sleep(&self, backoff: Duration) -> bool {
+        let status = self.lock();
+        self.changed
+            .wait_timeout_while(status, backoff, |status| !status.stopping)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+            .stopping
+    }
+}
+
+/// Lets every run of a supervised command write to the same writer
+struct SharedWriter<W>(Arc<Mutex<W>>);
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_stop_kills_running_command() {
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo up && exec sleep 100"]);
+
+        let supervised = Supervisor::new(command)
+            .named("sleepy")
+            .start(io::sink(), io::sink())
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(supervised.is_running());
+
+        let start = Instant::now();
+        let runs = supervised.stop();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(1, runs.len());
+        assert_eq!("sleepy", runs[0].name());
+        assert_eq!("up\n", runs[0].stdout_lossy());
+    }
+
+    #[test]
+    fn test_success_is_not_restarted() {
+        let mut command = Command::new("echo");
+        command.arg("done");
+
+        let supervised = Supervisor::new(command)
+            .start(io::sink(), io::sink())
+            .unwrap();
+        let runs = supervised.join();
+
+        assert_eq!(1, runs.len());
+        assert!(runs[0].status().success());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::NamedOutput;
+use std::sync::Arc;
+
+/// The wording of the error shown when a command exits with a non-zero status
+///
+/// Placeholders in braces are filled in from the failed command:
+///
+/// - `{name}` the command name
+/// - `{quoted_name}` the command name in the quotes of [crate::CmdError::display_with],
+///   backticks by default
+/// - `{status}` the exit code
+/// - `{stdout}` and `{stderr}` the output, or a note when it was streamed or discarded
+/// - `{stdin}` a `stdin:` line with the input from [crate::Interaction::record_input], or
+///   nothing when input was not recorded
+/// - `{duration}` seconds from spawn to exit, or `unknown` when it was not measured
+/// - `{context}` the lines from [NamedOutput::with_failure_context], each ending in a newline
+/// - `{hint}` a new line with the hint from [NamedOutput::with_streaming_hint] when output was
+///   captured or discarded rather than streamed, or nothing
+///
+/// Anything else, including unknown placeholders, is shown as written. Values are filled in
+/// once so output that contains braces is never treated as a placeholder. See
+/// [ErrorTemplate::DEFAULT] for the wording used without a template and [ErrorFormat] for
+/// wording that does not change between releases.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, ErrorTemplate};
+/// use std::process::Command;
+///
+/// let template = ErrorTemplate::new("Build step failed: {name} (exit {status})\n{stderr}");
+/// let error = Command::new("bash")
+///     .args(["-c", "echo 'missing semicolon' >&2; exit 2"])
+///     .named_output()
+///     .map_err(|error| error.with_error_template(template))
+///     .unwrap_err();
+///
+/// assert_eq!(
+///     "Build step failed: bash -c \"echo 'missing semicolon' >&2; exit 2\" (exit 2)\nmissing semicolon\n",
+///     error.to_string()
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorTemplate(Arc<str>);
+
+impl ErrorTemplate {
+    /// The wording used when no template is set
+    ///
+    /// May change in any release, use an [ErrorFormat] to rely on the exact text.
+    pub const DEFAULT: &'static str =
+        "Command failed {quoted_name}\n{context}exit status: {status}\n{stdin}stdout: {stdout}\nstderr: {stderr}{hint}";
+
+    /// A template with placeholders, see [ErrorTemplate]
+    #[must_use]
+    pub fn new(template: impl Into<String>) -> Self {
+        Self(Arc::from(template.into()))
+    }
+
+    /// The template as written
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A rendering of non-zero exit errors that stays the same across releases
+///
+/// The default wording may change to make errors clearer. Snapshot tests and tools that parse
+/// logs can select a versioned format instead, anywhere an [ErrorTemplate] is accepted. A
+/// format's template and the notes it shows for streamed, discarded or empty output are never
+/// changed once released, new wording gets a new version.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, ErrorFormat, FunRunner};
+/// use std::process::Command;
+///
+/// let runner = FunRunner::new().error_template(ErrorFormat::V1);
+/// let error = runner
+///     .run(
+///         Command::new("bash").args(["-c", "echo 'oh no' >&2; exit 3"]),
+///         |cmd| cmd.named_output(),
+///     )
+///     .unwrap_err();
+///
+/// assert_eq!(
+///     "Command failed `bash -c \"echo 'oh no' >&2; exit 3\"`\nexit status: 3\nstdout: <empty>\nstderr: oh no\n",
+///     error.to_string()
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorFormat {
+    /// The original wording, lines for the name, context, exit status, stdout and stderr:
+    ///
+    /// ```text
+    /// Command failed `{name}`
+    /// {context}exit status: {status}
+    /// stdout: {stdout}
+    /// stderr: {stderr}
+    /// ```
+    V1,
+}
+
+impl ErrorFormat {
+    /// The template of this format, see [ErrorTemplate] for placeholders
+    #[must_use]
+    pub fn template(self) -> &'static str {
+        match self {
+            ErrorFormat::V1 => {
+                "Command failed `{name}`\n{context}exit status: {status}\nstdout: {stdout}\nstderr: {stderr}"
+            }
+        }
+    }
+}
+
+/// How command names are set apart from the rest of an error message
+///
+/// Backticks read well in a terminal, but render as inline code when an error is posted
+/// somewhere that understands Markdown, such as a CI summary or a chat message. Select
+/// another style with [crate::CmdError::display_with].
+///
+/// Example:
+///
+/// ```
+/// use fun_run::NameQuote;
+///
+/// assert_eq!("`make`", NameQuote::Backtick.quoted("make").to_string());
+/// assert_eq!("make", NameQuote::None.quoted("make").to_string());
+/// assert_eq!("\n```\nmake\n```\n", NameQuote::CodeBlock.quoted("make").to_string());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NameQuote {
+    /// Surround the name with backticks
+    #[default]
+    Backtick,
+    /// Show the name as is
+    None,
+    /// Put the name in a fenced Markdown code block on its own lines
+    CodeBlock,
+}
+
+impl NameQuote {
+    /// Displays `name` in this style
+    #[must_use]
+    pub fn quoted(self, name: &str) -> impl std::fmt::Display + '_ {
+        Quoted(self, name)
+    }
+}
+
+struct Quoted<'a>(NameQuote, &'a str);
+
+impl std::fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Quoted(quote, name) = self;
+        match quote {
+            NameQuote::Backtick => write!(f, "`{name}`"),
+            NameQuote::None => f.write_str(name),
+            NameQuote::CodeBlock => write!(f, "\n```\n{name}\n```\n"),
+        }
+    }
+}
+
+impl From<ErrorFormat> for ErrorTemplate {
+    fn from(format: ErrorFormat) -> Self {
+        Self::new(format.template())
+    }
+}
+
+impl Default for ErrorTemplate {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT)
+    }
+}
+
+impl std::fmt::Display for ErrorTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Write `template` with placeholders filled in, see [ErrorTemplate]
+pub(crate) fn render(
+    f: &mut std::fmt::Formatter<'_>,
+    template: &str,
+    named_output: &NamedOutput,
+    stdout: &str,
+    stderr: &str,
+    quote: NameQuote,
+    hint: Option<&str>,
+) -> std::fmt::Result {
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        f.write_str(&rest[..open])?;
+        let after = &rest[open..];
+        let placeholder = after.find('}').map(|close| (&after[1..close], close));
+        match placeholder {
+            Some((key, close))
+                if write_value(f, key, named_output, stdout, stderr, quote, hint)? =>
+            {
+                rest = &after[close + 1..];
+            }
+            _ => {
+                f.write_str("{")?;
+                rest = &after[1..];
+            }
+        }
+    }
+    f.write_str(rest)
+}
+
+/// Writes the value of a placeholder, returns false for unknown placeholders
+fn write_value(
+    f: &mut std::fmt::Formatter<'_>,
+    key: &str,
+    named_output: &NamedOutput,
+    stdout: &str,
+    stderr: &str,
+    quote: NameQuote,
+    hint: Option<&str>,
+) -> Result<bool, std::fmt::Error> {
+    match key {
+        "name" => f.write_str(&named_output.fitted_name())?,
+        "quoted_name" => write!(f, "{}", quote.quoted(&named_output.fitted_name()))?,
+        "status" => write!(f, "{}", named_output.status().code().unwrap_or(1))?,
+        "stdout" => f.write_str(stdout)?,
+        "stderr" => f.write_str(stderr)?,
+        "duration" => match named_output.duration() {
+            Some(duration) => write!(f, "{:.2}s", duration.as_secs_f64())?,
+            None => f.write_str("unknown")?,
+        },
+        "context" => crate::write_context(f, named_output)?,
+        "stdin" => crate::write_stdin(f, named_output)?,
+        "hint" => {
+            if let Some(hint) = hint {
+                write!(f, "\nhint: {hint}")?;
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    struct Rendered<'a>(&'a str, &'a NamedOutput);
+
+    impl std::fmt::Display for Rendered<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            render(
+                f,
+                self.0,
+                self.1,
+                "{stderr}",
+                "oops",
+                NameQuote::Backtick,
+                Some("try -v"),
+            )
+        }
+    }
+
+    #[test]
+    fn test_v1_format_does_not_change() {
+        assert_eq!(
+            "Command failed `{name}`\n{context}exit status: {status}\nstdout: {stdout}\nstderr: {stderr}",
+            ErrorFormat::V1.template()
+        );
+        assert_eq!("<empty>", crate::display_out_or_empty(""));
+        assert_eq!("<see above>", crate::SEE_ABOVE);
+        assert_eq!("<discarded>", crate::DISCARDED);
+    }
+
+    #[test]
+    fn test_placeholders_are_filled_in_once() {
+        let named_output = NamedOutput::new(
+            "make".to_string(),
+            Output {
+                status: ExitStatus::from_raw(2 << 8),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            },
+        );
+        assert_eq!(
+            "make `make` 2 {stderr} oops unknown {other}\nhint: try -v {",
+            Rendered(
+                "{name} {quoted_name} {status} {stdout} {stderr} {duration} {other}{hint} {",
+                &named_output
+            )
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_hint_follows_captured_or_discarded_output() {
+        let named_output = NamedOutput::new(
+            "make".to_string(),
+            Output {
+                status: ExitStatus::from_raw(2 << 8),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            },
+        )
+        .with_streaming_hint("re-run with -v");
+
+        let error = named_output.clone().nonzero_stdout_discarded().unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("stdout: <discarded>\nstderr: <empty>\nhint: re-run with -v"));
+
+        let error = named_output.clone().nonzero_streamed().unwrap_err();
+        assert!(!error.to_string().contains("hint:"), "{error}");
+
+        let error = named_output
+            .with_error_template(ErrorFormat::V1)
+            .nonzero_captured()
+            .unwrap_err();
+        assert!(!error.to_string().contains("hint:"), "{error}");
+    }
+}
@@ -0,0 +1,195 @@
+use std::process::Command;
+
+/// The number of columns and rows of a terminal
+///
+/// Commands run by fun run are attached to pipes rather than a terminal, so tools that size
+/// their output (progress bars, column layouts) fall back to the `COLUMNS` and `LINES`
+/// environment variables. Use [TerminalSize::apply] to set them, either from the terminal this
+/// process is attached to or a fixed size for deterministic captures on CI.
+///
+/// The size is read once when the command starts, later resizes are not seen by the command.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, TerminalSize};
+/// use std::process::Command;
+///
+/// let mut command = Command::new("bash");
+/// command.args(["-c", "echo $COLUMNS"]);
+///
+/// TerminalSize::from_parent()
+///     .unwrap_or(TerminalSize::new(80, 24))
+///     .apply(&mut command);
+///
+/// let output = command.named_output().unwrap();
+/// assert!(output.stdout_lossy().trim().parse::<u16>().is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TerminalSize {
+    /// Width in characters, set as `COLUMNS`
+    pub columns: u16,
+    /// Height in lines, set as `LINES`
+    pub rows: u16,
+}
+
+impl TerminalSize {
+    #[must_use]
+    pub fn new(columns: u16, rows: u16) -> Self {
+        Self { columns, rows }
+    }
+
+    /// Size of the terminal attached to stdout, stderr or stdin of this process, in that order
+    ///
+    /// Returns `None` when none of them are a terminal, such as on CI.
+    #[must_use]
+    pub fn from_parent() -> Option<Self> {
+        [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+            .into_iter()
+            .find_map(window_size)
+    }
+
+    /// Size of the terminal attached to this process, falling back to the `COLUMNS` and
+    /// `LINES` environment variables
+    ///
+    /// `LINES` defaults to 24 when only `COLUMNS` is set. Returns `None` when neither gives a
+    /// width, callers pick their own default.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use fun_run::TerminalSize;
+    ///
+    /// let columns = TerminalSize::detect().map_or(80, |size| size.columns);
+    /// assert!(columns > 0);
+    /// ```
+    #[must_use]
+    pub fn detect() -> Option<Self> {
+        Self::from_parent().or_else(|| {
+            let env = |key| std::env::var(key).ok()?.trim().parse::<u16>().ok();
+            let columns = env("COLUMNS").filter(|columns| *columns > 0)?;
+            Some(Self::new(columns, env("LINES").unwrap_or(24)))
+        })
+    }
+
+    /// Set `COLUMNS` and `LINES` for the command
+    pub fn apply(self, command: &mut Command) {
+        command
+            .env("COLUMNS", self.columns.to_string())
+            .env("LINES", self.rows.to_string());
+    }
+}
+
+/// How to show a command name that is wider than the space it is printed in
+///
+/// Widths are counted in characters.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::NameFit;
+///
+/// let name = "bundle exec rspec spec/models/user_spec.rb";
+/// assert_eq!("bundle exec …user_spec.rb", NameFit::Truncate.apply(name, 25));
+/// assert_eq!(
+///     "bundle exec rspec\nspec/models/user_spec.rb",
+///     NameFit::Wrap.apply(name, 26)
+/// );
+/// assert_eq!(name, NameFit::Truncate.apply(name, 80));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameFit {
+    /// Replace the middle with `…`, keeping the program and the last arguments
+    Truncate,
+    /// Break into lines between arguments, splitting arguments wider than a line
+    Wrap,
+}
+
+impl NameFit {
+    /// Fit `name` into `width` characters, names that already fit are unchanged
+    #[must_use]
+    pub fn apply(self, name: &str, width: usize) -> String {
+        let chars = name.chars().collect::<Vec<_>>();
+        if chars.len() <= width {
+            return name.to_string();
+        }
+        match self {
+            NameFit::Truncate if width == 0 => String::new(),
+            NameFit::Truncate => {
+                let keep = width.saturating_sub(1);
+                let head = chars[..keep.div_ceil(2)].iter();
+                let tail = chars[chars.len() - keep / 2..].iter();
+                head.chain(std::iter::once(&'…')).chain(tail).collect()
+            }
+            NameFit::Wrap => {
+                let width = width.max(1);
+                let mut lines = Vec::<String>::new();
+                let mut line = String::new();
+                for word in name.split(' ') {
+                    let line_width = line.chars().count();
+                    if line_width > 0 && line_width + 1 + word.chars().count() > width {
+                        lines.push(std::mem::take(&mut line));
+                    } else if line_width > 0 {
+                        line.push(' ');
+                    }
+                    for c in word.chars() {
+                        if line.chars().count() == width {
+                            lines.push(std::mem::take(&mut line));
+                        }
+                        line.push(c);
+                    }
+                }
+                lines.push(line);
+                lines.join("\n")
+            }
+        }
+    }
+}
+
+fn window_size(fd: libc::c_int) -> Option<TerminalSize> {
+    // SAFETY: `winsize` is plain data and `TIOCGWINSZ` only writes to it
+    let size = unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(fd, libc::TIOCGWINSZ, &raw mut size) == -1 {
+            return None;
+        }
+        size
+    };
+    Some(TerminalSize::new(size.ws_col, size.ws_row)).filter(|size| size.columns > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+
+    #[test]
+    fn test_apply_fixed_size() {
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo ${COLUMNS}x${LINES}"]);
+        TerminalSize::new(120, 40).apply(&mut command);
+
+        assert_eq!("120x40\n", command.named_output().unwrap().stdout_lossy());
+    }
+
+    #[test]
+    fn test_fit_edge_cases() {
+        assert_eq!(
+            "ab…yz",
+            NameFit::Truncate.apply("abcdefghijklmnopqrstuvwxyz", 5)
+        );
+        assert_eq!("…", NameFit::Truncate.apply("abc", 1));
+        assert_eq!("", NameFit::Truncate.apply("abc", 0));
+        assert_eq!(
+            "make\nabcde\nfghij\nk",
+            NameFit::Wrap.apply("make abcdefghijk", 5)
+        );
+        assert_eq!("a\nb", NameFit::Wrap.apply("ab", 0));
+    }
+
+    #[test]
+    fn test_pipe_is_not_a_terminal() {
+        let (read, _write) = std::io::pipe().unwrap();
+        assert_eq!(None, window_size(std::os::fd::AsRawFd::as_raw_fd(&read)));
+    }
+}
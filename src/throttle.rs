@@ -0,0 +1,236 @@
+use crate::{CmdError, CommandWithName, NamedOutput};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Limits how many runs may happen at once and how many may start within a window
+///
+/// Use one throttle per group of commands that share a limit, for example all `git clone`
+/// calls against a rate limited host. When a limit is reached runs wait for a free slot by
+/// default, call [Throttle::error_when_limited] to return `CmdError::Throttled` instead.
+///
+/// Clones share state.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, Throttle};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let throttle = Throttle::new()
+///     .max_concurrent(2)
+///     .max_per(10, Duration::from_secs(60));
+///
+/// let handles = (0..4)
+///     .map(|_| {
+///         let throttle = throttle.clone();
+///         std::thread::spawn(move || {
+///             throttle.run(&mut Command::new("true"), |cmd| cmd.named_output())
+///         })
+///     })
+///     .collect::<Vec<_>>();
+///
+/// for handle in handles {
+///     handle.join().unwrap().unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    max_concurrent: Option<usize>,
+    max_per: Option<(usize, Duration)>,
+    wait: bool,
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    started: VecDeque<Instant>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            max_concurrent: None,
+            max_per: None,
+            wait: true,
+            state: Arc::default(),
+        }
+    }
+}
+
+impl Throttle {
+    /// A throttle without limits, add them with the builder methods
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// At most `max` runs at the same time, a `max` of 0 is treated as 1
+    #[must_use]
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = Some(max.max(1));
+        self
+    }
+
+    /// At most `max` runs started within any `window`, a `max` of 0 is treated as 1
+    #[must_use]
+    pub fn max_per(mut self, max: usize, window: Duration) -> Self {
+        self.max_per = Some((max.max(1), window));
+        self
+    }
+
+    /// Return `CmdError::Throttled` when a limit is reached instead of waiting
+    #[must_use]
+    pub fn error_when_limited(mut self) -> Self {
+        self.wait = false;
+        self
+    }
+
+    /// Run the command with `f` once the limits allow it
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmdError::Throttled` if a limit is reached and the throttle does not wait,
+    /// otherwise any error returned by `f`.
+    pub fn run<C, F>(&self, command: &mut C, f: F) -> Result<NamedOutput, CmdError>
+    where
+        C: CommandWithName,
+        F: FnOnce(&mut C) -> Result<NamedOutput, CmdError>,
+    {
+        let name = command.name();
+        let _slot = self
+            .acquire()
+            .map_err(|reason| CmdError::Throttled(name, reason))?;
+        f(command)
+    }
+
+    fn acquire(&self) -> Result<Slot<'_>, String> {
+        let (mutex, changed) = &*self.state;
+        let mut state = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let now = Instant::now();
+            if let Some((_, window)) = self.max_per {
+                while state
+                    .started
+                    .front()
+                    .is_some_and(|started| now.duration_since(*started) >= window)
+                {
+                    state.started.pop_front();
+                }
+            }
+
+            let limited = match (self.max_concurrent, self.max_per) {
+                (Some(max), _) if state.running >= max => {
+                    Some((format!("{max} already running"), None))
+                }
+                (_, Some((max, window))) if state.started.len() >= max => Some((
+                    format!("{max} started in the last {window:?}"),
+                    state.started.front().map(|started| *started + window),
+                )),
+                _ => None,
+            };
+
+            state = match limited {
+                None => {
+                    state.running += 1;
+                    state.started.push_back(now);
+                    return Ok(Slot { throttle: self });
+                }
+                Some((reason, _)) if !self.wait => return Err(reason),
+                Some((_, Some(free_at))) => {
+                    wait_timeout(changed, state, free_at.saturating_duration_since(now))
+                }
+                Some((_, None)) => changed.wait(state).unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+fn wait_timeout<'a>(
+    changed: &Condvar,
+    state: MutexGuard<'a, State>,
+    timeout: Duration,
+) -> MutexGuard<'a, State> {
+    changed
+        .wait_timeout(state, timeout)
+        .unwrap_or_else(PoisonError::into_inner)
+        .0
+}
+
+/// A running slot, freed on drop
+struct Slot<'a> {
+    throttle: &'a Throttle,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let (mutex, changed) = &*self.throttle.state;
+        mutex.lock().unwrap_or_else(PoisonError::into_inner).running -= 1;
+        changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_error_when_limited() {
+        let throttle = Throttle::new()
+            .max_per(1, Duration::from_secs(60))
+            .error_when_limited();
+        let mut command = Command::new("true");
+
+        throttle
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap();
+        let error = throttle
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap_err();
+
+        assert_eq!(
+            "Not running command `true`. Limit reached, 1 started in the last 60s",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_zero_limits_allow_one_run() {
+        let throttle = Throttle::new()
+            .max_concurrent(0)
+            .max_per(0, Duration::from_secs(60))
+            .error_when_limited();
+        let mut command = Command::new("true");
+
+        throttle
+            .run(&mut command, CommandWithName::named_output)
+            .unwrap();
+        assert!(throttle
+            .run(&mut command, CommandWithName::named_output)
+            .is_err());
+    }
+
+    #[test]
+    fn test_waits_for_concurrent_slot() {
+        let throttle = Throttle::new().max_concurrent(1);
+        let start = Instant::now();
+
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    throttle
+                        .run(&mut Command::new("sleep"), |cmd| {
+                            cmd.mut_cmd().arg("0.2");
+                            cmd.named_output()
+                        })
+                        .unwrap()
+                });
+            }
+        });
+
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::time::Duration;
+
+/// One command on a timeline
+pub(crate) struct Span<'a> {
+    pub(crate) name: &'a str,
+    /// See [crate::NamedOutput::run_id]
+    pub(crate) run_id: Option<u64>,
+    /// Since the start of the timeline
+    pub(crate) start: Duration,
+    pub(crate) duration: Duration,
+    pub(crate) error: Option<String>,
+}
+
+/// Write spans as a Chrome tracing JSON file, viewable in Perfetto or `chrome://tracing`
+///
+/// Overlapping spans are placed on separate rows (thread ids) so parallel commands show up
+/// side by side.
+pub(crate) fn write_chrome_trace(
+    spans: &[Span<'_>],
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    let mut order = (0..spans.len()).collect::<Vec<_>>();
+    order.sort_by_key(|index| spans[*index].start);
+
+    // End of the last span on each row
+    let mut rows: Vec<Duration> = Vec::new();
+    let mut events = Vec::new();
+    for index in order {
+        let span = &spans[index];
+        let end = span.start + span.duration;
+        let row = match rows.iter().position(|row_end| *row_end <= span.start) {
+            Some(row) => {
+                rows[row] = end;
+                row
+            }
+            None => {
+                rows.push(end);
+                rows.len() - 1
+            }
+        };
+        events.push(serde_json::json!({
+            "name": span.name,
+            "cat": "command",
+            "ph": "X",
+            "ts": span.start.as_micros() as u64,
+            "dur": span.duration.as_micros() as u64,
+            "pid": 1,
+            "tid": row + 1,
+            "args": {
+                "run_id": span.run_id,
+                "success": span.error.is_none(),
+                "error": span.error,
+            },
+        }));
+    }
+
+    let trace = serde_json::json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ms",
+    });
+    serde_json::to_writer(&mut writer, &trace)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_spans_get_their_own_rows() {
+        let span = |name, start, duration| Span {
+            name,
+            run_id: None,
+            start: Duration::from_millis(start),
+            duration: Duration::from_millis(duration),
+            error: None,
+        };
+        let spans = [
+            span("compile", 0, 100),
+            span("lint", 10, 20),
+            span("test", 100, 50),
+            span("docs", 40, 10),
+        ];
+
+        let mut out = Vec::new();
+        write_chrome_trace(&spans, &mut out).unwrap();
+        let trace: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let rows = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap(),
+                    event["tid"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![("compile", 1), ("lint", 2), ("docs", 2), ("test", 1)],
+            rows
+        );
+        assert_eq!(100_000, trace["traceEvents"][3]["ts"]);
+    }
+}
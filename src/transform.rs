@@ -0,0 +1,177 @@
+use crate::mux::MAX_LINE_LENGTH;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+type Step = Arc<dyn Fn(String) -> Option<String> + Send + Sync>;
+
+/// A composable pipeline of line transformations applied before output reaches a writer
+///
+/// Use it to drop noisy lines, rewrite paths, or colorize matches in streamed output.
+/// Steps run in the order they were added and a filtered line skips the remaining steps.
+///
+/// Transformations only affect what is written to the wrapped writer. When used with
+/// [crate::CommandWithName::stream_output] the captured stdout and stderr in the resulting
+/// [crate::NamedOutput] keep the untransformed original. To get transformed captures
+/// pass them through [LineTransform::apply].
+///
+/// Example:
+///
+/// ```
+/// use fun_run::{CommandWithName, LineTransform};
+/// use std::process::Command;
+///
+/// let transform = LineTransform::new()
+///     .filter(|line| !line.starts_with("debug:"))
+///     .map(|line| line.replace("/tmp/build", "<build>"));
+///
+/// let mut shown = Vec::new();
+/// let output = Command::new("bash")
+///     .args(["-c", "echo 'debug: noise' && echo 'wrote /tmp/build/out.txt'"])
+///     .stream_output(transform.writer(&mut shown), std::io::stderr())
+///     .unwrap();
+///
+/// assert_eq!("wrote <build>/out.txt\n", String::from_utf8_lossy(&shown));
+/// assert_eq!("debug: noise\nwrote /tmp/build/out.txt\n", output.stdout_lossy());
+/// assert_eq!(
+///     "wrote <build>/out.txt\n",
+///     String::from_utf8_lossy(&transform.apply(output.stdout()))
+/// );
+/// ```
+#[derive(Clone, Default)]
+pub struct LineTransform {
+    steps: Vec<Step>,
+}
+
+impl LineTransform {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep lines for which `keep` returns true
+    #[must_use]
+    pub fn filter(mut self, keep: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.steps
+            .push(Arc::new(move |line| keep(&line).then_some(line)));
+        self
+    }
+
+    /// Replace each line with the result of `f`
+    #[must_use]
+    pub fn map(mut self, f: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.steps.push(Arc::new(move |line| Some(f(&line))));
+        self
+    }
+
+    /// Replace each line with the result of `f`, returning `None` removes the line
+    #[must_use]
+    pub fn filter_map(
+        mut self,
+        f: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.push(Arc::new(move |line| f(&line)));
+        self
+    }
+
+    /// Returns a writer that applies the transformations to every line before writing to `inner`
+    ///
+    /// Lines are written once complete, a trailing partial line is written when the writer is dropped.
+    /// Lines longer than 64 KiB are transformed in pieces.
+    #[must_use]
+    pub fn writer<W: Write>(&self, inner: W) -> TransformWriter<W> {
+        TransformWriter {
+            inner,
+            transform: self.clone(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Apply the transformations to already captured output
+    #[must_use]
+    pub fn apply(&self, contents: &[u8]) -> Vec<u8> {
+        contents
+            .split_inclusive(|&byte| byte == b'\n')
+            .filter_map(|line| self.line(line))
+            .flatten()
+            .collect()
+    }
+
+    fn line(&self, line: &[u8]) -> Option<Vec<u8>> {
+        let (line, newline) = match line.strip_suffix(b"\n") {
+            Some(line) => (line, "\n"),
+            None => (line, ""),
+        };
+
+        self.steps
+            .iter()
+            .try_fold(String::from_utf8_lossy(line).to_string(), |line, step| {
+                step(line)
+            })
+            .map(|line| format!("{line}{newline}").into_bytes())
+    }
+}
+
+impl std::fmt::Debug for LineTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LineTransform")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+/// A writer created with [LineTransform::writer]
+#[derive(Debug)]
+pub struct TransformWriter<W: Write> {
+    inner: W,
+    transform: LineTransform,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Write for TransformWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.ends_with(b"\n") || self.line.len() >= MAX_LINE_LENGTH {
+                if let Some(transformed) = self.transform.line(&self.line) {
+                    self.inner.write_all(&transformed)?;
+                }
+                self.line.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for TransformWriter<W> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            if let Some(transformed) = self.transform.line(&self.line) {
+                let _ = self.inner.write_all(&transformed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_str_eq;
+
+    #[test]
+    fn test_writer_across_writes() {
+        let transform = LineTransform::new()
+            .filter_map(|line| line.strip_prefix("keep ").map(str::to_uppercase));
+        let mut out = Vec::new();
+        let mut writer = transform.writer(&mut out);
+
+        writer.write_all(b"keep a\ndrop b\nke").unwrap();
+        writer.write_all(b"ep c\nkeep d").unwrap();
+        drop(writer);
+
+        assert_str_eq!("A\nC\nD", String::from_utf8_lossy(&out));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::CmdError;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Check a command's configuration before spawning it
+///
+/// Verifies that the program, arguments and environment contain no NUL bytes, that
+/// environment keys set on the command are not empty or contain `=`, that the working
+/// directory exists and that the program resolves to an executable. Also checks that the
+/// arguments and environment fit in the platform's `ARG_MAX` limit and that no single
+/// argument is over the Linux `MAX_ARG_STRLEN` limit, see
+/// [ValidationError::ArgumentListTooLong] and [ValidationError::ArgumentTooLong]. Programs
+/// containing a `/` are resolved relative to the working directory, otherwise they are searched
+/// for on the command's `PATH`.
+///
+/// Example:
+///
+/// ```
+/// use fun_run::ValidationError;
+/// use std::process::Command;
+///
+/// let mut command = Command::new("bundle");
+/// command.arg("install").current_dir("/does/not/exist");
+///
+/// match fun_run::validate(&mut command) {
+///     Err(ValidationError::MissingCurrentDir(name, dir)) => {
+///         assert_eq!("bundle install", name);
+///         assert_eq!(std::path::Path::new("/does/not/exist"), dir);
+///     }
+///     other => panic!("Expected a missing directory, got {other:?}"),
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns the first [ValidationError] found. NUL bytes and environment problems are checked
+/// first, then the size of the arguments and environment, the working directory and finally
+/// the program.
+pub fn validate(command: &mut Command) -> Result<(), ValidationError> {
+    if let Some(error) = find_nul(command) {
+        return Err(error);
+    }
+    let name = crate::display(command);
+
+    if let Some((key, _)) = command
+        .get_envs()
+        .find(|(key, _)| key.is_empty() || key.as_bytes().contains(&b'='))
+    {
+        return Err(ValidationError::InvalidEnvKey(name, key.to_os_string()));
+    }
+
+    if let Some((size, limit)) = exceeds_arg_max(command) {
+        return Err(ValidationError::ArgumentListTooLong(name, size, limit));
+    }
+    if let Some((position, len)) = exceeds_arg_strlen(command) {
+        return Err(ValidationError::ArgumentTooLong(name, position, len));
+    }
+
+    let dir = command.get_current_dir();
+    if let Some(dir) = dir {
+        if !dir.is_dir() {
+            return Err(ValidationError::MissingCurrentDir(name, dir.to_path_buf()));
+        }
+    }
+
+    let program = command.get_program();
+    if program.as_bytes().contains(&b'/') {
+        let path = match dir {
+            Some(dir) => dir.join(program),
+            None => PathBuf::from(program),
+        };
+        if !path.exists() {
+            return Err(ValidationError::ProgramNotFound(
+                name,
+                program.to_os_string(),
+            ));
+        }
+        if !crate::command::is_executable(&path) {
+            return Err(ValidationError::ProgramNotExecutable(name, path));
+        }
+        Ok(())
+    } else if find_on_path(program, crate::command::path_env(command).as_deref()).is_some() {
+        Ok(())
+    } else {
+        Err(ValidationError::ProgramNotFound(
+            name,
+            program.to_os_string(),
+        ))
+    }
+}
+
+/// What [Command] stores in place of a program or argument containing a NUL byte
+const NUL_PLACEHOLDER: &str = "<string-with-nul>";
+
+/// The first NUL byte in the program, arguments or environment, which would make spawning fail
+///
+/// [Command] replaces a program or argument containing a NUL byte with a placeholder, so only
+/// the position can be reported. An argument that really is the placeholder text is reported too.
+pub(crate) fn find_nul(command: &mut Command) -> Option<ValidationError> {
+    let has_nul = |value: &OsStr| value == NUL_PLACEHOLDER || value.as_bytes().contains(&0);
+
+    if has_nul(command.get_program()) {
+        return Some(ValidationError::NulInProgram(crate::display(command)));
+    }
+    if let Some(position) = command.get_args().position(has_nul) {
+        return Some(ValidationError::NulInArgument(
+            crate::display(command),
+            position + 1,
+        ));
+    }
+    let key = command
+        .get_envs()
+        .find(|(key, value)| has_nul(key) || value.is_some_and(has_nul))
+        .map(|(key, _)| key.to_os_string())?;
+    let name = crate::display(command);
+    Some(if has_nul(&key) {
+        ValidationError::InvalidEnvKey(name, key)
+    } else {
+        ValidationError::NulInEnvValue(name, key)
+    })
+}
+
+/// Headroom below `ARG_MAX` recommended by POSIX for `xargs`
+const ARG_MAX_HEADROOM: usize = 2048;
+
+/// The estimated size of the arguments and environment and the limit, if the limit is exceeded
+///
+/// Each string counts its bytes, a NUL terminator and a pointer, the same way the kernel does.
+/// The environment is the parent's with the command's changes applied. After an `env_clear`
+/// this overestimates, which only matters when the kernel already refused the command.
+pub(crate) fn exceeds_arg_max(command: &Command) -> Option<(usize, usize)> {
+    // SAFETY: sysconf has no preconditions and only reads a system setting
+    let arg_max = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    let limit = usize::try_from(arg_max)
+        .ok()?
+        .saturating_sub(ARG_MAX_HEADROOM);
+
+    let mut env = std::env::vars_os().collect::<std::collections::BTreeMap<_, _>>();
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => env.insert(key.to_os_string(), value.to_os_string()),
+            None => env.remove(key),
+        };
+    }
+
+    let entry = |len: usize| len + 1 + std::mem::size_of::<*const libc::c_char>();
+    let size = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| entry(arg.len()))
+        .chain(
+            env.iter()
+                .map(|(key, value)| entry(key.len() + 1 + value.len())),
+        )
+        .sum::<usize>();
+
+    (size > limit).then_some((size, limit))
+}
+
+/// The position, starting from 1 like `$1`, and length of the first argument over the
+/// `MAX_ARG_STRLEN` limit
+///
+/// Linux limits every argument to 32 pages, 128 KiB on most systems, no matter how much of
+/// `ARG_MAX` is left. Other platforms have no separate limit.
+pub(crate) fn exceeds_arg_strlen(command: &Command) -> Option<(usize, usize)> {
+    let limit = max_arg_strlen()?;
+    command
+        .get_args()
+        .enumerate()
+        // The kernel counts the NUL terminator
+        .find(|(_, arg)| arg.len() + 1 > limit)
+        .map(|(index, arg)| (index + 1, arg.len()))
+}
+
+/// `MAX_ARG_STRLEN` on Linux, `None` on platforms without a separate limit
+pub(crate) fn max_arg_strlen() -> Option<usize> {
+    if !cfg!(any(target_os = "linux", target_os = "android")) {
+        return None;
+    }
+    // SAFETY: sysconf has no preconditions and only reads a system setting
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(page_size)
+        .ok()
+        .map(|page_size| page_size * 32)
+}
+
+fn find_on_path(program: &OsStr, path_env: Option<&OsStr>) -> Option<PathBuf> {
+    std::env::split_paths(path_env?)
+        .map(|dir| dir.join(program))
+        .find(|path| crate::command::is_executable(path))
+}
+
+/// A problem found by [validate], the first field is the name of the command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The program contains a NUL byte
+    NulInProgram(String),
+    /// The argument at this position, starting from 1 like `$1`, contains a NUL byte
+    NulInArgument(String, usize),
+    /// The arguments and environment take this many bytes, more than the `ARG_MAX` limit
+    ///
+    /// Split the arguments across several runs like `xargs` does, or pass them in a file.
+    ArgumentListTooLong(String, usize, usize),
+    /// The argument at this position, starting from 1 like `$1`, is this many bytes, more than
+    /// the `MAX_ARG_STRLEN` limit Linux puts on a single argument
+    ///
+    /// Pass the value in a file or on stdin instead.
+    ArgumentTooLong(String, usize, usize),
+    /// The program is not on the `PATH` or the path to it does not exist
+    ProgramNotFound(String, OsString),
+    /// The path to the program exists but is not an executable file
+    ProgramNotExecutable(String, PathBuf),
+    /// The working directory does not exist or is not a directory
+    MissingCurrentDir(String, PathBuf),
+    /// An environment key is empty or contains `=` or a NUL byte
+    InvalidEnvKey(String, OsString),
+    /// The value of the environment key contains a NUL byte
+    NulInEnvValue(String, OsString),
+}
+
+impl ValidationError {
+    /// Name of the command that failed validation
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            ValidationError::NulInProgram(name)
+            | ValidationError::NulInArgument(name, _)
+            | ValidationError::ArgumentListTooLong(name, _, _)
+            | ValidationError::ArgumentTooLong(name, _, _)
+            | ValidationError::ProgramNotFound(name, _)
+            | ValidationError::ProgramNotExecutable(name, _)
+            | ValidationError::MissingCurrentDir(name, _)
+            | ValidationError::InvalidEnvKey(name, _)
+            | ValidationError::NulInEnvValue(name, _) => name,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.name();
+        match self {
+            ValidationError::NulInProgram(_) => {
+                write!(f, "Cannot run command `{name}`. The program contains a NUL byte")
+            }
+            ValidationError::NulInArgument(_, position) => write!(
+                f,
+                "Cannot run command `{name}`. Argument {position} contains a NUL byte"
+            ),
+            ValidationError::ArgumentListTooLong(_, size, limit) => write!(
+                f,
+                "Cannot run command `{name}`. Arguments and environment take about {size} bytes which is more than the {limit} byte limit (ARG_MAX). Split the arguments across several runs or pass them in a file instead"
+            ),
+            ValidationError::ArgumentTooLong(_, position, len) => {
+                let limit = max_arg_strlen()
+                    .map(|limit| format!("{limit} byte "))
+                    .unwrap_or_default();
+                write!(
+                    f,
+                    "Cannot run command `{name}`. Argument {position} is {len} bytes which is more than the {limit}limit for a single argument (MAX_ARG_STRLEN). Pass it in a file or on stdin instead"
+                )
+            }
+            ValidationError::ProgramNotFound(_, program) => write!(
+                f,
+                "Cannot run command `{name}`. Program {} was not found",
+                crate::Quoting::Debug.quote(program)
+            ),
+            ValidationError::ProgramNotExecutable(_, path) => write!(
+                f,
+                "Cannot run command `{name}`. {} is not an executable file",
+                crate::Quoting::Debug.quote(path.as_os_str())
+            ),
+            ValidationError::MissingCurrentDir(_, dir) => write!(
+                f,
+                "Cannot run command `{name}`. Working directory {} does not exist",
+                crate::Quoting::Debug.quote(dir.as_os_str())
+            ),
+            ValidationError::InvalidEnvKey(_, key) => write!(
+                f,
+                "Cannot run command `{name}`. Environment key {} must not be empty or contain `=` or NUL bytes",
+                crate::Quoting::Debug.quote(key)
+            ),
+            ValidationError::NulInEnvValue(_, key) => write!(
+                f,
+                "Cannot run command `{name}`. Value of environment variable {} contains a NUL byte",
+                crate::Quoting::Debug.quote(key)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Becomes a [CmdError::SystemError] so validation can be chained with running the command
+impl From<ValidationError> for CmdError {
+    fn from(error: ValidationError) -> Self {
+        let kind = match error {
+            ValidationError::ProgramNotFound(..) | ValidationError::MissingCurrentDir(..) => {
+                std::io::ErrorKind::NotFound
+            }
+            ValidationError::ProgramNotExecutable(..) => std::io::ErrorKind::PermissionDenied,
+            ValidationError::NulInProgram(..)
+            | ValidationError::NulInArgument(..)
+            | ValidationError::InvalidEnvKey(..)
+            | ValidationError::NulInEnvValue(..)
+            | ValidationError::ArgumentListTooLong(..)
+            | ValidationError::ArgumentTooLong(..) => std::io::ErrorKind::InvalidInput,
+        };
+        CmdError::SystemError(
+            error.name().to_string(),
+            std::io::Error::new(kind, error),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandWithName;
+
+    #[test]
+    fn test_valid_command() {
+        let mut command = Command::new("echo");
+        command.env("GREETING", "hello").current_dir("/");
+        assert_eq!(Ok(()), validate(&mut command));
+
+        let mut command = Command::new("./bin/sh");
+        command.current_dir("/");
+        assert_eq!(Ok(()), validate(&mut command));
+    }
+
+    #[test]
+    fn test_invalid_env() {
+        let mut command = Command::new("echo");
+        command.env("A=B", "c");
+        assert_eq!(
+            Err(ValidationError::InvalidEnvKey(
+                "echo".to_string(),
+                OsString::from("A=B")
+            )),
+            validate(&mut command)
+        );
+
+        let mut command = Command::new("echo");
+        command.env("TOKEN", "abc\0def");
+        let error = validate(&mut command).unwrap_err();
+        assert_eq!(
+            "Cannot run command `echo`. Value of environment variable TOKEN contains a NUL byte",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_nul_argument_spawn_error_names_argument() {
+        let mut command = Command::new("echo");
+        command.args(["one", "two", "th\0ree"]);
+
+        let error = command.named_output().unwrap_err();
+        assert_eq!(
+            Some(&ValidationError::NulInArgument(
+                "echo one two \"<string-with-nul>\"".to_string(),
+                3
+            )),
+            error.validation_error()
+        );
+        assert_eq!(
+            "Cannot run command `echo one two \"<string-with-nul>\"`. Argument 3 contains a NUL byte",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_argument_list_too_long() {
+        let mut command = Command::new("echo");
+        command.args(std::iter::repeat_n("x".repeat(1024), 4096));
+
+        let error = CmdError::from(validate(&mut command).unwrap_err());
+        assert!(matches!(
+            error.validation_error(),
+            Some(ValidationError::ArgumentListTooLong(_, size, limit)) if size > limit
+        ));
+
+        let error = command.named_output().unwrap_err();
+        assert!(matches!(
+            error.validation_error(),
+            Some(ValidationError::ArgumentListTooLong(..))
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_single_argument_too_long() {
+        let mut command = Command::new("echo");
+        command.args(["short", &"x".repeat(200 * 1024)]);
+
+        let error = validate(&mut command).unwrap_err();
+        assert_eq!(
+            ValidationError::ArgumentTooLong(error.name().to_string(), 2, 200 * 1024),
+            error
+        );
+
+        let error = command.named_output().unwrap_err();
+        assert!(matches!(
+            error.validation_error(),
+            Some(ValidationError::ArgumentTooLong(_, 2, _))
+        ));
+        let message = error.to_string();
+        assert!(message.contains("Argument 2 is 204800 bytes"), "{message}");
+        assert!(!message.contains(" 0 byte"), "{message}");
+    }
+
+    #[test]
+    fn test_program_not_found() {
+        let mut command = Command::new("does_not_exist_fun_run");
+        command.env("PATH", "/bin:/usr/bin");
+        assert!(matches!(
+            validate(&mut command),
+            Err(ValidationError::ProgramNotFound(..))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("fun_run_validate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("script.sh"), "echo hello\n").unwrap();
+
+        let mut command = Command::new("./script.sh");
+        command.current_dir(&dir);
+        assert_eq!(
+            Err(ValidationError::ProgramNotExecutable(
+                "./script.sh".to_string(),
+                dir.join("./script.sh")
+            )),
+            validate(&mut command)
+        );
+
+        let error = CmdError::from(validate(&mut command).unwrap_err());
+        assert_eq!("./script.sh", error.name());
+        assert!(matches!(
+            error,
+            CmdError::SystemError(_, ref io, _) if io.kind() == std::io::ErrorKind::PermissionDenied
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+//! Runs in its own process, [fun_run::terminate_children_on_exit] changes process wide state
+//! that would reach children spawned by other tests
+
+use fun_run::{CmdError, CommandWithName};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PREVIOUS_CALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn previous_handler(_: libc::c_int) {
+    PREVIOUS_CALLED.store(true, Ordering::SeqCst);
+}
+
+#[test]
+fn test_tracked_children_are_terminated_on_signal() {
+    // SAFETY: The handler only stores to an atomic
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            previous_handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    fun_run::terminate_children_on_exit();
+
+    let child = Command::new("sleep")
+        .arg("100")
+        .start(std::io::sink(), std::io::sink())
+        .unwrap();
+    let captured = std::thread::spawn(|| {
+        let error = Command::new("sleep").arg("100").named_output().unwrap_err();
+        error.status().signal()
+    });
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    // SAFETY: `raise` has no memory safety requirements
+    unsafe {
+        libc::raise(libc::SIGHUP);
+    }
+
+    assert!(PREVIOUS_CALLED.load(Ordering::SeqCst));
+    let error = child.wait().unwrap_err();
+    assert!(matches!(error, CmdError::NonZeroExitAlreadyStreamed(_)));
+    assert_eq!(Some(libc::SIGTERM), error.status().signal());
+    assert_eq!(Some(libc::SIGTERM), captured.join().unwrap());
+
+    // Captured output still works while tracking
+    let output = Command::new("echo").arg("hello").named_output().unwrap();
+    assert_eq!("hello\n", output.stdout_lossy());
+
+    // Stdio set on the command is kept
+    let path = std::env::temp_dir().join(format!("fun_run_orphans_{}", std::process::id()));
+    let mut command = Command::new("echo");
+    command
+        .arg("hello")
+        .stdout(std::fs::File::create(&path).unwrap());
+    let output = command.named_output().unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!("", output.stdout_lossy());
+    assert_eq!("hello\n", contents);
+}